@@ -0,0 +1,683 @@
+use clap::Parser;
+use futures::StreamExt;
+use mongo_cloner::{
+    connection::redact,
+    db::Db,
+    endpoint::{EndpointConfig, Namespace},
+    gridfs::{self, FileFilter},
+    job::{CloneJob, CloneOptions, ProgressEvent},
+    plan::ClonePlan,
+    progress::{HumanBytes, HumanDuration, ProgressTracker},
+    secrets,
+    selection::Selection,
+    sharding::Sharding,
+    throttle::Throttle,
+    tunnel::SshTunnel,
+    users,
+};
+use std::{
+    io::BufRead,
+    path::PathBuf,
+    process::ExitCode,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How often overall progress is printed
+const PRINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Clone MongoDB collections from one cluster or local store to another
+#[derive(Debug, Parser)]
+#[command(name = "mongo-cloner-cli", version)]
+struct Args {
+    /// Where to clone from: a `mongodb://` connection string, `bson:<directory>` or
+    /// `json:<directory>`. Read preference and read concern can be set in the connection string.
+    #[arg(short, long, required_unless_present = "plan")]
+    source: Option<EndpointConfig>,
+
+    /// Where to clone to, in the same format as `--source`. Write concern can be set in the
+    /// connection string.
+    #[arg(short, long, required_unless_present = "plan")]
+    target: Option<EndpointConfig>,
+
+    /// A collection to clone, as `<db>.<collection>`, optionally renamed with
+    /// `<db>.<collection>=<new db>.<new collection>`. Everything is cloned if none are given.
+    #[arg(short, long = "collection")]
+    collections: Vec<Mapping>,
+
+    /// Only clone namespaces matching a pattern like `app.*`, or leave out ones matching a
+    /// pattern starting with `!` like `!*.system.*`. `*` matches anything and `?` any one
+    /// character, and the last pattern matching a namespace wins. They go after any in the plan,
+    /// which leaves out `admin`, `local` and `config` by default. If the plan lists its
+    /// collections, only these patterns pick out which of them are cloned.
+    #[arg(
+        long = "select",
+        value_name = "PATTERN",
+        conflicts_with = "collections"
+    )]
+    patterns: Vec<String>,
+
+    /// Only copy the files in a GridFS bucket matching a query, along with their chunks, as
+    /// `<db>.<bucket>=<extended JSON>`, like `app.fs={"metadata.kind":"avatar"}`. The bucket's
+    /// `.files` collection still has to be cloned.
+    #[arg(long = "file-filter", value_name = "FILTER", value_parser = file_filter)]
+    file_filters: Vec<FileFilter>,
+
+    /// Load the source, target, collections and options from a plan file. Any other flags
+    /// override what's in the plan.
+    #[arg(long, value_name = "FILE")]
+    plan: Option<PathBuf>,
+
+    /// Let the plan's usernames and passwords look up environment variables, files and .netrc
+    /// on this machine, which are sent to the servers in the plan
+    #[arg(long, requires = "plan")]
+    trust_plan_secrets: bool,
+
+    /// Save the plan to a file instead of cloning. Passwords are left out of it unless
+    /// `--save-passwords` is given.
+    #[arg(long, value_name = "FILE")]
+    save_plan: Option<PathBuf>,
+
+    /// Keep the source's and target's passwords in the plan saved with `--save-plan`
+    #[arg(long, requires = "save_plan")]
+    save_passwords: bool,
+
+    /// How many parts large collections are split into and copied at the same time [default: 4]
+    #[arg(long)]
+    partitions: Option<usize>,
+
+    /// Collections with at least this many documents are split into parts [default: 1000000]
+    #[arg(long)]
+    partition_threshold: Option<u64>,
+
+    /// How many collections are copied at the same time [default: 4]
+    #[arg(long)]
+    max_collections: Option<usize>,
+
+    /// How many batches are copied at the same time, across every collection [default: 8]
+    #[arg(long)]
+    max_batches: Option<usize>,
+
+    /// How many times to try something that fails with a transient error before giving up
+    /// [default: 5]
+    #[arg(long)]
+    max_attempts: Option<u32>,
+
+    /// Copy every collection as of the same moment, so they're consistent with each other
+    #[arg(long)]
+    snapshot: bool,
+
+    /// Shard target collections on the same key as sharded source collections before loading them
+    #[arg(long)]
+    shard_keys: bool,
+
+    /// Like `--shard-keys`, and also split them into the same chunks and zones so the load is
+    /// spread over every shard
+    #[arg(long)]
+    presplit: bool,
+
+    /// Limit how many documents are read from the source each second
+    #[arg(long, value_parser = rate)]
+    read_docs_per_sec: Option<f64>,
+
+    /// Limit how many megabytes are read from the source each second
+    #[arg(long, value_parser = rate)]
+    read_mb_per_sec: Option<f64>,
+
+    /// Limit how many documents are written to the target each second
+    #[arg(long, value_parser = rate)]
+    write_docs_per_sec: Option<f64>,
+
+    /// Limit how many megabytes are written to the target each second
+    #[arg(long, value_parser = rate)]
+    write_mb_per_sec: Option<f64>,
+
+    /// Also copy the users and custom roles of the databases being cloned. Passwords can't be
+    /// read back, so users not in `--user-passwords` are given generated ones, which are printed
+    /// to stdout as `db,user,password` lines.
+    #[arg(long)]
+    copy_users: bool,
+
+    /// Passwords for copied users, as `db,user,password` lines using the target's database names
+    #[arg(long, value_name = "FILE", requires = "copy_users")]
+    user_passwords: Option<PathBuf>,
+
+    /// Reach the source through an SSH jump host, as `[user@]host[:port]`. Only its first host is
+    /// tunnelled to, and it's connected to directly.
+    #[arg(long, value_name = "HOST", value_parser = tunnel)]
+    source_tunnel: Option<SshTunnel>,
+
+    /// Reach the target through an SSH jump host, like `--source-tunnel`
+    #[arg(long, value_name = "HOST", value_parser = tunnel)]
+    target_tunnel: Option<SshTunnel>,
+
+    /// The private key to log in to jump hosts with, rather than the SSH agent
+    #[arg(long, value_name = "FILE")]
+    tunnel_key: Option<String>,
+}
+
+impl Args {
+    /// Loads the plan if one was given, then puts the rest of the flags on top of it
+    fn plan(self) -> Result<ClonePlan, String> {
+        let mut plan = match &self.plan {
+            Some(path) => ClonePlan::load(path)
+                .map_err(|ex| format!("Error loading plan {}: {ex}", path.display()))?,
+            None => ClonePlan {
+                source: self.source.clone().ok_or("No source given")?,
+                target: self.target.clone().ok_or("No target given")?,
+                collections: vec![],
+                selection: Selection::default(),
+                file_filters: vec![],
+                rename_rules: vec![],
+                options: CloneOptions::default(),
+            },
+        };
+
+        // Whatever a plan's references look up is sent to the servers in it, and plans can come
+        // from anyone, so they're only used if asked to
+        if self.plan.is_some() && !self.trust_plan_secrets {
+            let references = [(&plan.source, &self.source), (&plan.target, &self.target)]
+                .into_iter()
+                .filter(|(_, replaced)| replaced.is_none())
+                .flat_map(|(endpoint, _)| secrets::references(&endpoint.url))
+                .collect::<Vec<_>>();
+            if !references.is_empty() {
+                return Err(format!(
+                    "The plan logs in with {} from this machine, pass --trust-plan-secrets if \
+                     that's what you want",
+                    references.join(", ")
+                ));
+            }
+        }
+
+        if let Some(source) = self.source {
+            plan.source = source;
+        }
+        if let Some(target) = self.target {
+            plan.target = target;
+        }
+        for (endpoint, tunnel) in [
+            (&mut plan.source, self.source_tunnel),
+            (&mut plan.target, self.target_tunnel),
+        ] {
+            if let Some(mut tunnel) = tunnel {
+                tunnel.key_file = self.tunnel_key.clone().unwrap_or_default();
+                endpoint.tunnel = tunnel;
+            }
+        }
+        if !self.collections.is_empty() {
+            plan.collections = self
+                .collections
+                .into_iter()
+                .map(|Mapping { source, target }| (source, target))
+                .collect();
+        } else if !self.patterns.is_empty() {
+            let selection = Selection {
+                patterns: self.patterns.clone(),
+            };
+            plan.collections = selection.filter(plan.collections);
+            // No collections would mean every collection, rather than none
+            if plan.collections.is_empty() {
+                return Err("None of the plan's collections match --select".into());
+            }
+        }
+        plan.selection.patterns.extend(self.patterns);
+        if !self.file_filters.is_empty() {
+            plan.file_filters = self.file_filters;
+        }
+
+        let options = &mut plan.options;
+        if let Some(partitions) = self.partitions {
+            options.partitions = partitions;
+        }
+        if let Some(threshold) = self.partition_threshold {
+            options.partition_threshold = threshold;
+        }
+        if let Some(collections) = self.max_collections {
+            options.max_collections = collections;
+        }
+        if let Some(batches) = self.max_batches {
+            options.max_batches = batches;
+        }
+        if let Some(attempts) = self.max_attempts {
+            options.retry.max_attempts = attempts.max(1);
+        }
+        if self.snapshot {
+            options.snapshot = true;
+        }
+        if self.presplit {
+            options.sharding = Sharding::Split;
+        } else if self.shard_keys {
+            options.sharding = Sharding::Keys;
+        }
+        if let Some(limit) = self.read_docs_per_sec {
+            options.read_limit.documents_per_sec = Some(limit);
+        }
+        if let Some(limit) = self.read_mb_per_sec {
+            options.read_limit.bytes_per_sec = Some(limit * MB);
+        }
+        if let Some(limit) = self.write_docs_per_sec {
+            options.write_limit.documents_per_sec = Some(limit);
+        }
+        if let Some(limit) = self.write_mb_per_sec {
+            options.write_limit.bytes_per_sec = Some(limit * MB);
+        }
+
+        Ok(plan)
+    }
+}
+
+/// The number of bytes in a megabyte, for the rate limit flags
+const MB: f64 = 1_000_000.0;
+
+/// Explains the commands that can be typed in while a clone is running
+const CONTROL_HELP: &str = "Change the rate limits while cloning by typing \
+`<read|write> <docs|mb> <limit|off>`, for example `read mb 20`";
+
+/// A source collection and what it should be called in the target
+#[derive(Debug, Clone)]
+struct Mapping {
+    source: Namespace,
+    target: Namespace,
+}
+
+impl FromStr for Mapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (source, target) = s.split_once('=').unwrap_or((s, s));
+        Ok(Self {
+            source: source.parse()?,
+            target: target.parse()?,
+        })
+    }
+}
+
+/// Parses a `--file-filter` like `app.fs={"metadata.kind":"avatar"}`
+fn file_filter(s: &str) -> Result<FileFilter, String> {
+    let (bucket, filter) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected `<db>.<bucket>=<filter>`, found `{s}`"))?;
+    Ok(FileFilter {
+        bucket: bucket.parse()?,
+        filter: gridfs::parse_filter(filter)?,
+    })
+}
+
+/// Parses a rate limit, which has to be a number more than 0
+fn rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        Ok(_) => Err(format!("The limit has to be more than 0, found `{s}`")),
+        Err(ex) => Err(format!("Invalid limit `{s}`: {ex}")),
+    }
+}
+
+/// Parses a `--source-tunnel` or `--target-tunnel` like `deploy@bastion.example.com:2222`
+fn tunnel(s: &str) -> Result<SshTunnel, String> {
+    let (user, host) = s.rsplit_once('@').unwrap_or(("", s));
+    if host.is_empty() {
+        return Err(format!("Expected `[user@]host[:port]`, found `{s}`"));
+    }
+    Ok(SshTunnel {
+        enabled: true,
+        host: host.to_string(),
+        user: user.to_string(),
+        ..SshTunnel::default()
+    })
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "warn".into()),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    let args = Args::parse();
+    let save_plan = args.save_plan.clone();
+    let save_passwords = args.save_passwords;
+    let copy_users = args.copy_users;
+    let passwords = match &args.user_passwords {
+        Some(path) => match std::fs::read_to_string(path)
+            .map_err(|ex| ex.to_string())
+            .and_then(|text| users::read_passwords(&text))
+        {
+            Ok(passwords) => passwords,
+            Err(ex) => {
+                eprintln!("Error reading passwords from {}: {ex}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Default::default(),
+    };
+    let plan = match args.plan() {
+        Ok(plan) => plan,
+        Err(ex) => {
+            eprintln!("{ex}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Some(path) = save_plan {
+        let saved = if save_passwords {
+            plan.save(&path)
+        } else {
+            plan.without_passwords().save(&path)
+        };
+        return match saved {
+            Ok(()) => {
+                eprintln!("Saved plan to {}", path.display());
+                ExitCode::SUCCESS
+            }
+            Err(ex) => {
+                eprintln!("Error saving plan to {}: {ex}", path.display());
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let source = match plan.source.connect().await {
+        Ok(source) => source,
+        Err(ex) => {
+            eprintln!("Error connecting to source: {}", redact(&ex.to_string()));
+            return ExitCode::FAILURE;
+        }
+    };
+    let target = match plan.target.connect().await {
+        Ok(target) => target,
+        Err(ex) => {
+            eprintln!("Error connecting to target: {}", redact(&ex.to_string()));
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let collections = if plan.collections.is_empty() {
+        match source.list_namespaces().await {
+            Ok(namespaces) => {
+                let mut dbs = Db::from_namespaces(namespaces);
+                Db::select_matching(&mut dbs, &plan.selection);
+                dbs.iter().flat_map(Db::selected_namespaces).collect()
+            }
+            Err(ex) => {
+                eprintln!("Error getting collections: {}", redact(&ex.to_string()));
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        plan.collections
+    };
+
+    let renames = users::renames(&collections);
+    let mut job = CloneJob::builder(source.clone(), target.clone())
+        .collections(collections)
+        .file_filters(plan.file_filters)
+        .options(plan.options)
+        .build();
+
+    // Read rate limit changes from stdin on a plain thread, as a tokio read would hold the
+    // runtime open at the end waiting for a line that never comes
+    let throttle = job.throttle();
+    eprintln!("{CONTROL_HELP}");
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if let Err(ex) = control(&throttle, &line) {
+                eprintln!("{ex}. {CONTROL_HELP}");
+            }
+        }
+    });
+    let mut events = job.subscribe();
+    let printer = tokio::spawn(async move {
+        let mut tracker = ProgressTracker::new();
+        let mut last_print = Instant::now();
+
+        while let Some(event) = events.next().await {
+            tracker.update(&event);
+            match event {
+                ProgressEvent::CollectionStarted { source, target } => {
+                    eprintln!("Copying {source} -> {target}");
+                }
+                ProgressEvent::CollectionProgress { .. }
+                    if last_print.elapsed() >= PRINT_INTERVAL =>
+                {
+                    last_print = Instant::now();
+                    let throughput = tracker.throughput();
+                    eprintln!(
+                        "[{:>3.0}%] {} documents, {}, {:.0} documents/s, {}/s, ETA {}",
+                        tracker.fraction() * 100.0,
+                        tracker.documents(),
+                        HumanBytes(tracker.bytes() as f64),
+                        throughput.documents_per_sec,
+                        HumanBytes(throughput.bytes_per_sec),
+                        tracker
+                            .eta()
+                            .map(|eta| HumanDuration(eta).to_string())
+                            .unwrap_or_else(|| "unknown".into()),
+                    );
+                }
+                ProgressEvent::CollectionRetrying { source, retry, .. } => {
+                    eprintln!(
+                        "Retrying {source} in {:?} after attempt {} failed: {}",
+                        retry.backoff,
+                        retry.attempt,
+                        redact(&retry.error.to_string())
+                    );
+                }
+                ProgressEvent::CatchUpRetrying { retry } => {
+                    eprintln!(
+                        "Retrying catching up in {:?} after attempt {} failed: {}",
+                        retry.backoff,
+                        retry.attempt,
+                        redact(&retry.error.to_string())
+                    );
+                }
+                ProgressEvent::CatchingUp { changes: 0 } => {
+                    eprintln!("Catching up on changes made while copying");
+                }
+                ProgressEvent::CatchingUp { changes } => {
+                    eprintln!("Replayed {changes} changes");
+                }
+                ProgressEvent::CollectionFinished(report) => match report.result {
+                    Ok(()) => eprintln!(
+                        "Copied {} -> {} ({} documents, {} in {})",
+                        report.source,
+                        report.target,
+                        report.documents,
+                        HumanBytes(report.bytes as f64),
+                        HumanDuration(report.elapsed)
+                    ),
+                    Err(ex) => eprintln!(
+                        "Error copying {}: {}",
+                        report.source,
+                        redact(&ex.to_string())
+                    ),
+                },
+                _ => {}
+            }
+        }
+    });
+
+    let report = job.run().await;
+    printer.await.expect("Progress printer panicked");
+
+    eprintln!(
+        "Copied {} documents ({}) from {} collections in {}",
+        report.documents(),
+        HumanBytes(report.bytes() as f64),
+        report.collections.len(),
+        HumanDuration(report.elapsed)
+    );
+    if let Some(snapshot) = &report.snapshot {
+        match &snapshot.result {
+            Ok(()) => eprintln!(
+                "Everything is as of cluster time {}, after replaying {} changes",
+                snapshot.time, snapshot.changes
+            ),
+            Err(ex) => eprintln!("Error catching up: {}", redact(&ex.to_string())),
+        }
+    }
+    if report.retries() > 0 {
+        eprintln!("Tried again after {} transient errors", report.retries());
+    }
+
+    let mut succeeded = report.succeeded();
+    if !succeeded {
+        eprintln!("{} collections failed", report.failures().count());
+    }
+
+    if copy_users {
+        match users::copy(&*source, &*target, &renames, &passwords).await {
+            Ok(report) => {
+                for (name, result) in &report.roles {
+                    if let Err(ex) = result {
+                        eprintln!(
+                            "Error creating role {}.{}: {}",
+                            name.db,
+                            name.role,
+                            redact(&ex.to_string())
+                        );
+                    }
+                }
+                for ((db, user), result) in &report.users {
+                    if let Err(ex) = result {
+                        eprintln!(
+                            "Error creating user {db}.{user}: {}",
+                            redact(&ex.to_string())
+                        );
+                    }
+                }
+                eprintln!(
+                    "Copied {} roles and {} users",
+                    report.roles.len(),
+                    report.users.len()
+                );
+                print!("{}", users::write_passwords(&report.generated));
+                succeeded &= report.succeeded();
+            }
+            Err(ex) => {
+                eprintln!("Error reading users: {}", redact(&ex.to_string()));
+                succeeded = false;
+            }
+        }
+    }
+
+    if succeeded {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Changes a rate limit with a command like `read mb 20` or `write docs off`
+fn control(throttle: &Arc<Throttle>, command: &str) -> Result<(), String> {
+    let words = command.split_whitespace().collect::<Vec<_>>();
+    let [direction, unit, value] = words.as_slice() else {
+        return Err(format!("Unknown command `{command}`"));
+    };
+
+    let value = match *value {
+        "off" => None,
+        // 0 would stop everything rather than lift the limit
+        value => Some(rate(value).map_err(|ex| format!("{ex}, use `off` for no limit"))?),
+    };
+
+    let mut limit = match *direction {
+        "read" => throttle.read_limit(),
+        "write" => throttle.write_limit(),
+        _ => return Err(format!("Expected `read` or `write`, found `{direction}`")),
+    };
+    match *unit {
+        "docs" => limit.documents_per_sec = value,
+        "mb" => limit.bytes_per_sec = value.map(|x| x * MB),
+        _ => return Err(format!("Expected `docs` or `mb`, found `{unit}`")),
+    }
+
+    match *direction {
+        "read" => throttle.set_read_limit(limit),
+        _ => throttle.set_write_limit(limit),
+    }
+    eprintln!("Limited {direction}s to {limit:?}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongo_cloner::throttle::RateLimit;
+
+    #[test]
+    fn only_takes_positive_rates() {
+        assert_eq!(rate("2.5"), Ok(2.5));
+        for s in ["0", "-1", "inf", "NaN"] {
+            assert_eq!(
+                rate(s),
+                Err(format!("The limit has to be more than 0, found `{s}`"))
+            );
+        }
+        assert!(rate("fast").is_err());
+    }
+
+    #[test]
+    fn selects_from_the_plans_collections() {
+        let mut plan = ClonePlan {
+            source: "mongodb://source".parse().unwrap(),
+            target: "mongodb://target".parse().unwrap(),
+            collections: ["app.users", "app.orders", "blog.posts"]
+                .map(|ns| {
+                    let ns = ns.parse::<Namespace>().unwrap();
+                    (ns.clone(), ns)
+                })
+                .to_vec(),
+            selection: Selection::default(),
+            file_filters: vec![],
+            rename_rules: vec![],
+            options: CloneOptions::default(),
+        };
+        let file = tempfile::NamedTempFile::new().unwrap();
+        plan.save(file.path()).unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let args = |extra: &[&str]| {
+            Args::try_parse_from(["mongo-cloner", "--plan", path].iter().chain(extra))
+                .unwrap()
+                .plan()
+        };
+        plan = args(&["--select", "app.*", "--select", "!app.orders"]).unwrap();
+        assert_eq!(plan.collections.len(), 1);
+        assert_eq!(plan.collections[0].0.to_string(), "app.users");
+        assert_eq!(
+            args(&["--select", "shop.*"]).unwrap_err(),
+            "None of the plan's collections match --select"
+        );
+        assert_eq!(args(&[]).unwrap().collections.len(), 3);
+
+        assert!(Args::try_parse_from([
+            "mongo-cloner",
+            "--plan",
+            path,
+            "--collection",
+            "app.users",
+            "--select",
+            "app.*"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn changes_limits_while_cloning() {
+        let throttle = Arc::new(Throttle::new(RateLimit::default(), RateLimit::default()));
+        control(&throttle, "read mb 2").unwrap();
+        assert_eq!(throttle.read_limit().bytes_per_sec, Some(2.0 * MB));
+        assert_eq!(
+            control(&throttle, "read mb 0"),
+            Err("The limit has to be more than 0, found `0`, use `off` for no limit".into())
+        );
+        assert_eq!(throttle.read_limit().bytes_per_sec, Some(2.0 * MB));
+        control(&throttle, "read mb off").unwrap();
+        assert_eq!(throttle.read_limit().bytes_per_sec, None);
+    }
+}
@@ -1,15 +1,56 @@
-use futures::stream::TryStreamExt;
+use crate::{
+    connection::ConnectionString,
+    diagnostics::{self, Access, Diagnosis, Topology},
+    endpoint::{
+        Change, Changes, CollectionStats, DocumentStream, Endpoint, IdRange, Namespace, Snapshot,
+    },
+    gridfs::{self, FileFilter},
+    rename::{Renamer, RuleScope},
+    secrets,
+    selection::Selection,
+    sharding::{self, ShardLayout, ZoneRange},
+    tunnel::{SshTunnel, Tunnel},
+    users::{Privilege, Role, RoleName, User, Users},
+};
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt, TryStreamExt};
 use mongodb::{
-    bson::Document,
-    error::{Error as MongoError, Result as MongoResult},
+    bson::{doc, Bson, Document, Timestamp},
+    change_stream::event::{ChangeStreamEvent, OperationType, ResumeToken},
+    error::{ErrorKind, Result as MongoResult},
+    options::{
+        Acknowledgment, ChangeStreamOptions, ClientOptions, FindOneOptions, FindOptions,
+        InsertManyOptions, ReadConcern, ReadPreference, ReadPreferenceOptions, ReplaceOptions,
+        SelectionCriteria, SessionOptions, WriteConcern,
+    },
     Client,
 };
-use tokio::{
-    runtime::Runtime,
-    sync::{mpsc, oneshot},
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use tracing::{debug, info};
-use url::Url;
+use tracing::{debug, info, warn};
+
+/// How many `_id`s are sampled for each partition when splitting up a collection, more gives
+/// evener partitions
+const SAMPLES_PER_PARTITION: usize = 100;
+
+/// The `maxWireVersion` of MongoDB 5.0, the first that can read as of a past time outside of a
+/// transaction
+const SNAPSHOT_WIRE_VERSION: u64 = 13;
+
+/// How many documents are read from a snapshot at once
+const SNAPSHOT_PAGE_SIZE: i64 = 1000;
+
+/// The server error code for reading a snapshot older than the history the cluster keeps
+const SNAPSHOT_TOO_OLD: i32 = 239;
+
+/// Server error codes for creating a role or user that's already there, which used to be a
+/// duplicate key error
+const ALREADY_EXISTS: [i32; 3] = [11000, 51002, 51003];
 
 /// Used for storing a database's name and what it should be renamed to
 #[derive(Debug, Clone)]
@@ -18,6 +59,28 @@ pub struct DbName {
     pub name: String,
     /// What it should be renamed to
     pub rename: String,
+    /// Whether `rename` was set by hand, so rename rules leave it alone
+    pub edited: bool,
+}
+
+impl DbName {
+    /// What `renamer` renames it to, unless it's been renamed by hand
+    pub fn renamed(&self, renamer: &Renamer) -> String {
+        if self.edited {
+            self.rename.clone()
+        } else {
+            renamer.rename(RuleScope::Databases, &self.name)
+        }
+    }
+}
+
+/// What a listed collection is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectionKind {
+    /// A plain old collection
+    Collection,
+    /// A GridFS bucket, standing in for its `.files` and `.chunks` collections
+    Bucket,
 }
 
 /// Used for storing a collection's name and what it should be renamed to and weather or not it
@@ -30,6 +93,52 @@ pub struct DbCollection {
     pub rename: String,
     /// Weather or not it should be moved
     pub selected: bool,
+    /// Whether it's a collection or a whole GridFS bucket
+    pub kind: CollectionKind,
+    /// For buckets, the files to copy as extended JSON. Blank copies every file.
+    pub file_filter: String,
+    /// The database it goes to when it's not the one the rest of its database goes to, like
+    /// after dragging it onto another one
+    pub rename_db: Option<String>,
+    /// Whether `rename` was set by hand, so rename rules leave it alone
+    pub edited: bool,
+}
+
+impl DbCollection {
+    /// What `renamer` renames it to, unless it's been renamed by hand
+    pub fn renamed(&self, renamer: &Renamer) -> String {
+        if self.edited {
+            self.rename.clone()
+        } else {
+            renamer.rename(RuleScope::Collections, &self.name)
+        }
+    }
+
+    /// The source and target namespaces this stands for in `db`, which is both halves of a bucket
+    fn namespaces(&self, db: &DbName) -> Vec<(Namespace, Namespace)> {
+        let source = Namespace::new(&db.name, &self.name);
+        let target = Namespace::new(self.target_db(db), &self.rename);
+        match self.kind {
+            CollectionKind::Collection => vec![(source, target)],
+            CollectionKind::Bucket => vec![
+                (gridfs::files(&source), gridfs::files(&target)),
+                (gridfs::chunks(&source), gridfs::chunks(&target)),
+            ],
+        }
+    }
+
+    /// The database this goes to in the target, renamed from `db`
+    pub fn target_db<'a>(&'a self, db: &'a DbName) -> &'a str {
+        self.rename_db.as_deref().unwrap_or(&db.rename)
+    }
+
+    /// The namespaces this would be written to in the target, renamed from `db`
+    pub fn targets(&self, db: &DbName) -> Vec<Namespace> {
+        self.namespaces(db)
+            .into_iter()
+            .map(|(_, target)| target)
+            .collect()
+    }
 }
 
 /// A Database and it's collections
@@ -43,212 +152,1338 @@ pub struct Db {
 
 impl From<(String, Vec<String>)> for Db {
     fn from((name, collections): (String, Vec<String>)) -> Self {
+        let (buckets, collections) = gridfs::buckets(collections);
+        let mut collections = collections
+            .into_iter()
+            .map(|x| (x, CollectionKind::Collection))
+            .chain(buckets.into_iter().map(|x| (x, CollectionKind::Bucket)))
+            .map(|(x, kind)| DbCollection {
+                name: x.clone(),
+                rename: x,
+                selected: true,
+                kind,
+                file_filter: String::new(),
+                rename_db: None,
+                edited: false,
+            })
+            .collect::<Vec<_>>();
+        collections.sort_by(|a, b| a.name.cmp(&b.name));
+
         Self {
             db_name: DbName {
                 rename: name.clone(),
                 name,
+                edited: false,
             },
-            collections: collections
-                .into_iter()
-                .map(|x| DbCollection {
-                    name: x.clone(),
-                    rename: x,
-                    selected: true,
-                })
-                .collect(),
+            collections,
         }
     }
 }
 
-// Convenience types
-type DbClientChannel = (Request, oneshot::Sender<Response>);
-type DbClientSender = mpsc::Sender<DbClientChannel>;
+impl Db {
+    /// Groups namespaces up by their database
+    pub fn from_namespaces(namespaces: Vec<Namespace>) -> Vec<Self> {
+        let mut dbs = BTreeMap::<String, Vec<String>>::new();
+        for Namespace { db, collection } in namespaces {
+            dbs.entry(db).or_default().push(collection);
+        }
+        dbs.into_iter().map(Db::from).collect()
+    }
+
+    /// Selects just the collections in `namespaces` and renames them to their targets, like when
+    /// opening a plan. An empty list means every collection, so nothing changes.
+    pub fn select_namespaces(dbs: &mut [Db], namespaces: &[(Namespace, Namespace)]) {
+        if namespaces.is_empty() {
+            return;
+        }
+
+        for db in dbs {
+            // The database goes where its first collection does, and the rest only say where
+            // they go if it's somewhere else
+            let mut db_rename = None;
+            for collection in &mut db.collections {
+                // Buckets go by their files collection
+                let (name, suffix) = match collection.kind {
+                    CollectionKind::Collection => (collection.name.clone(), ""),
+                    CollectionKind::Bucket => (
+                        format!("{}{}", collection.name, gridfs::FILES),
+                        gridfs::FILES,
+                    ),
+                };
+                let target = namespaces
+                    .iter()
+                    .find(|(source, _)| source.db == db.db_name.name && source.collection == name)
+                    .map(|(_, target)| target);
 
-/// All the requests that can be made to a database
-#[derive(Debug)]
-enum Request {
-    /// Get the names of all of the databases and collections in a cluster
-    ListCollections,
-    /// Upload a set of documents to a database with the given name
-    UploadCollection {
-        db: String,
-        collection: String,
-        documents: Vec<Document>,
-    },
-    /// Download a given collection
-    DownloadCollection { db: String, collection: String },
+                collection.selected = target.is_some();
+                if let Some(target) = target {
+                    let db_rename = db_rename.get_or_insert_with(|| target.db.clone());
+                    db.db_name.rename = db_rename.clone();
+                    db.db_name.edited = db.db_name.rename != db.db_name.name;
+                    collection.rename_db = (target.db != *db_rename).then(|| target.db.clone());
+                    collection.rename = target
+                        .collection
+                        .strip_suffix(suffix)
+                        .unwrap_or(&target.collection)
+                        .to_string();
+                    collection.edited = collection.rename != collection.name;
+                }
+            }
+        }
+    }
+
+    /// Selects just the collections `selection` picks, and buckets picked by their own namespace
+    /// or either of their collections'
+    pub fn select_matching(dbs: &mut [Db], selection: &Selection) {
+        for db in dbs {
+            for collection in &mut db.collections {
+                let ns = Namespace::new(&db.db_name.name, &collection.name);
+                collection.selected = match collection.kind {
+                    CollectionKind::Collection => selection.selects(&ns),
+                    CollectionKind::Bucket => {
+                        selection.selects_any(&[gridfs::files(&ns), gridfs::chunks(&ns), ns])
+                    }
+                };
+            }
+        }
+    }
+
+    /// Renames every database and collection that hasn't been renamed by hand with `renamer`
+    pub fn apply_renames(dbs: &mut [Db], renamer: &Renamer) {
+        for db in dbs {
+            db.db_name.rename = db.db_name.renamed(renamer);
+            for collection in &mut db.collections {
+                collection.rename = collection.renamed(renamer);
+            }
+        }
+    }
+
+    /// Fills in the file filters of buckets, like when opening a plan
+    pub fn select_file_filters(dbs: &mut [Db], filters: &[FileFilter]) {
+        for db in dbs {
+            for collection in &mut db.collections {
+                let bucket = Namespace::new(&db.db_name.name, &collection.name);
+                if let Some(FileFilter { filter, .. }) =
+                    filters.iter().find(|filter| filter.bucket == bucket)
+                {
+                    collection.file_filter = gridfs::format_filter(filter);
+                }
+            }
+        }
+    }
+
+    /// The file filters of the selected buckets that have one
+    pub fn file_filters(&self) -> Result<Vec<FileFilter>, String> {
+        self.collections
+            .iter()
+            .filter(|collection| {
+                collection.selected
+                    && collection.kind == CollectionKind::Bucket
+                    && !collection.file_filter.trim().is_empty()
+            })
+            .map(|collection| {
+                let bucket = Namespace::new(&self.db_name.name, &collection.name);
+                let filter = gridfs::parse_filter(&collection.file_filter)
+                    .map_err(|ex| format!("Invalid file filter for {bucket}: {ex}"))?;
+                Ok(FileFilter { bucket, filter })
+            })
+            .collect()
+    }
+
+    /// The source and target namespaces of every selected collection
+    pub fn selected_namespaces(&self) -> Vec<(Namespace, Namespace)> {
+        self.collections
+            .iter()
+            .filter(|collection| collection.selected)
+            .flat_map(|collection| collection.namespaces(&self.db_name))
+            .collect()
+    }
+}
+
+/// Which members of a replica set reads go to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReadMode {
+    /// Whatever the connection string says, which is the primary if it doesn't say
+    #[default]
+    Default,
+    Primary,
+    PrimaryPreferred,
+    Secondary,
+    SecondaryPreferred,
+    Nearest,
+}
+
+/// How sure it has to be that what's read won't be rolled back
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReadLevel {
+    /// Whatever the connection string says, or the server's default
+    #[default]
+    Default,
+    Local,
+    Majority,
+    /// Only documents are read at this level, other commands don't take it
+    Snapshot,
 }
 
-#[derive(Debug)]
-enum Response {
-    /// Response type for if there is an error
-    MongoError(MongoError),
-    /// Successful  result of [`Request::ListCollections`](Request::ListCollections)
-    Collections(Vec<(String, Vec<String>)>),
-    /// Successful  result of [`Request::DownloadCollection`](Request::DownloadCollection)
-    DownloadedDocuments(Vec<Document>),
-    /// Successful result of [`Request::UploadCollection`](Request::UploadCollection)
-    UploadSuccess,
+/// How many members a write has to reach before it counts as done
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WriteAck {
+    /// Whatever the connection string says, or the server's default
+    #[default]
+    Default,
+    /// This many members
+    Nodes(u32),
+    /// Most of the members
+    Majority,
+    /// The members picked out by a custom write concern in the replica set config
+    Tag(String),
+}
+
+/// How a cluster is read from and written to, on top of the connection string. Anything left as
+/// the default goes with what the connection string says.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClusterSettings {
+    /// Which members reads go to
+    pub read_mode: ReadMode,
+    /// Sets of member tags to read from, tried in order until one matches. They're ignored when
+    /// reading from the primary.
+    pub read_tags: Vec<BTreeMap<String, String>>,
+    /// How sure it has to be that what's read won't be rolled back
+    pub read_concern: ReadLevel,
+    /// How many members a write has to reach
+    pub write_ack: WriteAck,
+    /// Whether writes have to reach the journal
+    pub journal: bool,
+    /// How long to wait for a write to reach enough members before failing it
+    pub write_timeout: Option<Duration>,
+}
+
+impl ClusterSettings {
+    /// Overrides the read preference and concerns in `options` with any that have been set. A
+    /// snapshot read concern is left out, as most commands other than reading documents don't
+    /// take it, see [`ClusterSettings::document_read_concern`].
+    fn apply(&self, options: &mut ClientOptions) {
+        let read_options = || {
+            let mut options = ReadPreferenceOptions::default();
+            if !self.read_tags.is_empty() {
+                options.tag_sets = Some(
+                    self.read_tags
+                        .iter()
+                        .map(|tags| tags.clone().into_iter().collect())
+                        .collect(),
+                );
+            }
+            options
+        };
+        let read_preference = match self.read_mode {
+            ReadMode::Default => None,
+            ReadMode::Primary => Some(ReadPreference::Primary),
+            ReadMode::PrimaryPreferred => Some(ReadPreference::PrimaryPreferred {
+                options: read_options(),
+            }),
+            ReadMode::Secondary => Some(ReadPreference::Secondary {
+                options: read_options(),
+            }),
+            ReadMode::SecondaryPreferred => Some(ReadPreference::SecondaryPreferred {
+                options: read_options(),
+            }),
+            ReadMode::Nearest => Some(ReadPreference::Nearest {
+                options: read_options(),
+            }),
+        };
+        if let Some(read_preference) = read_preference {
+            options.selection_criteria = Some(SelectionCriteria::ReadPreference(read_preference));
+        }
+
+        match self.read_concern {
+            ReadLevel::Default => {}
+            ReadLevel::Local => options.read_concern = Some(ReadConcern::local()),
+            ReadLevel::Majority => options.read_concern = Some(ReadConcern::majority()),
+            ReadLevel::Snapshot => {}
+        }
+
+        let mut write_concern = options.write_concern.clone().unwrap_or_default();
+        match &self.write_ack {
+            WriteAck::Default => {}
+            WriteAck::Nodes(nodes) => write_concern.w = Some(Acknowledgment::Nodes(*nodes)),
+            WriteAck::Majority => write_concern.w = Some(Acknowledgment::Majority),
+            WriteAck::Tag(tag) => write_concern.w = Some(Acknowledgment::Custom(tag.clone())),
+        }
+        if self.journal {
+            write_concern.journal = Some(true);
+        }
+        if self.write_timeout.is_some() {
+            write_concern.w_timeout = self.write_timeout;
+        }
+        if write_concern != WriteConcern::default() {
+            options.write_concern = Some(write_concern);
+        }
+    }
+
+    /// The read concern documents are read from collections with, on top of the client's
+    fn document_read_concern(&self) -> Option<ReadConcern> {
+        (self.read_concern == ReadLevel::Snapshot).then(ReadConcern::snapshot)
+    }
 }
 
 /// A connection to a cluster
+#[derive(Clone)]
 pub struct ClusterClient {
-    /// Channel for sending requests to the loop
-    tx: DbClientSender,
+    /// The driver's client, which handles pooling connections to the cluster
+    client: Client,
+    /// The read concern documents are read with, if it's not the client's
+    document_read_concern: Option<ReadConcern>,
+    /// The SSH tunnel the cluster is reached through, closed once every clone is dropped
+    _tunnel: Option<Arc<Tunnel>>,
 }
 
 impl ClusterClient {
-    /// Creates a new connection and spawns a request handler loop
-    pub fn new(url: &Url, rt: &Runtime) -> MongoResult<Self> {
-        info!("Connecting to {url}");
-        let client = rt.block_on(Client::with_uri_str(&format!("{url}")))?;
-        let (tx, mut rx) = mpsc::channel::<DbClientChannel>(1024);
-
-        rt.spawn(async move {
-            let thread = tokio::runtime::Handle::current();
-
-            'command_loop: while let Some((request, sender)) = rx.recv().await {
-                match request {
-                    Request::ListCollections => {
-                        match client.list_database_names(None, None).await {
-                            Ok(databases) => {
-                                let mut response = vec![];
-                                for database in databases {
-                                    match client
-                                        .database(&database)
-                                        .list_collection_names(None)
-                                        .await
-                                    {
-                                        Ok(collections) => {
-                                            response.push((database, collections));
-                                        }
-                                        Err(ex) => {
-                                            sender.send(Response::MongoError(ex)).expect("Sender for `ClusterClient::get_collections` dropped");
-                                            continue 'command_loop;
-                                        }
-                                    };
-                                }
-                                sender
-                                    .send(Response::Collections(response))
-                                    .expect("Sender for `ClusterClient::get_collections` dropped");
-                            }
-                            Err(ex) => {
-                                sender.send(Response::MongoError(ex)).expect("Sender for `ClusterClient::get_collections` dropped");
-                            }
-                        };
-                    }
-                    Request::UploadCollection {
-                        collection,
-                        db,
-                        documents,
-                    } => {
-                        let client = client.clone();
-                        thread.spawn(async move {
-                            debug!("Uploading collection {db}.{collection}");
-                            match client
-                                .database(&db)
-                                .collection(&collection)
-                                .insert_many(documents, None)
-                                .await
-                            {
-                                Ok(_) => sender.send(Response::UploadSuccess),
-                                Err(ex) => sender.send(Response::MongoError(ex)),
-                            }
-                                .expect("Sender for `ClusterClient::upload_collection` dropped");
-                            debug!("Uploaded collection {db}.{collection}");
-                        });
-                    }
-                    Request::DownloadCollection { collection, db } => {
-                        debug!("Getting collection {db}.{collection}");
-                        let client = client.clone();
-                        thread.spawn(async move {
-                            match client
-                                .database(&db)
-                                .collection::<Document>(&collection)
-                                .find(None, None)
-                                .await
-                            {
-                                Ok(collection) => match collection.try_collect().await {
-                                    Err(ex) => sender.send(Response::MongoError(ex)),
-                                    Ok(val) => sender.send(Response::DownloadedDocuments(val)),
-                                },
-                                Err(ex) => sender.send(Response::MongoError(ex)),
-                            }
-                                .expect("Sender for `ClusterClient::download_collection` dropped");
-                        });
+    /// Connects to the cluster at `url`, reading and writing as `settings` says, through `tunnel`
+    /// if it's enabled. Any [secrets] in `url` are looked up first, once it's checked for
+    /// [problems](ConnectionString::problems).
+    pub async fn connect(
+        url: &ConnectionString,
+        settings: &ClusterSettings,
+        tunnel: &SshTunnel,
+    ) -> MongoResult<Self> {
+        if let Some(problem) = url.problems().into_iter().next() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, problem).into());
+        }
+        for warning in url.warnings() {
+            warn!("{warning}");
+        }
+        let url = secrets::resolve(url)?;
+        let (tunnel, url) = if tunnel.enabled {
+            let (tunnel, url) = tunnel.open(&url).await?;
+            (Some(Arc::new(tunnel)), url)
+        } else {
+            (None, url)
+        };
+
+        info!("Connecting to {}", url.redacted());
+        let mut options = ClientOptions::parse(url.to_string()).await?;
+        settings.apply(&mut options);
+        let client = Client::with_options(options)?;
+        Ok(Self {
+            client,
+            document_read_concern: settings.document_read_concern(),
+            _tunnel: tunnel,
+        })
+    }
+
+    /// How to find the documents in `range`. Anything short of the whole collection is sorted by
+    /// `_id`, so reading can carry on from the last `_id` if it fails.
+    fn find_options(&self, range: &IdRange) -> FindOptions {
+        FindOptions::builder()
+            .sort((!range.is_all()).then(|| doc! { "_id": 1 }))
+            .read_concern(self.document_read_concern.clone())
+            .build()
+    }
+
+    /// The cluster's current time, which only replica sets and sharded clusters have
+    async fn cluster_time(&self) -> MongoResult<Timestamp> {
+        let response = self
+            .client
+            .database("admin")
+            .run_command(doc! { "ping": 1 }, None)
+            .await?;
+        response
+            .get_timestamp("operationTime")
+            .or_else(|_| {
+                response
+                    .get_document("$clusterTime")
+                    .and_then(|time| time.get_timestamp("clusterTime"))
+            })
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Only replica sets and sharded clusters keep track of time",
+                )
+                .into()
+            })
+    }
+}
+
+#[async_trait]
+impl Endpoint for ClusterClient {
+    async fn list_namespaces(&self) -> MongoResult<Vec<Namespace>> {
+        let mut namespaces = vec![];
+        for db in self.client.list_database_names(None, None).await? {
+            for collection in self
+                .client
+                .database(&db)
+                .list_collection_names(None)
+                .await?
+            {
+                namespaces.push(Namespace::new(&db, collection));
+            }
+        }
+        Ok(namespaces)
+    }
+
+    async fn collection_stats(&self, ns: &Namespace) -> MongoResult<CollectionStats> {
+        let db = self.client.database(&ns.db);
+        match db
+            .run_command(doc! { "collStats": &ns.collection }, None)
+            .await
+        {
+            Ok(stats) => Ok(CollectionStats {
+                documents: number(&stats, "count"),
+                bytes: number(&stats, "size"),
+            }),
+            // Views don't have stats, but they can still be counted
+            Err(ex) => {
+                debug!("Couldn't get stats for {ns}, counting it instead: {ex}");
+                Ok(CollectionStats {
+                    documents: db
+                        .collection::<Document>(&ns.collection)
+                        .estimated_document_count(None)
+                        .await?,
+                    bytes: 0,
+                })
+            }
+        }
+    }
+
+    async fn read_collection(&self, ns: &Namespace) -> MongoResult<DocumentStream> {
+        debug!("Getting collection {ns}");
+        let cursor = self
+            .client
+            .database(&ns.db)
+            .collection::<Document>(&ns.collection)
+            .find(None, self.find_options(&IdRange::all()))
+            .await?;
+        Ok(cursor.boxed())
+    }
+
+    async fn partition(&self, ns: &Namespace, count: usize) -> MongoResult<Vec<IdRange>> {
+        let collection = self
+            .client
+            .database(&ns.db)
+            .collection::<Document>(&ns.collection);
+
+        // Queries only compare values of the same type, so ranges would miss documents if the
+        // `_id`s are a mix of types. Types sort together, so the ends show if there's a mix.
+        let end = |direction: i32| {
+            collection.find_one(
+                None,
+                FindOneOptions::builder()
+                    .sort(doc! { "_id": direction })
+                    .projection(doc! { "_id": 1 })
+                    .build(),
+            )
+        };
+        let (first, last) = match (end(1).await?, end(-1).await?) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Ok(vec![IdRange::all()]),
+        };
+        if !same_type(first.get("_id"), last.get("_id")) {
+            debug!("{ns} has a mix of _id types, copying it in one go");
+            return Ok(vec![IdRange::all()]);
+        }
+        if count < 2 {
+            return Ok(vec![IdRange::ordered()]);
+        }
+
+        // Split at evenly spaced `_id`s out of a sorted random sample
+        let mut ids = collection
+            .aggregate(
+                [
+                    doc! { "$sample": { "size": (count * SAMPLES_PER_PARTITION) as i64 } },
+                    doc! { "$project": { "_id": 1 } },
+                    doc! { "$sort": { "_id": 1 } },
+                ],
+                None,
+            )
+            .await?
+            .try_filter_map(|mut document| async move { Ok(document.remove("_id")) })
+            .try_collect::<Vec<_>>()
+            .await?;
+        ids.dedup();
+
+        let mut boundaries = (1..count)
+            .filter_map(|i| ids.get(i * ids.len() / count).cloned())
+            .collect::<Vec<_>>();
+        boundaries.dedup();
+
+        debug!("Split {ns} into {} partitions", boundaries.len() + 1);
+        Ok(IdRange::split(boundaries))
+    }
+
+    async fn read_range(&self, ns: &Namespace, range: &IdRange) -> MongoResult<DocumentStream> {
+        debug!("Getting {range} of collection {ns}");
+        let cursor = self
+            .client
+            .database(&ns.db)
+            .collection::<Document>(&ns.collection)
+            .find(range.filter(), self.find_options(range))
+            .await?;
+        Ok(cursor.boxed())
+    }
+
+    async fn read_matching(
+        &self,
+        ns: &Namespace,
+        filter: &Document,
+    ) -> MongoResult<DocumentStream> {
+        debug!("Getting documents of {ns} matching {filter}");
+        let cursor = self
+            .client
+            .database(&ns.db)
+            .collection::<Document>(&ns.collection)
+            .find(filter.clone(), self.find_options(&IdRange::all()))
+            .await?;
+        Ok(cursor.boxed())
+    }
+
+    async fn snapshot(&self, namespaces: &[Namespace]) -> MongoResult<Snapshot> {
+        let hello = self
+            .client
+            .database("admin")
+            .run_command(doc! { "isMaster": 1 }, None)
+            .await?;
+        if !hello.contains_key("setName") && hello.get_str("msg") != Ok("isdbgrid") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Only replica sets and sharded clusters can be copied as of a single moment",
+            )
+            .into());
+        }
+
+        if number(&hello, "maxWireVersion") < SNAPSHOT_WIRE_VERSION {
+            debug!("Server can't read as of a past time, so changes will be caught up instead");
+            return Ok(Snapshot::CatchUp {
+                time: self.cluster_time().await?,
+            });
+        }
+
+        // Every read in a snapshot session is as of the time the first one happened
+        let mut session = self
+            .client
+            .start_session(Some(SessionOptions::builder().snapshot(true).build()))
+            .await?;
+        if let Some(ns) = namespaces.first() {
+            self.client
+                .database(&ns.db)
+                .collection::<Document>(&ns.collection)
+                .find_one_with_session(None, None, &mut session)
+                .await?;
+        }
+        let time = session
+            .operation_time()
+            .ok_or_else(|| io::Error::other("The cluster didn't say when the snapshot is"))?;
+
+        info!("Copying as of cluster time {time}");
+        Ok(Snapshot::Frozen {
+            endpoint: Arc::new(ClusterSnapshot {
+                cluster: self.clone(),
+                time,
+            }),
+            time,
+        })
+    }
+
+    async fn changes(&self, namespaces: &[Namespace], since: Timestamp) -> MongoResult<Changes> {
+        let until = self.cluster_time().await?;
+        if namespaces.is_empty() {
+            return Ok(Changes {
+                until,
+                stream: stream::empty().boxed(),
+            });
+        }
+
+        debug!("Getting changes from {since} until {until}");
+        let filter = namespaces
+            .iter()
+            .map(|ns| doc! { "ns.db": &ns.db, "ns.coll": &ns.collection })
+            .collect::<Vec<_>>();
+        let changes = self
+            .client
+            .watch(
+                [doc! { "$match": { "$or": filter } }],
+                ChangeStreamOptions::builder()
+                    .start_at_operation_time(Some(since))
+                    .build(),
+            )
+            .await?;
+
+        let stream = stream::unfold(Some(changes), move |changes| async move {
+            let mut changes = changes?;
+            loop {
+                match changes.next_if_any().await {
+                    // Nothing was ready yet, which only means it's caught up once the resume
+                    // token, which moves along with the cluster's time, is at `until`
+                    Ok(None) => match changes.resume_token().map(|token| resume_token_time(&token)) {
+                        Some(Some(time)) if time >= until => return None,
+                        Some(Some(_)) if changes.is_alive() => continue,
+                        Some(Some(_)) => {
+                            let ex = io::Error::other("The change stream closed before catching up");
+                            return Some((Err(ex.into()), None));
+                        }
+                        _ => {
+                            let ex = io::Error::other(
+                                "Couldn't tell how far the change stream has got from its resume token",
+                            );
+                            return Some((Err(ex.into()), None));
+                        }
+                    },
+                    Ok(Some(event)) if event.cluster_time.is_some_and(|time| time > until) => {
+                        return None
                     }
+                    Ok(Some(event)) => match change(event) {
+                        Ok(Some(change)) => return Some((Ok(change), Some(changes))),
+                        Ok(None) => continue,
+                        Err(ex) => return Some((Err(ex), None)),
+                    },
+                    Err(ex) => return Some((Err(ex), None)),
                 }
             }
-            info!("Thing yeet'd");
-        });
+        })
+        .boxed();
+
+        Ok(Changes { until, stream })
+    }
 
-        Ok(Self { tx })
+    fn applies_changes(&self) -> bool {
+        true
     }
 
-    /// Gets all the databases and collections in the cluster
-    pub async fn get_collections(&self) -> MongoResult<Vec<(String, Vec<String>)>> {
-        let (tx, rx) = oneshot::channel();
-        self.tx.send((Request::ListCollections, tx)).await.unwrap();
+    async fn apply_change(&self, ns: &Namespace, change: Change) -> MongoResult<()> {
+        let collection = self
+            .client
+            .database(&ns.db)
+            .collection::<Document>(&ns.collection);
+        match change {
+            Change::Upsert(document) => {
+                let id = document.get("_id").cloned().unwrap_or(Bson::Null);
+                collection
+                    .replace_one(
+                        doc! { "_id": id },
+                        document,
+                        ReplaceOptions::builder().upsert(true).build(),
+                    )
+                    .await?;
+            }
+            Change::Update { id, set, unset } => {
+                let mut update = Document::new();
+                if !set.is_empty() {
+                    update.insert("$set", set);
+                }
+                if !unset.is_empty() {
+                    update.insert(
+                        "$unset",
+                        unset
+                            .into_iter()
+                            .map(|field| (field, Bson::from("")))
+                            .collect::<Document>(),
+                    );
+                }
+                if !update.is_empty() {
+                    collection
+                        .update_one(doc! { "_id": id }, update, None)
+                        .await?;
+                }
+            }
+            Change::Delete(id) => {
+                collection.delete_one(doc! { "_id": id }, None).await?;
+            }
+        }
+        Ok(())
+    }
 
-        match rx
+    async fn shard_layout(&self, ns: &Namespace, splits: bool) -> MongoResult<Option<ShardLayout>> {
+        let config = self.client.database("config");
+        let entry = config
+            .collection::<ShardedCollection>("collections")
+            .find_one(doc! { "_id": ns.to_string() }, None)
+            .await?;
+        let Some(entry) = entry.filter(|entry| !entry.dropped) else {
+            return Ok(None);
+        };
+
+        let mut layout = ShardLayout {
+            key: entry.key,
+            unique: entry.unique,
+            split_points: vec![],
+            zones: vec![],
+        };
+        if splits {
+            let mut owners = vec![doc! { "ns": ns.to_string() }];
+            if let Some(uuid) = entry.uuid {
+                owners.push(doc! { "uuid": uuid });
+            }
+            // The first chunk starts at the lowest possible key, so it isn't a split
+            let points = config
+                .collection::<Chunk>("chunks")
+                .find(
+                    doc! { "$or": owners },
+                    FindOptions::builder()
+                        .sort(doc! { "min": 1 })
+                        .projection(doc! { "min": 1 })
+                        .build(),
+                )
+                .await?
+                .map_ok(|chunk| chunk.min)
+                .try_collect::<Vec<_>>()
+                .await?;
+            layout.split_points = sharding::thin_splits(points.into_iter().skip(1).collect());
+
+            layout.zones = config
+                .collection::<ZoneRange>("tags")
+                .find(doc! { "ns": ns.to_string() }, None)
+                .await?
+                .try_collect()
+                .await?;
+        }
+        Ok(Some(layout))
+    }
+
+    async fn shard(&self, ns: &Namespace, layout: &ShardLayout) -> MongoResult<()> {
+        info!("Sharding {ns} on {}", layout.key);
+        let admin = self.client.database("admin");
+        // Before 6.0 databases have to have sharding turned on first, after it does nothing
+        if let Err(ex) = admin
+            .run_command(doc! { "enableSharding": &ns.db }, None)
             .await
-            .expect("Sender for `ClusterClient::get_collections` dropped")
         {
-            Response::MongoError(err) => Err(err),
-            Response::Collections(ok) => Ok(ok),
-            _ => unreachable!(),
+            debug!("Couldn't enable sharding on {}: {ex}", ns.db);
+        }
+
+        for command in layout.commands(ns) {
+            admin.run_command(command, None).await?;
+        }
+        if layout.is_hashed() || layout.split_points.is_empty() {
+            return Ok(());
+        }
+
+        // Hand the empty chunks out to every shard now rather than leaving it to the balancer,
+        // which wouldn't get to them until after the load. It still will if any of these fail.
+        let shards = admin.run_command(doc! { "listShards": 1 }, None).await?;
+        let shards = shards
+            .get_array("shards")
+            .map(|shards| {
+                shards
+                    .iter()
+                    .filter_map(|shard| shard.as_document()?.get_str("_id").ok())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if shards.len() < 2 {
+            return Ok(());
+        }
+        for (i, point) in layout.split_points.iter().enumerate() {
+            let shard = &shards[(i + 1) % shards.len()];
+            if let Err(ex) = admin
+                .run_command(
+                    doc! { "moveChunk": ns.to_string(), "find": point.clone(), "to": shard },
+                    None,
+                )
+                .await
+            {
+                debug!("Couldn't move the chunk of {ns} at {point} to {shard}: {ex}");
+            }
+        }
+        Ok(())
+    }
+
+    async fn diagnose(&self, access: Access) -> MongoResult<Diagnosis> {
+        let admin = self.client.database("admin");
+        let started = Instant::now();
+        admin.run_command(doc! { "ping": 1 }, None).await?;
+        let latency = started.elapsed();
+
+        let build = admin.run_command(doc! { "buildInfo": 1 }, None).await?;
+        let hello = admin.run_command(doc! { "isMaster": 1 }, None).await?;
+        let topology = if hello.get_str("msg") == Ok("isdbgrid") {
+            Topology::Sharded
+        } else if let Ok(name) = hello.get_str("setName") {
+            Topology::ReplicaSet(name.to_string())
+        } else {
+            Topology::Standalone
+        };
+
+        let status = admin
+            .run_command(doc! { "connectionStatus": 1, "showPrivileges": true }, None)
+            .await?;
+        let auth = mongodb::bson::from_document::<ConnectionStatus>(status)?.auth_info;
+
+        // Nobody's logged in, which is fine if the server doesn't need a login
+        let missing = if auth.authenticated_users.is_empty() {
+            match self.client.list_database_names(None, None).await {
+                Ok(_) => vec![],
+                Err(ex) => vec![format!("A login, as the server said {ex}")],
+            }
+        } else {
+            // Servers that can read as of a past time don't need the changes to catch up
+            let access = match access {
+                Access::Read {
+                    namespaces,
+                    changes,
+                } => Access::Read {
+                    namespaces,
+                    changes: changes && number(&hello, "maxWireVersion") < SNAPSHOT_WIRE_VERSION,
+                },
+                access => access,
+            };
+            diagnostics::missing_privileges(&auth.authenticated_user_privileges, &access)
+        };
+
+        Ok(Diagnosis {
+            latency,
+            version: build.get_str("version").unwrap_or("unknown").to_string(),
+            topology,
+            users: auth
+                .authenticated_users
+                .iter()
+                .map(|user| format!("{}.{}", user.db, user.user))
+                .collect(),
+            roles: auth.authenticated_user_roles,
+            missing,
+        })
+    }
+
+    async fn users(&self, dbs: &[String]) -> MongoResult<Users> {
+        let mut users = Users::default();
+        for name in dbs {
+            let db = self.client.database(name);
+            let roles = db
+                .run_command(doc! { "rolesInfo": 1, "showPrivileges": true }, None)
+                .await?;
+            users
+                .roles
+                .extend(mongodb::bson::from_document::<RolesInfo>(roles)?.roles);
+
+            let found = db.run_command(doc! { "usersInfo": 1 }, None).await?;
+            users
+                .users
+                .extend(mongodb::bson::from_document::<UsersInfo>(found)?.users);
+        }
+        Ok(users)
+    }
+
+    async fn put_role(&self, role: &Role) -> MongoResult<()> {
+        debug!("Creating role {}@{}", role.role, role.db);
+        let db = self.client.database(&role.db);
+        let privileges = mongodb::bson::to_bson(&role.privileges)?;
+        let roles = mongodb::bson::to_bson(&role.roles)?;
+
+        let create = doc! {
+            "createRole": &role.role,
+            "privileges": privileges.clone(),
+            "roles": roles.clone(),
+        };
+        match db.run_command(create, None).await {
+            Err(ex) if already_exists(&ex) => {
+                let update = doc! {
+                    "updateRole": &role.role,
+                    "privileges": privileges,
+                    "roles": roles,
+                };
+                db.run_command(update, None).await?;
+            }
+            result => {
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn put_user(&self, user: &User, password: &str) -> MongoResult<()> {
+        debug!("Creating user {}@{}", user.user, user.db);
+        let db = self.client.database(&user.db);
+        let mut fields = doc! {
+            "pwd": password,
+            "roles": mongodb::bson::to_bson(&user.roles)?,
+        };
+        if let Some(custom_data) = &user.custom_data {
+            fields.insert("customData", custom_data.clone());
+        }
+        if !user.mechanisms.is_empty() {
+            fields.insert("mechanisms", user.mechanisms.clone());
+        }
+
+        let mut create = doc! { "createUser": &user.user };
+        create.extend(fields.clone());
+        match db.run_command(create, None).await {
+            Err(ex) if already_exists(&ex) => {
+                let mut update = doc! { "updateUser": &user.user };
+                update.extend(fields);
+                db.run_command(update, None).await?;
+            }
+            result => {
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_batch(&self, ns: &Namespace, documents: Vec<Document>) -> MongoResult<()> {
+        // The driver refuses to insert nothing, but an empty batch is still a success
+        if documents.is_empty() {
+            return Ok(());
         }
+
+        // Unordered, so if a batch is tried again after partly going in, everything that didn't
+        // make it the first time still does
+        debug!("Uploading {} documents to {ns}", documents.len());
+        self.client
+            .database(&ns.db)
+            .collection(&ns.collection)
+            .insert_many(
+                documents,
+                InsertManyOptions::builder().ordered(false).build(),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+/// A collection's entry in `config.collections`
+#[derive(Deserialize)]
+struct ShardedCollection {
+    key: Document,
+    #[serde(default)]
+    unique: bool,
+    /// How chunks refer to the collection since 5.0, before that they use the namespace
+    uuid: Option<Bson>,
+    #[serde(default)]
+    dropped: bool,
+}
+
+/// A chunk's entry in `config.chunks`
+#[derive(Deserialize)]
+struct Chunk {
+    min: Document,
+}
+
+/// The response to `rolesInfo`
+#[derive(Deserialize)]
+struct RolesInfo {
+    roles: Vec<Role>,
+}
+
+/// The response to `usersInfo`
+#[derive(Deserialize)]
+struct UsersInfo {
+    users: Vec<User>,
+}
+
+/// The response to `connectionStatus`
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConnectionStatus {
+    auth_info: AuthInfo,
+}
+
+/// Who a connection is logged in as and what they can do
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthInfo {
+    authenticated_users: Vec<UserName>,
+    #[serde(default)]
+    authenticated_user_roles: Vec<RoleName>,
+    #[serde(default)]
+    authenticated_user_privileges: Vec<Privilege>,
+}
+
+/// A user and the database they're defined in
+#[derive(Deserialize)]
+struct UserName {
+    user: String,
+    db: String,
+}
+
+/// Whether a create command failed because what it was creating is already there
+fn already_exists(ex: &mongodb::error::Error) -> bool {
+    matches!(ex.kind.as_ref(), ErrorKind::Command(ex) if ALREADY_EXISTS.contains(&ex.code))
+}
+
+/// A cluster frozen at a moment in time, see [`Snapshot::Frozen`]
+#[derive(Clone)]
+struct ClusterSnapshot {
+    cluster: ClusterClient,
+    /// The cluster time every read is as of
+    time: Timestamp,
+}
+
+impl ClusterSnapshot {
+    /// Reads the documents matching `filter` as of the snapshot, in `_id` order. Each page is its
+    /// own `find` at the snapshot's time rather than a cursor in a shared session, so any number
+    /// of them can be read at once, and it doesn't matter which mongos answers each one.
+    fn find(&self, ns: &Namespace, filter: Document) -> DocumentStream {
+        let snapshot = self.clone();
+        let ns = ns.clone();
+        stream::try_unfold(
+            (VecDeque::new(), None),
+            move |(mut page, after): (VecDeque<Document>, Option<Bson>)| {
+                let snapshot = snapshot.clone();
+                let ns = ns.clone();
+                let filter = filter.clone();
+                async move {
+                    if page.is_empty() {
+                        page = snapshot.page(&ns, filter, after.as_ref()).await?.into();
+                    }
+                    Ok(page.pop_front().map(|document| {
+                        let after = document.get("_id").cloned();
+                        (document, (page, after))
+                    }))
+                }
+            },
+        )
+        .boxed()
     }
 
-    /// Download a collection from cluster
-    pub async fn download_collection(
+    /// The next page of documents matching `filter` after the `_id` `after`
+    async fn page(
         &self,
-        db: String,
-        collection: String,
+        ns: &Namespace,
+        filter: Document,
+        after: Option<&Bson>,
     ) -> MongoResult<Vec<Document>> {
-        let (tx, rx) = oneshot::channel();
-        self.tx
-            .send((Request::DownloadCollection { collection, db }, tx))
-            .await
-            .unwrap();
+        let mut find = doc! {
+            "find": &ns.collection,
+            "filter": filter,
+            "sort": { "_id": 1 },
+            "hint": { "_id": 1 },
+            "limit": SNAPSHOT_PAGE_SIZE,
+            "singleBatch": true,
+            "readConcern": { "level": "snapshot", "atClusterTime": self.time },
+        };
+        // `min` goes by the index, which orders `_id`s of every type, where `$gt` would only
+        // find ones of the same type. It includes `after` itself, so that's skipped.
+        if let Some(after) = after {
+            find.insert("min", doc! { "_id": after.clone() });
+            find.insert("skip", 1);
+        }
 
-        match rx
+        let db = self.cluster.client.database(&ns.db);
+        let response = db
+            .run_command(find, db.selection_criteria().cloned())
             .await
-            .expect("Sender for `ClusterClient::get_collections` dropped")
-        {
-            Response::MongoError(err) => Err(err),
-            Response::DownloadedDocuments(ok) => Ok(ok),
-            _ => unreachable!(),
-        }
+            .map_err(|ex| match ex.kind.as_ref() {
+                ErrorKind::Command(command) if command.code == SNAPSHOT_TOO_OLD => {
+                    io::Error::other(format!(
+                        "The snapshot at cluster time {} is older than the history the cluster \
+                         keeps, which is 5 minutes unless `minSnapshotHistoryWindowInSeconds` is \
+                         raised. Raise it, or clone without a snapshot.",
+                        self.time
+                    ))
+                    .into()
+                }
+                _ => ex,
+            })?;
+
+        let batch = response
+            .get_document("cursor")
+            .and_then(|cursor| cursor.get_array("firstBatch"))
+            .map_err(|ex| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unexpected reply to find: {ex}"),
+                )
+            })?;
+        Ok(batch
+            .iter()
+            .filter_map(|document| document.as_document().cloned())
+            .collect())
+    }
+}
+
+#[async_trait]
+impl Endpoint for ClusterSnapshot {
+    async fn list_namespaces(&self) -> MongoResult<Vec<Namespace>> {
+        self.cluster.list_namespaces().await
+    }
+
+    async fn collection_stats(&self, ns: &Namespace) -> MongoResult<CollectionStats> {
+        self.cluster.collection_stats(ns).await
+    }
+
+    // Sharding metadata lives on the config servers, outside of the snapshot
+    async fn shard_layout(&self, ns: &Namespace, splits: bool) -> MongoResult<Option<ShardLayout>> {
+        self.cluster.shard_layout(ns, splits).await
+    }
+
+    async fn read_collection(&self, ns: &Namespace) -> MongoResult<DocumentStream> {
+        self.read_range(ns, &IdRange::all()).await
+    }
+
+    // Where collections are split doesn't need to be from the snapshot, any `_id`s will do
+    async fn partition(&self, ns: &Namespace, count: usize) -> MongoResult<Vec<IdRange>> {
+        self.cluster.partition(ns, count).await
     }
 
-    /// Upload a collection to cluster
-    pub async fn upload_collection(
+    async fn read_range(&self, ns: &Namespace, range: &IdRange) -> MongoResult<DocumentStream> {
+        debug!("Getting {range} of collection {ns} from the snapshot");
+        Ok(self.find(ns, range.filter()))
+    }
+
+    async fn read_matching(
         &self,
-        db: String,
-        collection: String,
-        documents: Vec<Document>,
-    ) -> MongoResult<()> {
-        let (tx, rx) = oneshot::channel();
-        self.tx
-            .send((
-                Request::UploadCollection {
-                    collection,
-                    db,
-                    documents,
-                },
-                tx,
+        ns: &Namespace,
+        filter: &Document,
+    ) -> MongoResult<DocumentStream> {
+        debug!("Getting documents of {ns} matching {filter} from the snapshot");
+        Ok(self.find(ns, filter.clone()))
+    }
+
+    async fn write_batch(&self, ns: &Namespace, _documents: Vec<Document>) -> MongoResult<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Can't write to {ns} in a snapshot"),
+        )
+        .into())
+    }
+}
+
+/// The cluster time a resume token is at. Tokens are hex, starting with a timestamp's type byte
+/// then its seconds and increment.
+fn resume_token_time(token: &ResumeToken) -> Option<Timestamp> {
+    let token = mongodb::bson::to_document(token).ok()?;
+    let data = token.get_str("_data").ok()?;
+    if !data.starts_with("82") {
+        return None;
+    }
+    Some(Timestamp {
+        time: u32::from_str_radix(data.get(2..10)?, 16).ok()?,
+        increment: u32::from_str_radix(data.get(10..18)?, 16).ok()?,
+    })
+}
+
+/// Turns a change stream event into a change to replay, skipping anything that isn't one
+fn change(
+    event: ChangeStreamEvent<Document>,
+) -> MongoResult<Option<(Timestamp, Namespace, Change)>> {
+    let ns = match event.ns.as_ref() {
+        Some(ns) => match &ns.coll {
+            Some(collection) => Namespace::new(&ns.db, collection),
+            None => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+    let time = event
+        .cluster_time
+        .or_else(|| resume_token_time(&event.id))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Couldn't tell when a change to {ns} was made"),
+            )
+        })?;
+    let id = event.document_key.and_then(|mut key| key.remove("_id"));
+
+    let change = match (event.operation_type, id) {
+        (OperationType::Insert | OperationType::Replace, _) => {
+            event.full_document.map(Change::Upsert)
+        }
+        (OperationType::Update, Some(id)) => {
+            event.update_description.map(|update| Change::Update {
+                id,
+                set: update.updated_fields,
+                unset: update.removed_fields,
+            })
+        }
+        (OperationType::Delete, Some(id)) => Some(Change::Delete(id)),
+        (operation, _) => {
+            return Err(io::Error::other(format!(
+                "{ns} had a {operation:?} while it was being copied, so it can't be caught up"
             ))
-            .await
-            .unwrap();
+            .into())
+        }
+    };
 
-        match rx
-            .await
-            .expect("Sender for `ClusterClient::get_collections` dropped")
-        {
-            Response::MongoError(err) => Err(err),
-            Response::UploadSuccess => Ok(()),
-            _ => unreachable!(),
+    match change {
+        Some(change) => Ok(Some((time, ns, change))),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("A change to {ns} was missing the document or what changed"),
+        )
+        .into()),
+    }
+}
+
+/// Gets a number out of a command response, which the server may send as any numeric type
+fn number(document: &Document, key: &str) -> u64 {
+    match document.get(key) {
+        Some(Bson::Int32(x)) => *x as u64,
+        Some(Bson::Int64(x)) => *x as u64,
+        Some(Bson::Double(x)) => *x as u64,
+        _ => 0,
+    }
+}
+
+/// Whether the server compares two values as the same type, which all numbers are
+fn same_type(a: Option<&Bson>, b: Option<&Bson>) -> bool {
+    let numeric = |x: &Bson| {
+        matches!(
+            x,
+            Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) | Bson::Decimal128(_)
+        )
+    };
+    match (a, b) {
+        (Some(a), Some(b)) => (numeric(a) && numeric(b)) || a.element_type() == b.element_type(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_time_of_resume_tokens() {
+        let token = |data: &str| {
+            mongodb::bson::from_document::<ResumeToken>(doc! { "_data": data }).unwrap()
+        };
+        assert_eq!(
+            resume_token_time(&token("8263A5B4E1000000022B022C0100296E5A1004")),
+            Some(Timestamp {
+                time: 0x63A5B4E1,
+                increment: 2,
+            })
+        );
+        assert_eq!(resume_token_time(&token("8263A5")), None);
+        assert_eq!(resume_token_time(&token("not a token")), None);
+    }
+
+    /// The read preference `settings` give a client whose connection string said to read from
+    /// the nearest member
+    fn read_preference(settings: &ClusterSettings) -> ReadPreference {
+        let mut options = ClientOptions::default();
+        options.selection_criteria =
+            Some(SelectionCriteria::ReadPreference(ReadPreference::Nearest {
+                options: Default::default(),
+            }));
+        settings.apply(&mut options);
+        match options.selection_criteria {
+            Some(SelectionCriteria::ReadPreference(read_preference)) => read_preference,
+            criteria => panic!("Not a read preference: {criteria:?}"),
         }
     }
+
+    #[test]
+    fn sets_the_read_preference() {
+        let mut settings = ClusterSettings::default();
+        assert!(matches!(
+            read_preference(&settings),
+            ReadPreference::Nearest { .. }
+        ));
+
+        settings.read_mode = ReadMode::Primary;
+        settings.read_tags = vec![BTreeMap::from([("dc".into(), "east".into())])];
+        assert_eq!(read_preference(&settings), ReadPreference::Primary);
+
+        settings.read_mode = ReadMode::Secondary;
+        settings.read_tags.push(BTreeMap::new());
+        match read_preference(&settings) {
+            ReadPreference::Secondary { options } => {
+                let tag_sets = options.tag_sets.unwrap();
+                assert_eq!(tag_sets.len(), 2);
+                assert_eq!(tag_sets[0].get("dc").map(String::as_str), Some("east"));
+                assert!(tag_sets[1].is_empty());
+            }
+            read_preference => panic!("Not secondary: {read_preference:?}"),
+        }
+
+        settings.read_mode = ReadMode::SecondaryPreferred;
+        settings.read_tags.clear();
+        match read_preference(&settings) {
+            ReadPreference::SecondaryPreferred { options } => assert!(options.tag_sets.is_none()),
+            read_preference => panic!("Not secondary preferred: {read_preference:?}"),
+        }
+    }
+
+    #[test]
+    fn only_reads_documents_at_snapshot_level() {
+        let read_concern = |level| {
+            let settings = ClusterSettings {
+                read_concern: level,
+                ..Default::default()
+            };
+            let mut options = ClientOptions::default();
+            options.read_concern = Some(ReadConcern::available());
+            settings.apply(&mut options);
+            (options.read_concern, settings.document_read_concern())
+        };
+
+        assert_eq!(
+            read_concern(ReadLevel::Default),
+            (Some(ReadConcern::available()), None)
+        );
+        assert_eq!(
+            read_concern(ReadLevel::Local),
+            (Some(ReadConcern::local()), None)
+        );
+        assert_eq!(
+            read_concern(ReadLevel::Majority),
+            (Some(ReadConcern::majority()), None)
+        );
+        assert_eq!(
+            read_concern(ReadLevel::Snapshot),
+            (
+                Some(ReadConcern::available()),
+                Some(ReadConcern::snapshot())
+            )
+        );
+    }
+
+    #[test]
+    fn adds_to_the_write_concern() {
+        let write_concern = |settings: ClusterSettings| {
+            let mut options = ClientOptions::default();
+            options.write_concern = Some(WriteConcern::builder().journal(false).build());
+            settings.apply(&mut options);
+            options.write_concern.unwrap()
+        };
+
+        let unchanged = write_concern(ClusterSettings::default());
+        assert_eq!(unchanged.journal, Some(false));
+        assert_eq!(unchanged.w, None);
+
+        let changed = write_concern(ClusterSettings {
+            write_ack: WriteAck::Tag("multiRegion".into()),
+            journal: true,
+            write_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        });
+        assert_eq!(
+            changed.w,
+            Some(Acknowledgment::Custom("multiRegion".into()))
+        );
+        assert_eq!(changed.journal, Some(true));
+        assert_eq!(changed.w_timeout, Some(Duration::from_secs(5)));
+
+        let majority = write_concern(ClusterSettings {
+            write_ack: WriteAck::Majority,
+            ..Default::default()
+        });
+        assert_eq!(majority.w, Some(Acknowledgment::Majority));
+        assert_eq!(majority.journal, Some(false));
+    }
+
+    #[test]
+    fn selects_buckets_by_any_of_their_names() {
+        let selected = |patterns: &str| {
+            let mut dbs = [Db::from((
+                "app".to_string(),
+                ["fs.files", "fs.chunks", "users"]
+                    .map(String::from)
+                    .to_vec(),
+            ))];
+            Db::select_matching(&mut dbs, &Selection::parse(patterns));
+            dbs[0]
+                .collections
+                .iter()
+                .filter(|collection| collection.selected)
+                .map(|collection| collection.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(selected("app.fs"), ["fs"]);
+        assert_eq!(selected("app.fs.files"), ["fs"]);
+        assert_eq!(selected("app.users"), ["users"]);
+        assert_eq!(selected("!app.fs.chunks"), ["users"]);
+        assert_eq!(selected("app.*"), ["fs", "users"]);
+    }
 }
@@ -0,0 +1,285 @@
+use crate::{
+    endpoint::Namespace,
+    users::{Privilege, RoleName},
+};
+use mongodb::bson::{Bson, Document};
+use std::{fmt, time::Duration};
+
+/// What an endpoint is going to be used for, which decides the privileges it needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+    /// Collections are read from it, as a source
+    Read {
+        /// The collections that are going to be read, or none if they haven't been picked yet
+        namespaces: Vec<Namespace>,
+        /// Whether the changes made while copying are read too, for a snapshot clone of a
+        /// server that can't read as of a past time
+        changes: bool,
+    },
+    /// Collections are written to it, as a target
+    Write,
+}
+
+/// How a cluster's servers are set up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Topology {
+    /// A single server on its own
+    Standalone,
+    /// Servers copying each other, with the set's name
+    ReplicaSet(String),
+    /// A `mongos` in front of shards
+    Sharded,
+}
+
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Topology::Standalone => write!(f, "Standalone"),
+            Topology::ReplicaSet(name) => write!(f, "Replica set {name}"),
+            Topology::Sharded => write!(f, "Sharded cluster"),
+        }
+    }
+}
+
+/// What testing a connection to a cluster found out
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnosis {
+    /// How long a ping took
+    pub latency: Duration,
+    /// The server's version, like `6.0.3`
+    pub version: String,
+    pub topology: Topology,
+    /// The users the connection logged in as, like `admin.cloner`. None if auth is off.
+    pub users: Vec<String>,
+    /// Every role the users have, including the ones they get through other roles
+    pub roles: Vec<RoleName>,
+    /// What the users can't do that they need to, empty if they can do everything
+    pub missing: Vec<String>,
+}
+
+/// What `privileges` are missing for `access`, as a line for each action
+pub fn missing_privileges(privileges: &[Privilege], access: &Access) -> Vec<String> {
+    let resources = |action: &str| {
+        privileges
+            .iter()
+            .filter(|privilege| privilege.actions.iter().any(|x| x == action))
+            .map(|privilege| &privilege.resource)
+            .collect::<Vec<_>>()
+    };
+    let mut missing = vec![];
+
+    match access {
+        // Without knowing what's going to be read, it can at least read something
+        Access::Read { namespaces, .. } if namespaces.is_empty() => {
+            for action in ["find", "listCollections"] {
+                if resources(action).is_empty() {
+                    missing.push(format!("{action} on any database"));
+                }
+            }
+        }
+        Access::Read { namespaces, .. } => {
+            let mut dbs = namespaces.iter().map(|ns| &ns.db).collect::<Vec<_>>();
+            dbs.sort_unstable();
+            dbs.dedup();
+            let list = resources("listCollections");
+            let unlisted = dbs
+                .into_iter()
+                .filter(|db| !list.iter().any(|resource| covers_db(resource, db)))
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            if !unlisted.is_empty() {
+                missing.push(format!("listCollections on {}", unlisted.join(", ")));
+            }
+
+            let find = resources("find");
+            let unread = namespaces
+                .iter()
+                .filter(|ns| !find.iter().any(|resource| covers(resource, ns)))
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            if !unread.is_empty() {
+                missing.push(format!("find on {}", unread.join(", ")));
+            }
+        }
+        Access::Write => {
+            for action in ["insert", "createCollection"] {
+                every_database(&resources(action), action, &mut missing);
+            }
+        }
+    }
+
+    // Changes are watched across the whole cluster
+    if let Access::Read { changes: true, .. } = access {
+        for action in ["changeStream", "find"] {
+            let resources = resources(action);
+            if !resources
+                .iter()
+                .any(|resource| any_resource(resource) || every_db(resource))
+            {
+                missing.push(format!(
+                    "{action} on every database, to catch up on changes made while copying"
+                ));
+            }
+        }
+    }
+
+    missing
+}
+
+/// Adds a line to `missing` unless `resources` cover every database for `action`
+fn every_database(resources: &[&Document], action: &str, missing: &mut Vec<String>) {
+    if resources
+        .iter()
+        .any(|resource| any_resource(resource) || every_db(resource))
+    {
+        return;
+    }
+
+    let mut dbs = resources
+        .iter()
+        .filter_map(|resource| resource.get_str("db").ok())
+        .filter(|db| !db.is_empty())
+        .collect::<Vec<_>>();
+    dbs.sort_unstable();
+    dbs.dedup();
+    if dbs.is_empty() {
+        missing.push(format!("{action} on any database"));
+    } else {
+        missing.push(format!(
+            "{action} on every database, only has it on {}",
+            dbs.join(", ")
+        ));
+    }
+}
+
+/// Whether a resource takes in the collection `ns`
+fn covers(resource: &Document, ns: &Namespace) -> bool {
+    let matches = |key, name: &str| {
+        resource
+            .get_str(key)
+            .is_ok_and(|value| value.is_empty() || value == name)
+    };
+    any_resource(resource) || (matches("db", &ns.db) && matches("collection", &ns.collection))
+}
+
+/// Whether a resource takes in the whole database `db`
+fn covers_db(resource: &Document, db: &str) -> bool {
+    any_resource(resource)
+        || (resource
+            .get_str("db")
+            .is_ok_and(|value| value.is_empty() || value == db)
+            && resource.get_str("collection") == Ok(""))
+}
+
+/// Whether a resource is `{ anyResource: true }`
+fn any_resource(resource: &Document) -> bool {
+    resource.get("anyResource") == Some(&Bson::Boolean(true))
+}
+
+/// Whether a resource is every collection of every database, `{ db: "", collection: "" }`
+fn every_db(resource: &Document) -> bool {
+    resource.get_str("db") == Ok("") && resource.get_str("collection") == Ok("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    fn privilege(resource: Document, actions: &[&str]) -> Privilege {
+        Privilege {
+            resource,
+            actions: actions.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    fn read(namespaces: &[&str], changes: bool) -> Access {
+        Access::Read {
+            namespaces: namespaces.iter().map(|ns| ns.parse().unwrap()).collect(),
+            changes,
+        }
+    }
+
+    #[test]
+    fn only_needs_to_read_what_is_selected() {
+        let privileges = [
+            privilege(
+                doc! { "db": "shop", "collection": "" },
+                &["find", "listCollections"],
+            ),
+            privilege(doc! { "db": "blog", "collection": "posts" }, &["find"]),
+        ];
+        let selected = read(&["shop.orders", "shop.users", "blog.posts"], false);
+        assert_eq!(
+            missing_privileges(&privileges, &selected),
+            ["listCollections on blog"]
+        );
+
+        let selected = read(&["shop.orders", "blog.comments"], false);
+        assert_eq!(
+            missing_privileges(&privileges, &selected),
+            ["listCollections on blog", "find on blog.comments"]
+        );
+    }
+
+    #[test]
+    fn reads_something_before_anything_is_selected() {
+        let privileges = [privilege(
+            doc! { "db": "shop", "collection": "" },
+            &["find"],
+        )];
+        assert_eq!(
+            missing_privileges(&privileges, &read(&[], false)),
+            ["listCollections on any database"]
+        );
+        assert!(missing_privileges(
+            &[privilege(
+                doc! { "anyResource": true },
+                &["find", "listCollections"]
+            )],
+            &read(&[], false)
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn needs_every_database_to_catch_up() {
+        let privileges = [
+            privilege(
+                doc! { "db": "shop", "collection": "" },
+                &["find", "listCollections"],
+            ),
+            privilege(doc! { "db": "shop", "collection": "" }, &["changeStream"]),
+        ];
+        assert!(missing_privileges(&privileges, &read(&["shop.orders"], false)).is_empty());
+        assert_eq!(
+            missing_privileges(&privileges, &read(&["shop.orders"], true)),
+            [
+                "changeStream on every database, to catch up on changes made while copying",
+                "find on every database, to catch up on changes made while copying",
+            ]
+        );
+
+        let privileges = [privilege(
+            doc! { "db": "", "collection": "" },
+            &["find", "listCollections", "changeStream"],
+        )];
+        assert!(missing_privileges(&privileges, &read(&["shop.orders"], true)).is_empty());
+    }
+
+    #[test]
+    fn writes_to_every_database() {
+        let privileges = [
+            privilege(doc! { "db": "shop", "collection": "" }, &["insert"]),
+            privilege(doc! { "db": "", "collection": "" }, &["createCollection"]),
+        ];
+        assert_eq!(
+            missing_privileges(&privileges, &Access::Write),
+            ["insert on every database, only has it on shop"]
+        );
+        assert_eq!(
+            missing_privileges(&[], &Access::Write),
+            ["insert on any database", "createCollection on any database"]
+        );
+    }
+}
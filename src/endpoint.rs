@@ -0,0 +1,425 @@
+pub mod local;
+pub mod memory;
+
+use crate::{
+    connection::ConnectionString,
+    db::{ClusterClient, ClusterSettings},
+    diagnostics::{Access, Diagnosis},
+    sharding::ShardLayout,
+    tunnel::SshTunnel,
+    users::{Role, User, Users},
+};
+use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt};
+use local::{LocalFormat, LocalStore};
+use mongodb::{
+    bson::{doc, Bson, Document, Timestamp},
+    error::Result as MongoResult,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, io, ops::Bound, str::FromStr, sync::Arc};
+
+/// The number of documents written to an endpoint at once
+pub const BATCH_SIZE: usize = 1000;
+
+/// The documents of a collection, as read from an endpoint
+pub type DocumentStream = BoxStream<'static, MongoResult<Document>>;
+
+/// The full name of a collection
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Namespace {
+    /// The database the collection is in
+    pub db: String,
+    /// The name of the collection
+    pub collection: String,
+}
+
+impl Namespace {
+    pub fn new(db: impl Into<String>, collection: impl Into<String>) -> Self {
+        Self {
+            db: db.into(),
+            collection: collection.into(),
+        }
+    }
+}
+
+impl FromStr for Namespace {
+    type Err = String;
+
+    /// Parses `<db>.<collection>`, database names can't have dots in them but collections can
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('.') {
+            Some((db, collection)) if !db.is_empty() && !collection.is_empty() => {
+                Ok(Self::new(db, collection))
+            }
+            _ => Err(format!("Expected `<db>.<collection>`, found `{s}`")),
+        }
+    }
+}
+
+impl fmt::Display for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.db, self.collection)
+    }
+}
+
+/// How big a collection is, as far as an endpoint can cheaply tell. Anything it can't tell is 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CollectionStats {
+    /// The number of documents in the collection
+    pub documents: u64,
+    /// The total size of the documents in the collection, as BSON
+    pub bytes: u64,
+}
+
+/// A range of `_id`s in a collection, used to read parts of a collection at the same time
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdRange {
+    /// The smallest `_id` in the range
+    pub start: Bound<Bson>,
+    /// The largest `_id` in the range
+    pub end: Bound<Bson>,
+}
+
+impl IdRange {
+    /// The range covering every document
+    pub fn all() -> Self {
+        Self {
+            start: Bound::Unbounded,
+            end: Bound::Unbounded,
+        }
+    }
+
+    /// Every document, like [`IdRange::all`], but read in `_id` order so a read that fails part
+    /// way can carry on from the last `_id`. Only for collections whose `_id`s are all one type,
+    /// as queries only compare values of the same type.
+    pub fn ordered() -> Self {
+        Self {
+            start: Bound::Included(Bson::MinKey),
+            end: Bound::Unbounded,
+        }
+    }
+
+    /// Whether this range covers every document
+    pub fn is_all(&self) -> bool {
+        matches!(
+            (&self.start, &self.end),
+            (Bound::Unbounded, Bound::Unbounded)
+        )
+    }
+
+    /// Splits the whole collection into ranges between sorted `boundaries`, each range including
+    /// its lower boundary
+    pub fn split(boundaries: Vec<Bson>) -> Vec<Self> {
+        let mut ranges = vec![];
+        let mut start = Bound::Unbounded;
+        for boundary in boundaries {
+            ranges.push(Self {
+                start,
+                end: Bound::Excluded(boundary.clone()),
+            });
+            start = Bound::Included(boundary);
+        }
+        ranges.push(Self {
+            start,
+            end: Bound::Unbounded,
+        });
+        ranges
+    }
+
+    /// A query filter matching the documents in the range
+    pub fn filter(&self) -> Document {
+        let mut id = Document::new();
+        match &self.start {
+            Bound::Included(x) => id.insert("$gte", x.clone()),
+            Bound::Excluded(x) => id.insert("$gt", x.clone()),
+            Bound::Unbounded => None,
+        };
+        match &self.end {
+            Bound::Included(x) => id.insert("$lte", x.clone()),
+            Bound::Excluded(x) => id.insert("$lt", x.clone()),
+            Bound::Unbounded => None,
+        };
+
+        if id.is_empty() {
+            Document::new()
+        } else {
+            doc! { "_id": id }
+        }
+    }
+}
+
+impl fmt::Display for IdRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.start {
+            Bound::Included(x) => write!(f, "[{x}, ")?,
+            Bound::Excluded(x) => write!(f, "({x}, ")?,
+            Bound::Unbounded => write!(f, "(.., ")?,
+        }
+        match &self.end {
+            Bound::Included(x) => write!(f, "{x}]"),
+            Bound::Excluded(x) => write!(f, "{x})"),
+            Bound::Unbounded => write!(f, "..)"),
+        }
+    }
+}
+
+/// Changes made to an endpoint's collections with the cluster time they were made at, see
+/// [`Endpoint::changes`]
+pub type ChangeStream = BoxStream<'static, MongoResult<(Timestamp, Namespace, Change)>>;
+
+/// How an endpoint lets every collection be copied as of the same moment
+pub enum Snapshot {
+    /// Reads through `endpoint` all see the data as it was at `time`
+    Frozen {
+        endpoint: Arc<dyn Endpoint>,
+        time: Timestamp,
+    },
+    /// The data can't be read as it was in the past, but the changes made from `time` onwards can
+    /// be replayed on top of a copy with [`Endpoint::changes`]
+    CatchUp { time: Timestamp },
+}
+
+/// The changes made to some collections from one moment up until about now
+pub struct Changes {
+    /// The moment the changes go up to
+    pub until: Timestamp,
+    /// Every change, in the order they were made
+    pub stream: ChangeStream,
+}
+
+/// A change made to a document, replayed on a copy to catch it up
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// A document was inserted or replaced
+    Upsert(Document),
+    /// Fields of the document with `id` were set or removed, with `set` keyed by dotted paths
+    Update {
+        id: Bson,
+        set: Document,
+        unset: Vec<String>,
+    },
+    /// The document with `id` was deleted
+    Delete(Bson),
+}
+
+/// The error for things an endpoint can't do
+fn unsupported(message: String) -> mongodb::error::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message).into()
+}
+
+/// Something collections can be cloned from or to, be it a live cluster, a directory of files or
+/// something in memory. Any endpoint can be cloned to any other endpoint.
+#[async_trait]
+pub trait Endpoint: Send + Sync {
+    /// Gets the namespace of every collection in the endpoint
+    async fn list_namespaces(&self) -> MongoResult<Vec<Namespace>>;
+
+    /// Roughly how big a collection is, used to show how far along a clone is
+    async fn collection_stats(&self, _ns: &Namespace) -> MongoResult<CollectionStats> {
+        Ok(CollectionStats::default())
+    }
+
+    /// Reads all the documents in a collection
+    async fn read_collection(&self, ns: &Namespace) -> MongoResult<DocumentStream>;
+
+    /// Splits a collection into about `count` ranges that can be read at the same time, which is
+    /// [`IdRange::ordered`] for a `count` of 1 if it can be read in `_id` order. Endpoints that
+    /// can't read parts of a collection give back a single range covering everything.
+    async fn partition(&self, _ns: &Namespace, _count: usize) -> MongoResult<Vec<IdRange>> {
+        Ok(vec![IdRange::all()])
+    }
+
+    /// Reads the documents in a collection with an `_id` in `range`. Anything short of the whole
+    /// collection comes back in `_id` order, so a read that fails part way can carry on from the
+    /// last `_id`.
+    async fn read_range(&self, ns: &Namespace, range: &IdRange) -> MongoResult<DocumentStream> {
+        if range.is_all() {
+            self.read_collection(ns).await
+        } else {
+            Err(unsupported(format!(
+                "Can't read part of {ns}, only the whole collection"
+            )))
+        }
+    }
+
+    /// Reads the documents in a collection matching a query `filter`. Only clusters can run
+    /// queries, anything else can only read the whole collection with an empty filter.
+    async fn read_matching(
+        &self,
+        ns: &Namespace,
+        filter: &Document,
+    ) -> MongoResult<DocumentStream> {
+        if filter.is_empty() {
+            self.read_collection(ns).await
+        } else {
+            Err(unsupported(format!(
+                "Can't query {ns}, only read the whole collection"
+            )))
+        }
+    }
+
+    /// Picks the current moment to copy `namespaces` as of, see [`Snapshot`]
+    async fn snapshot(&self, _namespaces: &[Namespace]) -> MongoResult<Snapshot> {
+        Err(unsupported(
+            "Only clusters can be copied as of a single moment".into(),
+        ))
+    }
+
+    /// Gets the changes made to `namespaces` from `since` up until about now, to catch up a copy
+    /// of them taken in the meantime
+    async fn changes(&self, _namespaces: &[Namespace], _since: Timestamp) -> MongoResult<Changes> {
+        Err(unsupported("Only clusters keep track of changes".into()))
+    }
+
+    /// Whether [`Endpoint::apply_change`] works, so changes can be replayed on it
+    fn applies_changes(&self) -> bool {
+        false
+    }
+
+    /// Replays a change from another endpoint's [`Endpoint::changes`] on a collection
+    async fn apply_change(&self, ns: &Namespace, _change: Change) -> MongoResult<()> {
+        Err(unsupported(format!("Can't replay changes on {ns}")))
+    }
+
+    /// How a collection is sharded, or `None` if it isn't. Split points and zones are only read
+    /// if `splits` is set.
+    async fn shard_layout(
+        &self,
+        _ns: &Namespace,
+        _splits: bool,
+    ) -> MongoResult<Option<ShardLayout>> {
+        Ok(None)
+    }
+
+    /// Shards a collection that hasn't been written to yet the way `layout` says
+    async fn shard(&self, ns: &Namespace, _layout: &ShardLayout) -> MongoResult<()> {
+        Err(unsupported(format!(
+            "Can't shard {ns}, only sharded clusters can"
+        )))
+    }
+
+    /// Checks the endpoint can be reached, what it is, and whether it can be used for `access`
+    async fn diagnose(&self, _access: Access) -> MongoResult<Diagnosis> {
+        Err(unsupported("Only clusters can be tested".into()))
+    }
+
+    /// Reads the custom roles and the users defined in `dbs`
+    async fn users(&self, _dbs: &[String]) -> MongoResult<Users> {
+        Err(unsupported("Only clusters have users".into()))
+    }
+
+    /// Creates a custom role, or updates it if it's already there
+    async fn put_role(&self, role: &Role) -> MongoResult<()> {
+        Err(unsupported(format!("Can't create role {}", role.role)))
+    }
+
+    /// Creates a user with `password`, or updates them if they're already there
+    async fn put_user(&self, user: &User, _password: &str) -> MongoResult<()> {
+        Err(unsupported(format!("Can't create user {}", user.user)))
+    }
+
+    /// Writes a batch of documents to a collection, adding to whatever is already in there. This
+    /// may be called for the same collection from several tasks at once.
+    async fn write_batch(&self, ns: &Namespace, documents: Vec<Document>) -> MongoResult<()>;
+
+    /// Writes a stream of documents to a collection in batches of [`BATCH_SIZE`], returning the
+    /// number of documents written
+    async fn write_collection(
+        &self,
+        ns: &Namespace,
+        documents: DocumentStream,
+    ) -> MongoResult<u64> {
+        let mut batches = documents.chunks(BATCH_SIZE);
+        let mut written = 0;
+
+        while let Some(batch) = batches.next().await {
+            let batch = batch.into_iter().collect::<MongoResult<Vec<_>>>()?;
+            written += batch.len() as u64;
+            self.write_batch(ns, batch).await?;
+        }
+
+        Ok(written)
+    }
+}
+
+/// The kinds of places collections can be cloned from and to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EndpointKind {
+    /// A live MongoDB cluster
+    Cluster,
+    /// A directory of files on this machine
+    Local,
+}
+
+/// Everything needed to open an endpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EndpointConfig {
+    /// Which of the fields below are used
+    pub kind: EndpointKind,
+    /// The connection string of the cluster, for [`EndpointKind::Cluster`]
+    pub url: ConnectionString,
+    /// The directory of the store, for [`EndpointKind::Local`]
+    pub path: String,
+    /// How the store's collections are written, for [`EndpointKind::Local`]
+    pub format: LocalFormat,
+    /// How the cluster is read from and written to, for [`EndpointKind::Cluster`]
+    #[serde(default)]
+    pub cluster: ClusterSettings,
+    /// The jump host the cluster is reached through, for [`EndpointKind::Cluster`]
+    #[serde(default)]
+    pub tunnel: SshTunnel,
+}
+
+impl EndpointConfig {
+    /// Configuration for a cluster at `url`
+    pub fn cluster(url: ConnectionString) -> Self {
+        Self {
+            kind: EndpointKind::Cluster,
+            url,
+            path: String::new(),
+            format: LocalFormat::Bson,
+            cluster: ClusterSettings::default(),
+            tunnel: SshTunnel::default(),
+        }
+    }
+
+    /// Connects to the cluster or opens the local store
+    pub async fn connect(&self) -> MongoResult<Arc<dyn Endpoint>> {
+        Ok(match self.kind {
+            EndpointKind::Cluster => {
+                Arc::new(ClusterClient::connect(&self.url, &self.cluster, &self.tunnel).await?)
+            }
+            EndpointKind::Local => Arc::new(LocalStore::new(&self.path, self.format)?),
+        })
+    }
+}
+
+impl FromStr for EndpointConfig {
+    type Err = String;
+
+    /// Parses a cluster's connection string, or `bson:<directory>` or `json:<directory>` for a
+    /// local store
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let local = |path: &str, format| Self {
+            kind: EndpointKind::Local,
+            url: "mongodb://localhost:27017".parse().unwrap(),
+            path: path.to_string(),
+            format,
+            cluster: ClusterSettings::default(),
+            tunnel: SshTunnel::default(),
+        };
+
+        if let Some(path) = s.strip_prefix("bson:") {
+            Ok(local(path, LocalFormat::Bson))
+        } else if let Some(path) = s.strip_prefix("json:") {
+            Ok(local(path, LocalFormat::Json))
+        } else if s.starts_with("mongodb://") || s.starts_with("mongodb+srv://") {
+            s.parse().map(Self::cluster)
+        } else {
+            Err(format!(
+                "Expected a `mongodb://` connection string, `bson:<directory>` or `json:<directory>`, found `{s}`"
+            ))
+        }
+    }
+}
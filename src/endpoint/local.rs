@@ -0,0 +1,398 @@
+use crate::endpoint::{CollectionStats, DocumentStream, Endpoint, Namespace, BATCH_SIZE};
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use mongodb::{
+    bson::{Bson, Document},
+    error::Result as MongoResult,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc;
+use tracing::{debug, info};
+
+/// How the documents of a collection are written to its file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LocalFormat {
+    /// Documents written back to back as raw BSON, the way `mongodump` does
+    Bson,
+    /// One canonical extended JSON document per line, the way `mongoexport` does
+    Json,
+}
+
+impl LocalFormat {
+    /// The extension of collection files in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Bson => "bson",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// A clone stored on the local filesystem, laid out the same way `mongodump` lays out a dump so
+/// that it can also be loaded with `mongorestore` (or `mongoimport` for JSON):
+///
+/// ```text
+/// <root>/<database>/<collection>.bson
+/// ```
+#[derive(Debug, Clone)]
+pub struct LocalStore {
+    /// The directory the databases are stored in
+    root: PathBuf,
+    /// How collections are stored
+    format: LocalFormat,
+    /// The files written to so far, each with a lock held while writing to it so batches written
+    /// at the same time don't get mixed together
+    files: Arc<Mutex<BTreeMap<PathBuf, Arc<Mutex<()>>>>>,
+}
+
+impl LocalStore {
+    /// Opens a local store at `root`, creating the directory if it doesn't exist yet
+    pub fn new(root: impl Into<PathBuf>, format: LocalFormat) -> MongoResult<Self> {
+        let root = root.into();
+        info!("Opening local store at {}", root.display());
+        fs::create_dir_all(&root)?;
+        Ok(Self {
+            root,
+            format,
+            files: Arc::default(),
+        })
+    }
+
+    /// The path of the file that a collection is stored in. Names that would lead out of the
+    /// database's directory are refused.
+    fn collection_path(&self, ns: &Namespace) -> MongoResult<PathBuf> {
+        for (name, what) in [(&ns.db, "database"), (&ns.collection, "collection")] {
+            if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("The {what} name `{name}` can't be used as a file name"),
+                )
+                .into());
+            }
+        }
+        Ok(self
+            .root
+            .join(&ns.db)
+            .join(format!("{}.{}", ns.collection, self.format.extension())))
+    }
+}
+
+#[async_trait]
+impl Endpoint for LocalStore {
+    async fn list_namespaces(&self) -> MongoResult<Vec<Namespace>> {
+        let root = self.root.clone();
+        let extension = self.format.extension();
+        tokio::task::spawn_blocking(move || list_namespaces(&root, extension))
+            .await
+            .expect("Local store listing panicked")
+    }
+
+    async fn collection_stats(&self, ns: &Namespace) -> MongoResult<CollectionStats> {
+        let path = self.collection_path(ns)?;
+        let format = self.format;
+        tokio::task::spawn_blocking(move || collection_stats(&path, format))
+            .await
+            .expect("Local store stats panicked")
+    }
+
+    async fn read_collection(&self, ns: &Namespace) -> MongoResult<DocumentStream> {
+        let path = self.collection_path(ns)?;
+        let format = self.format;
+        debug!("Reading collection {ns} from {}", path.display());
+
+        // Make sure the file is there before handing out the stream, so a missing collection is
+        // reported straight away
+        let file = File::open(&path)?;
+        let (tx, rx) = mpsc::channel(BATCH_SIZE);
+
+        tokio::task::spawn_blocking(move || {
+            let mut reader = BufReader::new(file);
+            loop {
+                let document = match read_document(&mut reader, format) {
+                    Ok(Some(document)) => Ok(document),
+                    Ok(None) => break,
+                    Err(ex) => Err(ex),
+                };
+                let failed = document.is_err();
+
+                // The reader hung up, nothing else to do
+                if tx.blocking_send(document).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|document| (document, rx))
+        })
+        .boxed())
+    }
+
+    async fn write_batch(&self, ns: &Namespace, documents: Vec<Document>) -> MongoResult<()> {
+        let path = self.collection_path(ns)?;
+        let format = self.format;
+        let files = self.files.clone();
+        debug!(
+            "Writing {} documents to {}",
+            documents.len(),
+            path.display()
+        );
+        tokio::task::spawn_blocking(move || {
+            let file = {
+                let mut files = files.lock().unwrap();
+                match files.get(&path) {
+                    Some(file) => file.clone(),
+                    None => {
+                        // Adding to what an earlier clone left would end up with everything
+                        // twice, so like a cluster's collection, the file has to be empty to
+                        // start with
+                        if fs::metadata(&path).is_ok_and(|metadata| metadata.len() > 0) {
+                            return Err(io::Error::new(
+                                io::ErrorKind::AlreadyExists,
+                                format!(
+                                    "{} already has documents in it from an earlier clone, which \
+                                     can't be carried on from. Delete it or clone somewhere else",
+                                    path.display()
+                                ),
+                            )
+                            .into());
+                        }
+                        files.entry(path.clone()).or_default().clone()
+                    }
+                }
+            };
+            let _lock = file.lock().unwrap();
+            write_documents(&path, format, &documents)
+        })
+        .await
+        .expect("Local store write panicked")
+    }
+}
+
+/// Walks `root` for `<db>/<collection>.<extension>` files
+fn list_namespaces(root: &Path, extension: &str) -> MongoResult<Vec<Namespace>> {
+    let mut namespaces = vec![];
+
+    for db in fs::read_dir(root)? {
+        let db = db?;
+        if !db.file_type()?.is_dir() {
+            continue;
+        }
+        let db_name = db.file_name().to_string_lossy().into_owned();
+
+        for collection in fs::read_dir(db.path())? {
+            let path = collection?.path();
+            if path.extension().and_then(|x| x.to_str()) != Some(extension) {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|x| x.to_str()) {
+                namespaces.push(Namespace::new(db_name.clone(), name));
+            }
+        }
+    }
+
+    namespaces.sort();
+    Ok(namespaces)
+}
+
+/// Counts the documents in a collection file without parsing them
+fn collection_stats(path: &Path, format: LocalFormat) -> MongoResult<CollectionStats> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut stats = CollectionStats::default();
+
+    match format {
+        LocalFormat::Bson => {
+            // Every document starts with its length, so hop from one to the next
+            let mut length = [0; 4];
+            while !reader.fill_buf()?.is_empty() {
+                reader.read_exact(&mut length)?;
+                let length = u32::from_le_bytes(length) as u64;
+                // The smallest document is its length and a terminating null, anything less and
+                // this would never get anywhere
+                if length < 5 {
+                    return Err(invalid_data(format!(
+                        "{} has a document {length} bytes long, it isn't BSON",
+                        path.display()
+                    ))
+                    .into());
+                }
+                reader.seek(SeekFrom::Current(length as i64 - 4))?;
+                stats.documents += 1;
+                stats.bytes += length;
+            }
+        }
+        // The size of JSON says little about the size of the BSON it turns into, so only count
+        LocalFormat::Json => {
+            for line in reader.lines() {
+                if !line?.trim().is_empty() {
+                    stats.documents += 1;
+                }
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Reads the next document from a collection file, or `None` at the end of the file
+fn read_document(reader: &mut impl BufRead, format: LocalFormat) -> MongoResult<Option<Document>> {
+    match format {
+        LocalFormat::Bson => {
+            if reader.fill_buf()?.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(Document::from_reader(reader)?))
+        }
+        LocalFormat::Json => {
+            let mut line = String::new();
+            // Skip over any blank lines
+            while line.trim().is_empty() {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+            }
+
+            let value: serde_json::Value = serde_json::from_str(&line).map_err(invalid_data)?;
+            match Bson::try_from(value).map_err(invalid_data)? {
+                Bson::Document(document) => Ok(Some(document)),
+                other => Err(invalid_data(format!(
+                    "Expected a document, found {:?}",
+                    other.element_type()
+                ))
+                .into()),
+            }
+        }
+    }
+}
+
+/// Appends documents to a collection file, creating it and it's database directory if needed
+fn write_documents(path: &Path, format: LocalFormat, documents: &[Document]) -> MongoResult<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut writer = BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?);
+    for document in documents {
+        match format {
+            LocalFormat::Bson => document.to_writer(&mut writer)?,
+            LocalFormat::Json => {
+                let value = Bson::Document(document.clone()).into_canonical_extjson();
+                serde_json::to_writer(&mut writer, &value).map_err(invalid_data)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Wraps up a parsing error as an IO error, so it can be reported like any other mongo error
+fn invalid_data(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use mongodb::bson::doc;
+    use tempfile::TempDir;
+
+    fn store(format: LocalFormat) -> (TempDir, LocalStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalStore::new(dir.path(), format).unwrap();
+        (dir, store)
+    }
+
+    #[tokio::test]
+    async fn refuses_to_add_to_an_earlier_clone() {
+        let (dir, store) = store(LocalFormat::Json);
+        let ns = Namespace::new("db", "collection");
+        let documents = vec![doc! { "_id": 1 }, doc! { "_id": 2 }];
+        store.write_batch(&ns, documents.clone()).await.unwrap();
+        store.write_batch(&ns, documents.clone()).await.unwrap();
+        assert_eq!(store.collection_stats(&ns).await.unwrap().documents, 4);
+
+        let again = LocalStore::new(dir.path(), LocalFormat::Json).unwrap();
+        let error = again.write_batch(&ns, documents).await.unwrap_err();
+        assert!(
+            error.to_string().contains("from an earlier clone"),
+            "{error}"
+        );
+        let read = again
+            .read_collection(&ns)
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(read.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn writes_to_collections_at_the_same_time() {
+        let (_dir, store) = store(LocalFormat::Bson);
+        let writes = (0..8).map(|i| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                let ns = Namespace::new("db", format!("collection{}", i % 2));
+                let documents = (0..100).map(|j| doc! { "_id": i * 100 + j }).collect();
+                store.write_batch(&ns, documents).await
+            })
+        });
+        for write in futures::future::join_all(writes).await {
+            write.unwrap().unwrap();
+        }
+        for collection in ["collection0", "collection1"] {
+            let ns = Namespace::new("db", collection);
+            let read = store
+                .read_collection(&ns)
+                .await
+                .unwrap()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(read.len(), 400);
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_names_leading_out_of_the_store() {
+        let (_dir, store) = store(LocalFormat::Bson);
+        for (db, collection) in [
+            ("..", "x"),
+            (".", "x"),
+            ("", "x"),
+            ("db", "../../x"),
+            ("a/b", "x"),
+            ("db", "x\\y"),
+        ] {
+            let ns = Namespace::new(db, collection);
+            let error = store.write_batch(&ns, vec![doc! {}]).await.unwrap_err();
+            assert!(
+                error.to_string().contains("can't be used as a file name"),
+                "{error}"
+            );
+        }
+        assert!(store.list_namespaces().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reports_bson_that_would_never_end() {
+        let (dir, store) = store(LocalFormat::Bson);
+        let ns = Namespace::new("db", "collection");
+        fs::create_dir_all(dir.path().join("db")).unwrap();
+        fs::write(store.collection_path(&ns).unwrap(), [0; 8]).unwrap();
+        let error = store.collection_stats(&ns).await.unwrap_err();
+        assert!(error.to_string().contains("isn't BSON"), "{error}");
+    }
+}
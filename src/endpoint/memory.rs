@@ -0,0 +1,203 @@
+use crate::endpoint::{Change, CollectionStats, DocumentStream, Endpoint, IdRange, Namespace};
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use mongodb::{
+    bson::{self, doc, Bson, Document},
+    error::{BulkWriteFailure, ErrorKind, Result as MongoResult},
+};
+use std::{cmp::Ordering, collections::BTreeMap, io, ops::Bound, sync::Mutex};
+
+/// An endpoint that keeps everything in memory, used to test cloning without a server. Like a
+/// server, `_id`s are unique and collections whose `_id`s can be compared are read in ranges.
+/// Changes can be replayed on it, though updates only set and unset top level fields.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    /// Every collection and it's documents
+    collections: Mutex<BTreeMap<Namespace, Vec<Document>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds documents to a collection, creating it if needed
+    pub fn insert(&self, ns: Namespace, documents: impl IntoIterator<Item = Document>) {
+        self.collections
+            .lock()
+            .unwrap()
+            .entry(ns)
+            .or_default()
+            .extend(documents);
+    }
+
+    /// Gets a copy of the documents in a collection
+    pub fn documents(&self, ns: &Namespace) -> Option<Vec<Document>> {
+        self.collections.lock().unwrap().get(ns).cloned()
+    }
+
+    /// The documents in a collection, or an error if there's no such collection
+    fn existing(&self, ns: &Namespace) -> MongoResult<Vec<Document>> {
+        self.documents(ns).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("No collection {ns}")).into()
+        })
+    }
+}
+
+#[async_trait]
+impl Endpoint for MemoryStore {
+    async fn list_namespaces(&self) -> MongoResult<Vec<Namespace>> {
+        Ok(self.collections.lock().unwrap().keys().cloned().collect())
+    }
+
+    async fn collection_stats(&self, ns: &Namespace) -> MongoResult<CollectionStats> {
+        let documents = self.documents(ns).unwrap_or_default();
+        let mut bytes = 0;
+        for document in &documents {
+            bytes += mongodb::bson::to_vec(document)?.len() as u64;
+        }
+        Ok(CollectionStats {
+            documents: documents.len() as u64,
+            bytes,
+        })
+    }
+
+    async fn read_collection(&self, ns: &Namespace) -> MongoResult<DocumentStream> {
+        let documents = self.existing(ns)?;
+        Ok(stream::iter(documents.into_iter().map(Ok)).boxed())
+    }
+
+    async fn partition(&self, ns: &Namespace, count: usize) -> MongoResult<Vec<IdRange>> {
+        let mut ids = self
+            .existing(ns)?
+            .iter()
+            .map(|document| document.get("_id").cloned().unwrap_or(Bson::Null))
+            .collect::<Vec<_>>();
+        // Like a cluster, `_id`s of different types are only read as a whole
+        let Some(first) = ids.first().cloned() else {
+            return Ok(vec![IdRange::all()]);
+        };
+        if ids.iter().any(|id| compare(&first, id).is_none()) {
+            return Ok(vec![IdRange::all()]);
+        }
+        if count < 2 {
+            return Ok(vec![IdRange::ordered()]);
+        }
+
+        ids.sort_by(|a, b| compare(a, b).unwrap());
+        let mut boundaries = (1..count)
+            .map(|i| ids[i * ids.len() / count].clone())
+            .collect::<Vec<_>>();
+        boundaries.dedup();
+        Ok(IdRange::split(boundaries))
+    }
+
+    async fn read_range(&self, ns: &Namespace, range: &IdRange) -> MongoResult<DocumentStream> {
+        if range.is_all() {
+            return self.read_collection(ns).await;
+        }
+        let mut documents = self
+            .existing(ns)?
+            .into_iter()
+            .filter(|document| document.get("_id").is_some_and(|id| contains(range, id)))
+            .collect::<Vec<_>>();
+        documents.sort_by(|a, b| match (a.get("_id"), b.get("_id")) {
+            (Some(a), Some(b)) => compare(a, b).unwrap_or(Ordering::Equal),
+            _ => Ordering::Equal,
+        });
+        Ok(stream::iter(documents.into_iter().map(Ok)).boxed())
+    }
+
+    /// Writes every document whose `_id` isn't already in the collection, and fails with a
+    /// duplicate key error for the rest, like an unordered insert
+    async fn write_batch(&self, ns: &Namespace, documents: Vec<Document>) -> MongoResult<()> {
+        let mut collections = self.collections.lock().unwrap();
+        let collection = collections.entry(ns.clone()).or_default();
+        let mut write_errors = vec![];
+        for (index, document) in documents.into_iter().enumerate() {
+            let id = document.get("_id");
+            if id.is_some() && collection.iter().any(|x| x.get("_id") == id) {
+                write_errors.push(doc! {
+                    "index": index as i64,
+                    "code": 11000,
+                    "errmsg": format!("Duplicate _id in {ns}"),
+                });
+            } else {
+                collection.push(document);
+            }
+        }
+
+        if write_errors.is_empty() {
+            Ok(())
+        } else {
+            // The driver's errors can't be made outside it, apart from by deserializing them
+            let failure: BulkWriteFailure =
+                bson::from_document(doc! { "writeErrors": write_errors })?;
+            Err(ErrorKind::BulkWrite(failure).into())
+        }
+    }
+
+    fn applies_changes(&self) -> bool {
+        true
+    }
+
+    async fn apply_change(&self, ns: &Namespace, change: Change) -> MongoResult<()> {
+        let mut collections = self.collections.lock().unwrap();
+        let collection = collections.entry(ns.clone()).or_default();
+        let position = |id: Option<&Bson>| collection.iter().position(|x| x.get("_id") == id);
+        match change {
+            Change::Upsert(document) => match position(document.get("_id")) {
+                Some(index) => collection[index] = document,
+                None => collection.push(document),
+            },
+            Change::Update { id, set, unset } => {
+                if let Some(index) = position(Some(&id)) {
+                    let document = &mut collection[index];
+                    document.extend(set);
+                    for field in unset {
+                        document.remove(&field);
+                    }
+                }
+            }
+            Change::Delete(id) => {
+                if let Some(index) = position(Some(&id)) {
+                    collection.remove(index);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares two `_id`s the way queries do, which is only between values of the same type
+fn compare(a: &Bson, b: &Bson) -> Option<Ordering> {
+    let number = |x: &Bson| match x {
+        Bson::Int32(x) => Some(*x as f64),
+        Bson::Int64(x) => Some(*x as f64),
+        Bson::Double(x) => Some(*x),
+        _ => None,
+    };
+    match (a, b) {
+        (Bson::MinKey, Bson::MinKey) | (Bson::MaxKey, Bson::MaxKey) => Some(Ordering::Equal),
+        (Bson::MinKey, _) | (_, Bson::MaxKey) => Some(Ordering::Less),
+        (_, Bson::MinKey) | (Bson::MaxKey, _) => Some(Ordering::Greater),
+        (Bson::String(a), Bson::String(b)) => Some(a.cmp(b)),
+        (Bson::ObjectId(a), Bson::ObjectId(b)) => Some(a.cmp(b)),
+        _ => number(a)?.partial_cmp(&number(b)?),
+    }
+}
+
+/// Whether `id` is in `range`
+fn contains(range: &IdRange, id: &Bson) -> bool {
+    let above = match &range.start {
+        Bound::Included(start) => compare(start, id).is_some_and(Ordering::is_le),
+        Bound::Excluded(start) => compare(start, id).is_some_and(Ordering::is_lt),
+        Bound::Unbounded => true,
+    };
+    let below = match &range.end {
+        Bound::Included(end) => compare(id, end).is_some_and(Ordering::is_le),
+        Bound::Excluded(end) => compare(id, end).is_some_and(Ordering::is_lt),
+        Bound::Unbounded => true,
+    };
+    above && below
+}
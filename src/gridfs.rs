@@ -0,0 +1,168 @@
+use crate::endpoint::Namespace;
+use mongodb::bson::{doc, Bson, Document};
+use serde::{Deserialize, Serialize};
+
+/// The suffix of the collection a GridFS bucket keeps its files in
+pub const FILES: &str = ".files";
+
+/// The suffix of the collection a GridFS bucket keeps the chunks of its files in
+pub const CHUNKS: &str = ".chunks";
+
+/// How many files' chunks are read with one query when copying some of a bucket
+pub const FILES_PER_QUERY: usize = 1000;
+
+/// Splits up collection names into the GridFS buckets among them, which have both a `.files` and a
+/// `.chunks` collection, and everything else
+pub fn buckets(collections: Vec<String>) -> (Vec<String>, Vec<String>) {
+    let buckets = collections
+        .iter()
+        .filter_map(|name| name.strip_suffix(FILES))
+        .filter(|bucket| collections.contains(&format!("{bucket}{CHUNKS}")))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    let rest = collections
+        .into_iter()
+        .filter(|name| !buckets.iter().any(|bucket| is_part_of(name, bucket)))
+        .collect();
+    (buckets, rest)
+}
+
+/// Whether `collection` is the files or chunks collection of `bucket`
+pub fn is_part_of(collection: &str, bucket: &str) -> bool {
+    collection
+        .strip_prefix(bucket)
+        .is_some_and(|suffix| suffix == FILES || suffix == CHUNKS)
+}
+
+/// The files collection of the bucket at `bucket`
+pub fn files(bucket: &Namespace) -> Namespace {
+    Namespace::new(&bucket.db, format!("{}{FILES}", bucket.collection))
+}
+
+/// The chunks collection of the bucket at `bucket`
+pub fn chunks(bucket: &Namespace) -> Namespace {
+    Namespace::new(&bucket.db, format!("{}{CHUNKS}", bucket.collection))
+}
+
+/// The bucket a files collection belongs to
+pub fn bucket_of_files(files: &Namespace) -> Option<Namespace> {
+    files
+        .collection
+        .strip_suffix(FILES)
+        .map(|bucket| Namespace::new(&files.db, bucket))
+}
+
+/// A query for the chunks of the files with `ids`
+pub fn chunks_filter(ids: &[Bson]) -> Document {
+    doc! { "files_id": { "$in": ids } }
+}
+
+/// Only copy the files in a bucket matching `filter`, along with their chunks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileFilter {
+    /// The bucket in the source, like `app.fs`
+    pub bucket: Namespace,
+    /// A query on the files collection, like `{ "metadata.kind": "avatar" }`
+    pub filter: Document,
+}
+
+/// Parses a file filter typed out as extended JSON, like `{ "metadata.kind": "avatar" }`
+pub fn parse_filter(text: &str) -> Result<Document, String> {
+    let json = serde_json::from_str::<serde_json::Value>(text).map_err(|ex| ex.to_string())?;
+    match Bson::try_from(json).map_err(|ex| ex.to_string())? {
+        Bson::Document(filter) => Ok(filter),
+        other => Err(format!("Expected a document, found {other}")),
+    }
+}
+
+/// Writes out a file filter the way [`parse_filter`] reads it
+pub fn format_filter(filter: &Document) -> String {
+    Bson::Document(filter.clone())
+        .into_relaxed_extjson()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buckets(collections: &[&str]) -> (Vec<String>, Vec<String>) {
+        super::buckets(collections.iter().map(|x| x.to_string()).collect())
+    }
+
+    #[test]
+    fn finds_buckets_with_both_collections() {
+        assert_eq!(
+            buckets(&["fs.files", "users", "fs.chunks", "photos.files"]),
+            (
+                vec!["fs".to_string()],
+                vec!["users".to_string(), "photos.files".to_string()]
+            )
+        );
+        // A `.chunks` collection on its own isn't a bucket either
+        assert_eq!(buckets(&["fs.chunks"]).0, Vec::<String>::new());
+    }
+
+    #[test]
+    fn tells_apart_buckets_named_like_collections() {
+        // `fs.files.files` and `fs.files.chunks` are a bucket called `fs.files`, apart from `fs`
+        let (found, rest) = buckets(&[
+            "fs.files",
+            "fs.chunks",
+            "fs.files.files",
+            "fs.files.chunks",
+            "fs.files.other",
+        ]);
+        assert_eq!(found, ["fs", "fs.files"]);
+        assert_eq!(rest, ["fs.files.other"]);
+
+        // Without its own chunks, `fs.files.files` is left as it is
+        let (found, rest) = buckets(&["fs.files", "fs.chunks", "fs.files.files"]);
+        assert_eq!(found, ["fs"]);
+        assert_eq!(rest, ["fs.files.files"]);
+    }
+
+    #[test]
+    fn knows_what_belongs_to_a_bucket() {
+        assert!(is_part_of("fs.files", "fs"));
+        assert!(is_part_of("fs.chunks", "fs"));
+        assert!(!is_part_of("fs", "fs"));
+        assert!(!is_part_of("fs.files.files", "fs"));
+        assert!(!is_part_of("fsx.files", "fs"));
+        assert!(is_part_of("fs.files.files", "fs.files"));
+    }
+
+    #[test]
+    fn finds_the_bucket_of_files() {
+        let bucket = bucket_of_files(&Namespace::new("app", "fs.files.files")).unwrap();
+        assert_eq!(bucket, Namespace::new("app", "fs.files"));
+        assert_eq!(files(&bucket), Namespace::new("app", "fs.files.files"));
+        assert_eq!(chunks(&bucket), Namespace::new("app", "fs.files.chunks"));
+        assert_eq!(bucket_of_files(&Namespace::new("app", "fs.chunks")), None);
+    }
+
+    #[test]
+    fn parses_what_it_formats() {
+        let filter =
+            parse_filter(r#"{ "metadata.kind": "avatar", "length": { "$gt": 5 } }"#).unwrap();
+        assert_eq!(
+            filter,
+            doc! { "metadata.kind": "avatar", "length": { "$gt": 5 } }
+        );
+        assert_eq!(parse_filter(&format_filter(&filter)).unwrap(), filter);
+
+        let dated =
+            parse_filter(r#"{ "uploadDate": { "$date": "2024-01-02T03:04:05Z" } }"#).unwrap();
+        assert!(matches!(dated.get("uploadDate"), Some(Bson::DateTime(_))));
+        assert_eq!(parse_filter(&format_filter(&dated)).unwrap(), dated);
+    }
+
+    #[test]
+    fn only_parses_documents() {
+        assert!(parse_filter("[1, 2]")
+            .unwrap_err()
+            .contains("Expected a document"));
+        assert!(parse_filter("{ not json").is_err());
+        assert_eq!(parse_filter("{}").unwrap(), Document::new());
+    }
+}
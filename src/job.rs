@@ -0,0 +1,1474 @@
+use crate::{
+    endpoint::{
+        Changes, CollectionStats, DocumentStream, Endpoint, IdRange, Namespace, Snapshot,
+        BATCH_SIZE,
+    },
+    gridfs::{self, FileFilter},
+    retry::{self, Retry, RetryPolicy},
+    sharding::Sharding,
+    throttle::{RateLimit, Throttle},
+};
+use futures::{
+    future,
+    stream::{self, BoxStream, Chunks, StreamExt},
+};
+use mongodb::{
+    bson::{Bson, Document, Timestamp},
+    error::Result as MongoResult,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io,
+    ops::Bound,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, Semaphore};
+use tracing::{debug, info, warn};
+
+/// The events sent while a [`CloneJob`] runs
+pub type ProgressStream = BoxStream<'static, ProgressEvent>;
+
+/// Something that happened while a [`CloneJob`] was running
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    /// The job has started, with every `(source, target, size of source)` it is going to copy
+    JobStarted {
+        collections: Vec<(Namespace, Namespace, CollectionStats)>,
+    },
+    /// A collection has started being copied
+    CollectionStarted {
+        source: Namespace,
+        target: Namespace,
+    },
+    /// Another batch of a collection has been copied, with the totals copied so far
+    CollectionProgress {
+        source: Namespace,
+        target: Namespace,
+        documents: u64,
+        bytes: u64,
+    },
+    /// Copying a collection hit a transient error, and is trying again
+    CollectionRetrying {
+        source: Namespace,
+        target: Namespace,
+        retry: Retry,
+    },
+    /// A collection has been copied, or failed to be
+    CollectionFinished(CollectionReport),
+    /// Every collection has been copied, and the changes made in the meantime are being replayed
+    /// on the target, with how many have been so far
+    CatchingUp { changes: u64 },
+    /// Catching up hit a transient error, and is trying again
+    CatchUpRetrying { retry: Retry },
+    /// Every collection has been copied, or failed to be
+    JobFinished,
+}
+
+/// The outcome of cloning a single collection
+#[derive(Debug, Clone)]
+pub struct CollectionReport {
+    /// Where the collection was copied from
+    pub source: Namespace,
+    /// Where the collection was copied to
+    pub target: Namespace,
+    /// The number of documents written to `target`
+    pub documents: u64,
+    /// The number of bytes written to `target`
+    pub bytes: u64,
+    /// Why the copy failed, if it did
+    pub result: MongoResult<()>,
+    /// Transient failures that were tried again
+    pub retries: Vec<Retry>,
+    /// How long the copy took
+    pub elapsed: Duration,
+}
+
+/// How a snapshot clone kept every collection as of the same moment
+#[derive(Debug, Clone)]
+pub struct SnapshotReport {
+    /// The cluster time everything was copied as of
+    pub time: Timestamp,
+    /// The number of changes replayed on the target to catch it up to `time`, if the source
+    /// couldn't be read as of a past time
+    pub changes: u64,
+    /// Every transient error catching up hit and tried again after
+    pub retries: Vec<Retry>,
+    /// Why catching up failed, if it did
+    pub result: MongoResult<()>,
+}
+
+/// The outcome of a whole [`CloneJob`]
+#[derive(Debug, Clone)]
+pub struct CloneReport {
+    /// Every collection in the job, in the order they were given
+    pub collections: Vec<CollectionReport>,
+    /// The moment everything was copied as of, for a snapshot clone
+    pub snapshot: Option<SnapshotReport>,
+    /// How long the whole job took
+    pub elapsed: Duration,
+}
+
+impl CloneReport {
+    /// The total number of documents copied
+    pub fn documents(&self) -> u64 {
+        self.collections.iter().map(|x| x.documents).sum()
+    }
+
+    /// The total number of bytes copied
+    pub fn bytes(&self) -> u64 {
+        self.collections.iter().map(|x| x.bytes).sum()
+    }
+
+    /// The number of transient failures that were tried again
+    pub fn retries(&self) -> usize {
+        let snapshot = self.snapshot.as_ref().map_or(0, |x| x.retries.len());
+        self.collections
+            .iter()
+            .map(|x| x.retries.len())
+            .sum::<usize>()
+            + snapshot
+    }
+
+    /// The collections that failed to copy
+    pub fn failures(&self) -> impl Iterator<Item = &CollectionReport> {
+        self.collections
+            .iter()
+            .filter(|collection| collection.result.is_err())
+    }
+
+    /// Whether every collection was copied, and caught up if it had to be
+    pub fn succeeded(&self) -> bool {
+        self.failures().next().is_none()
+            && self
+                .snapshot
+                .as_ref()
+                .is_none_or(|snapshot| snapshot.result.is_ok())
+    }
+}
+
+/// How a [`CloneJob`] goes about copying
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CloneOptions {
+    /// How many parts large collections are split into and copied at the same time
+    pub partitions: usize,
+    /// Collections with at least this many documents are split into `partitions`
+    pub partition_threshold: u64,
+    /// How many collections are copied at the same time
+    pub max_collections: usize,
+    /// How many batches are copied at the same time, across every collection and partition
+    pub max_batches: usize,
+    /// How fast documents may be read from the source
+    pub read_limit: RateLimit,
+    /// How fast documents may be written to the target
+    pub write_limit: RateLimit,
+    /// How to try again after transient errors
+    pub retry: RetryPolicy,
+    /// Copy every collection as of the same moment, so they're consistent with each other
+    pub snapshot: bool,
+    /// Shard target collections like their sources before loading them
+    pub sharding: Sharding,
+}
+
+impl Default for CloneOptions {
+    fn default() -> Self {
+        Self {
+            partitions: 4,
+            partition_threshold: 1_000_000,
+            max_collections: 4,
+            max_batches: 8,
+            read_limit: RateLimit::unlimited(),
+            write_limit: RateLimit::unlimited(),
+            retry: RetryPolicy::default(),
+            snapshot: false,
+            sharding: Sharding::Off,
+        }
+    }
+}
+
+/// Copies collections from one endpoint to another
+pub struct CloneJob {
+    /// Where collections are read from
+    source: Arc<dyn Endpoint>,
+    /// Where collections are written to
+    target: Arc<dyn Endpoint>,
+    /// The collections to copy, and what to call them in `target`
+    collections: Vec<(Namespace, Namespace)>,
+    /// GridFS buckets to only copy some of the files of
+    file_filters: Vec<FileFilter>,
+    /// How to go about copying
+    options: CloneOptions,
+    /// Slows the copy down, shared with whoever wants to change the limits while it runs
+    throttle: Arc<Throttle>,
+    /// Where to send progress events, if anyone is listening
+    events: Option<mpsc::UnboundedSender<ProgressEvent>>,
+}
+
+/// Builds up a [`CloneJob`]
+pub struct CloneJobBuilder {
+    job: CloneJob,
+}
+
+impl CloneJobBuilder {
+    /// Copy the collection at `source` to `target`
+    pub fn collection(mut self, source: Namespace, target: Namespace) -> Self {
+        self.job.collections.push((source, target));
+        self
+    }
+
+    /// Copy every `(source, target)` pair of collections
+    pub fn collections(
+        mut self,
+        collections: impl IntoIterator<Item = (Namespace, Namespace)>,
+    ) -> Self {
+        self.job.collections.extend(collections);
+        self
+    }
+
+    /// Only copy the files in a GridFS bucket matching a filter, along with their chunks. The
+    /// bucket's files collection still has to be one of the collections copied.
+    pub fn file_filter(mut self, filter: FileFilter) -> Self {
+        self.job.file_filters.push(filter);
+        self
+    }
+
+    /// See [`CloneJobBuilder::file_filter`]
+    pub fn file_filters(mut self, filters: impl IntoIterator<Item = FileFilter>) -> Self {
+        self.job.file_filters.extend(filters);
+        self
+    }
+
+    /// Replaces all the options at once
+    pub fn options(mut self, options: CloneOptions) -> Self {
+        self.job.options = options;
+        self
+    }
+
+    /// See [`CloneOptions::partitions`]
+    pub fn partitions(mut self, partitions: usize) -> Self {
+        self.job.options.partitions = partitions;
+        self
+    }
+
+    /// See [`CloneOptions::partition_threshold`]
+    pub fn partition_threshold(mut self, documents: u64) -> Self {
+        self.job.options.partition_threshold = documents;
+        self
+    }
+
+    /// See [`CloneOptions::max_collections`]
+    pub fn max_collections(mut self, collections: usize) -> Self {
+        self.job.options.max_collections = collections;
+        self
+    }
+
+    /// See [`CloneOptions::max_batches`]
+    pub fn max_batches(mut self, batches: usize) -> Self {
+        self.job.options.max_batches = batches;
+        self
+    }
+
+    /// See [`CloneOptions::read_limit`]
+    pub fn read_limit(mut self, limit: RateLimit) -> Self {
+        self.job.options.read_limit = limit;
+        self
+    }
+
+    /// See [`CloneOptions::write_limit`]
+    pub fn write_limit(mut self, limit: RateLimit) -> Self {
+        self.job.options.write_limit = limit;
+        self
+    }
+
+    /// See [`CloneOptions::retry`]
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.job.options.retry = policy;
+        self
+    }
+
+    /// See [`CloneOptions::snapshot`]
+    pub fn snapshot(mut self, snapshot: bool) -> Self {
+        self.job.options.snapshot = snapshot;
+        self
+    }
+
+    /// See [`CloneOptions::sharding`]
+    pub fn sharding(mut self, sharding: Sharding) -> Self {
+        self.job.options.sharding = sharding;
+        self
+    }
+
+    pub fn build(mut self) -> CloneJob {
+        // The chunks of filtered files are found from the files, so they go with the files even
+        // if they weren't asked for
+        let job = &mut self.job;
+        for FileFilter { bucket, .. } in &job.file_filters {
+            let files = gridfs::files(bucket);
+            let chunks = gridfs::chunks(bucket);
+            if job.collections.iter().any(|(source, _)| *source == chunks) {
+                continue;
+            }
+            if let Some((_, target)) = job.collections.iter().find(|(source, _)| *source == files) {
+                let bucket = target
+                    .collection
+                    .strip_suffix(gridfs::FILES)
+                    .unwrap_or(&target.collection);
+                let target = gridfs::chunks(&Namespace::new(&target.db, bucket));
+                job.collections.push((chunks, target));
+            }
+        }
+
+        self.job.throttle = Arc::new(Throttle::new(
+            self.job.options.read_limit,
+            self.job.options.write_limit,
+        ));
+        self.job
+    }
+}
+
+impl CloneJob {
+    pub fn builder(source: Arc<dyn Endpoint>, target: Arc<dyn Endpoint>) -> CloneJobBuilder {
+        CloneJobBuilder {
+            job: Self {
+                source,
+                target,
+                collections: vec![],
+                file_filters: vec![],
+                options: CloneOptions::default(),
+                throttle: Arc::new(Throttle::new(
+                    RateLimit::unlimited(),
+                    RateLimit::unlimited(),
+                )),
+                events: None,
+            },
+        }
+    }
+
+    /// The collections that will be copied, and what they will be called in the target
+    pub fn collections(&self) -> &[(Namespace, Namespace)] {
+        &self.collections
+    }
+
+    /// The throttle limiting how fast the job copies, which can be changed while it runs
+    pub fn throttle(&self) -> Arc<Throttle> {
+        self.throttle.clone()
+    }
+
+    /// Gets the events sent while the job runs, which ends when the job finishes
+    pub fn subscribe(&mut self) -> ProgressStream {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.events = Some(tx);
+        stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|event| (event, rx))
+        })
+        .boxed()
+    }
+
+    /// Copies every collection, reporting how each one went
+    pub async fn run(self) -> CloneReport {
+        let start = Instant::now();
+        info!("Cloning {} collections", self.collections.len());
+
+        // Size everything up first so progress can be shown against the whole job
+        let source = &self.source;
+        let stats = future::join_all(self.collections.iter().map(|(source_ns, _)| async move {
+            source
+                .collection_stats(source_ns)
+                .await
+                .unwrap_or_else(|ex| {
+                    debug!("Couldn't get stats for {source_ns}: {ex}");
+                    CollectionStats::default()
+                })
+        }))
+        .await;
+        send(
+            &self.events,
+            ProgressEvent::JobStarted {
+                collections: self
+                    .collections
+                    .iter()
+                    .zip(&stats)
+                    .map(|((source, target), stats)| (source.clone(), target.clone(), *stats))
+                    .collect(),
+            },
+        );
+
+        // Pin everything to the same moment before any of it is copied
+        let snapshot = if self.options.snapshot && !self.collections.is_empty() {
+            match self.snapshot().await {
+                Ok(snapshot) => Some(snapshot),
+                Err(ex) => {
+                    let collections = self
+                        .collections
+                        .into_iter()
+                        .map(|(source, target)| {
+                            let report = CollectionReport {
+                                source,
+                                target,
+                                documents: 0,
+                                bytes: 0,
+                                result: Err(ex.clone()),
+                                retries: vec![],
+                                elapsed: Duration::ZERO,
+                            };
+                            send(
+                                &self.events,
+                                ProgressEvent::CollectionFinished(report.clone()),
+                            );
+                            report
+                        })
+                        .collect();
+                    send(&self.events, ProgressEvent::JobFinished);
+                    return CloneReport {
+                        collections,
+                        snapshot: None,
+                        elapsed: start.elapsed(),
+                    };
+                }
+            }
+        } else {
+            None
+        };
+        let reader = match &snapshot {
+            Some(Snapshot::Frozen { endpoint, .. }) => endpoint.clone(),
+            _ => self.source.clone(),
+        };
+
+        let copier = Copier {
+            source: reader,
+            target: self.target,
+            collections: Arc::new(Semaphore::new(self.options.max_collections.max(1))),
+            batches: Arc::new(Semaphore::new(self.options.max_batches.max(1))),
+            options: Arc::new(self.options),
+            throttle: self.throttle,
+            events: self.events,
+        };
+        let collections = self.collections.into_iter().enumerate().collect::<Vec<_>>();
+        let find = |ns: &Namespace| {
+            collections
+                .iter()
+                .find(|(_, (source, _))| source == ns)
+                .cloned()
+        };
+        // Filtered files are copied along with their chunks, in the same task
+        let filters = self
+            .file_filters
+            .into_iter()
+            .filter(|FileFilter { bucket, .. }| {
+                find(&gridfs::files(bucket)).is_some() && find(&gridfs::chunks(bucket)).is_some()
+            })
+            .map(|FileFilter { bucket, filter }| (bucket, filter))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut tasks = vec![];
+        for ((index, (source_ns, target_ns)), stats) in collections.iter().cloned().zip(stats) {
+            let bucket = gridfs::bucket_of_files(&source_ns);
+            match bucket.and_then(|bucket| filters.get_key_value(&bucket)) {
+                Some((bucket, filter)) => {
+                    let (chunks_index, (chunks_source, chunks_target)) =
+                        find(&gridfs::chunks(bucket)).expect("Filtered buckets have chunks");
+                    tasks.push(tokio::spawn(copier.clone().copy_bucket(
+                        (index, source_ns, target_ns),
+                        (chunks_index, chunks_source, chunks_target),
+                        filter.clone(),
+                    )));
+                }
+                None if filters
+                    .keys()
+                    .any(|bucket| gridfs::chunks(bucket) == source_ns) => {}
+                None => {
+                    let copier = copier.clone();
+                    tasks.push(tokio::spawn(async move {
+                        vec![(
+                            index,
+                            copier.copy_collection(source_ns, target_ns, stats).await,
+                        )]
+                    }));
+                }
+            }
+        }
+
+        let mut collections = vec![];
+        for task in tasks {
+            collections.extend(task.await.expect("Collection clone panicked"));
+        }
+        collections.sort_by_key(|(index, _)| *index);
+        let collections = collections
+            .into_iter()
+            .map(|(_, report)| report)
+            .collect::<Vec<_>>();
+
+        let snapshot = match snapshot {
+            Some(Snapshot::Frozen { time, .. }) => Some(SnapshotReport {
+                time,
+                changes: 0,
+                retries: vec![],
+                result: Ok(()),
+            }),
+            Some(Snapshot::CatchUp { time }) => {
+                Some(copier.catch_up(&*self.source, &collections, time).await)
+            }
+            None => None,
+        };
+        send(&copier.events, ProgressEvent::JobFinished);
+
+        CloneReport {
+            collections,
+            snapshot,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    /// Picks the moment to copy everything as of, making sure the target can be caught up to it
+    /// if it has to be
+    async fn snapshot(&self) -> MongoResult<Snapshot> {
+        let sources = self
+            .collections
+            .iter()
+            .map(|(source, _)| source.clone())
+            .collect::<Vec<_>>();
+        let snapshot = self.source.snapshot(&sources).await?;
+
+        if matches!(snapshot, Snapshot::CatchUp { .. }) && !self.target.applies_changes() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "The source can't be read as of a past time, and the target can't have the \
+                 changes made while copying replayed on it",
+            )
+            .into());
+        }
+        Ok(snapshot)
+    }
+}
+
+/// What has been copied of a collection so far, shared between its partitions
+#[derive(Debug, Default)]
+struct Copied {
+    documents: AtomicU64,
+    bytes: AtomicU64,
+    retries: Mutex<Vec<Retry>>,
+    /// The `_id` of everything copied, only kept for GridFS files so their chunks can be found
+    ids: Option<Mutex<Vec<Bson>>>,
+}
+
+/// Which documents of a collection a copy reads
+#[derive(Debug, Clone)]
+enum Selection {
+    /// The documents with an `_id` in a range
+    Range(IdRange),
+    /// The documents matching a query
+    Matching(Document),
+}
+
+/// Everything the tasks copying collections need
+#[derive(Clone)]
+struct Copier {
+    source: Arc<dyn Endpoint>,
+    target: Arc<dyn Endpoint>,
+    /// Permits for copying a collection
+    collections: Arc<Semaphore>,
+    /// Permits for copying a batch
+    batches: Arc<Semaphore>,
+    options: Arc<CloneOptions>,
+    throttle: Arc<Throttle>,
+    events: Option<mpsc::UnboundedSender<ProgressEvent>>,
+}
+
+impl Copier {
+    /// Copies a collection, splitting it up and copying the parts at the same time if it's big
+    async fn copy_collection(
+        self,
+        source_ns: Namespace,
+        target_ns: Namespace,
+        stats: CollectionStats,
+    ) -> CollectionReport {
+        let _permit = self
+            .collections
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("Collection semaphore closed");
+
+        self.started(&source_ns, &target_ns);
+        let start = Instant::now();
+        let copied = Arc::new(Copied::default());
+        let result = match self.shard(&source_ns, &target_ns, &copied).await {
+            Ok(()) => {
+                self.copy_partitions(&source_ns, &target_ns, stats, &copied)
+                    .await
+            }
+            Err(ex) => Err(ex),
+        };
+        self.finished(source_ns, target_ns, start, &copied, result)
+    }
+
+    /// Copies the files in a GridFS bucket matching `filter`, then the chunks of just those
+    /// files, giving back the `(index, report)` of each
+    async fn copy_bucket(
+        self,
+        (files_index, files_source, files_target): (usize, Namespace, Namespace),
+        (chunks_index, chunks_source, chunks_target): (usize, Namespace, Namespace),
+        filter: Document,
+    ) -> Vec<(usize, CollectionReport)> {
+        let _permit = self
+            .collections
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("Collection semaphore closed");
+
+        self.started(&files_source, &files_target);
+        let start = Instant::now();
+        let copied = Arc::new(Copied {
+            ids: Some(Mutex::default()),
+            ..Copied::default()
+        });
+        let result = match self.shard(&files_source, &files_target, &copied).await {
+            Ok(()) => {
+                self.clone()
+                    .copy_range(
+                        files_source.clone(),
+                        files_target.clone(),
+                        Selection::Matching(filter),
+                        copied.clone(),
+                    )
+                    .await
+            }
+            Err(ex) => Err(ex),
+        };
+        let ids = copied
+            .ids
+            .as_ref()
+            .map(|ids| ids.lock().unwrap().clone())
+            .unwrap_or_default();
+        let files = self.finished(files_source, files_target, start, &copied, result);
+
+        self.started(&chunks_source, &chunks_target);
+        let start = Instant::now();
+        let copied = Arc::new(Copied::default());
+        let result = match &files.result {
+            Ok(()) => {
+                let mut result = self.shard(&chunks_source, &chunks_target, &copied).await;
+                for ids in ids.chunks(gridfs::FILES_PER_QUERY) {
+                    if result.is_err() {
+                        break;
+                    }
+                    result = self
+                        .clone()
+                        .copy_range(
+                            chunks_source.clone(),
+                            chunks_target.clone(),
+                            Selection::Matching(gridfs::chunks_filter(ids)),
+                            copied.clone(),
+                        )
+                        .await;
+                }
+                result
+            }
+            Err(_) => Err(io::Error::other(
+                "The files failed to copy, so their chunks weren't copied either",
+            )
+            .into()),
+        };
+        let chunks = self.finished(chunks_source, chunks_target, start, &copied, result);
+
+        vec![(files_index, files), (chunks_index, chunks)]
+    }
+
+    /// Shards the target collection like the source one if that's been asked for and the source
+    /// is sharded, unless the target already is
+    async fn shard(
+        &self,
+        source_ns: &Namespace,
+        target_ns: &Namespace,
+        copied: &Copied,
+    ) -> MongoResult<()> {
+        if self.options.sharding == Sharding::Off {
+            return Ok(());
+        }
+        let splits = self.options.sharding == Sharding::Split;
+        let layout = retry::retry(
+            &self.options.retry,
+            |retry| self.retried(source_ns, target_ns, copied, retry),
+            |_| self.source.shard_layout(source_ns, splits),
+        )
+        .await?;
+        let Some(layout) = layout else {
+            return Ok(());
+        };
+
+        let existing = retry::retry(
+            &self.options.retry,
+            |retry| self.retried(source_ns, target_ns, copied, retry),
+            |_| self.target.shard_layout(target_ns, false),
+        )
+        .await?;
+        if let Some(existing) = existing {
+            debug!("{target_ns} is already sharded on {}", existing.key);
+            return Ok(());
+        }
+
+        debug!(
+            "Sharding {target_ns} on {} with {} splits and {} zones",
+            layout.key,
+            layout.split_points.len(),
+            layout.zones.len()
+        );
+        self.target.shard(target_ns, &layout).await
+    }
+
+    /// Lets everyone know a collection has started being copied
+    fn started(&self, source_ns: &Namespace, target_ns: &Namespace) {
+        send(
+            &self.events,
+            ProgressEvent::CollectionStarted {
+                source: source_ns.clone(),
+                target: target_ns.clone(),
+            },
+        );
+        debug!("Cloning collection {source_ns} -> {target_ns}");
+    }
+
+    /// Reports how copying a collection went, and lets everyone know it's finished
+    fn finished(
+        &self,
+        source_ns: Namespace,
+        target_ns: Namespace,
+        start: Instant,
+        copied: &Copied,
+        result: MongoResult<()>,
+    ) -> CollectionReport {
+        debug!("Done upload for {target_ns}");
+        let report = CollectionReport {
+            source: source_ns,
+            target: target_ns,
+            documents: copied.documents.load(Ordering::Relaxed),
+            bytes: copied.bytes.load(Ordering::Relaxed),
+            result,
+            retries: copied.retries.lock().unwrap().clone(),
+            elapsed: start.elapsed(),
+        };
+        send(
+            &self.events,
+            ProgressEvent::CollectionFinished(report.clone()),
+        );
+        report
+    }
+
+    /// Works out how to split up a collection, then copies each part in its own task
+    async fn copy_partitions(
+        &self,
+        source_ns: &Namespace,
+        target_ns: &Namespace,
+        stats: CollectionStats,
+        copied: &Arc<Copied>,
+    ) -> MongoResult<()> {
+        // Even collections copied in one go are asked for, so they're read in `_id` order if they
+        // can be and carry on from the last `_id` after a failure
+        let count =
+            if self.options.partitions > 1 && stats.documents >= self.options.partition_threshold {
+                self.options.partitions
+            } else {
+                1
+            };
+        let ranges = retry::retry(
+            &self.options.retry,
+            |retry| self.retried(source_ns, target_ns, copied, retry),
+            |_| self.source.partition(source_ns, count),
+        )
+        .await?;
+
+        let tasks = ranges
+            .into_iter()
+            .map(|range| {
+                tokio::spawn(self.clone().copy_range(
+                    source_ns.clone(),
+                    target_ns.clone(),
+                    Selection::Range(range),
+                    copied.clone(),
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        // Wait for every part, even after one fails, so nothing is still writing once the
+        // collection is reported as finished
+        let mut result = Ok(());
+        for task in tasks {
+            let partition = task.await.expect("Partition clone panicked");
+            if result.is_ok() {
+                result = partition;
+            }
+        }
+        result
+    }
+
+    /// Streams part of a collection from the source into the target a batch at a time
+    async fn copy_range(
+        self,
+        source_ns: Namespace,
+        target_ns: Namespace,
+        selection: Selection,
+        copied: Arc<Copied>,
+    ) -> MongoResult<()> {
+        let policy = self.options.retry;
+        // Where reading can carry on from after a failure
+        let mut last_id = None;
+        let mut read_attempt = 1;
+        // Whether reading has started again after failing, after which documents already written
+        // can come round again if they're read again rather than carried on after
+        let mut reread = false;
+        let mut batches = self
+            .read(&source_ns, &target_ns, &selection, None, &copied)
+            .await?;
+
+        loop {
+            let _permit = self
+                .batches
+                .acquire()
+                .await
+                .expect("Batch semaphore closed");
+            // Reads are paid for after they come in, so the next one waits for the last
+            self.throttle.before_read().await;
+            let batch = match batches.next().await {
+                Some(batch) => batch.into_iter().collect::<MongoResult<Vec<_>>>(),
+                None => break,
+            };
+            let batch = match batch {
+                Ok(batch) => {
+                    read_attempt = 1;
+                    batch
+                }
+                // The cursor is gone, so start reading again from after the last batch written
+                Err(error) if read_attempt < policy.max_attempts && retry::is_transient(&error) => {
+                    let backoff = policy.backoff(read_attempt);
+                    warn!("Reading {source_ns} failed, trying again in {backoff:?}: {error}");
+                    self.retried(
+                        &source_ns,
+                        &target_ns,
+                        &copied,
+                        Retry {
+                            attempt: read_attempt,
+                            error,
+                            backoff,
+                        },
+                    );
+                    tokio::time::sleep(backoff).await;
+                    read_attempt += 1;
+                    reread = true;
+                    batches = self
+                        .read(&source_ns, &target_ns, &selection, last_id.clone(), &copied)
+                        .await?;
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            let documents = batch.len() as u64;
+            let bytes = batch_bytes(&batch)?;
+
+            self.throttle.read(documents, bytes);
+            self.throttle.write(documents, bytes).await;
+            let target = &self.target;
+            // Documents already there from before reading started again were counted then
+            let duplicates = retry::retry(
+                &policy,
+                |retry| self.retried(&source_ns, &target_ns, &copied, retry),
+                |attempt| {
+                    let batch = batch.clone();
+                    let target_ns = &target_ns;
+                    async move {
+                        match target.write_batch(target_ns, batch).await {
+                            // Whatever made it in on an earlier try, or before reading started
+                            // again, is already there
+                            Err(ex)
+                                if (attempt > 1 || reread) && retry::is_duplicate_key_only(&ex) =>
+                            {
+                                Ok(if reread {
+                                    retry::duplicate_keys(&ex)
+                                } else {
+                                    0
+                                })
+                            }
+                            result => result.map(|_| 0),
+                        }
+                    }
+                },
+            )
+            .await?;
+            let (documents, bytes) = match duplicates.min(documents) {
+                0 => (documents, bytes),
+                duplicates => (
+                    documents - duplicates,
+                    bytes * (documents - duplicates) / documents,
+                ),
+            };
+
+            if let Some(ids) = &copied.ids {
+                let mut ids = ids.lock().unwrap();
+                ids.extend(
+                    batch
+                        .iter()
+                        .filter_map(|document| document.get("_id").cloned()),
+                );
+            }
+            last_id = batch
+                .last()
+                .and_then(|document| document.get("_id"))
+                .cloned();
+            send(
+                &self.events,
+                ProgressEvent::CollectionProgress {
+                    source: source_ns.clone(),
+                    target: target_ns.clone(),
+                    documents: copied.documents.fetch_add(documents, Ordering::Relaxed) + documents,
+                    bytes: copied.bytes.fetch_add(bytes, Ordering::Relaxed) + bytes,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Starts reading `selection` in batches, carrying on after `last_id` if some have already
+    /// been copied and the order allows it
+    async fn read(
+        &self,
+        source_ns: &Namespace,
+        target_ns: &Namespace,
+        selection: &Selection,
+        last_id: Option<Bson>,
+        copied: &Copied,
+    ) -> MongoResult<Chunks<DocumentStream>> {
+        let documents = match selection {
+            // Parts of a collection, and whole ones with one type of `_id`, come back in `_id`
+            // order, so they can carry on after the last `_id`. Otherwise the `_id`s are a mix of
+            // types, which can't be compared in a query, so the collection is read again from the
+            // start. Natural order can change between reads, so nothing is skipped, and what's
+            // already in the target is left out when it's written.
+            Selection::Range(range) => {
+                let range = match last_id {
+                    Some(id) if !range.is_all() => IdRange {
+                        start: Bound::Excluded(id),
+                        end: range.end.clone(),
+                    },
+                    _ => range.clone(),
+                };
+                retry::retry(
+                    &self.options.retry,
+                    |retry| self.retried(source_ns, target_ns, copied, retry),
+                    |_| self.source.read_range(source_ns, &range),
+                )
+                .await?
+            }
+            // Queries are run again from the start for the same reason
+            Selection::Matching(filter) => {
+                retry::retry(
+                    &self.options.retry,
+                    |retry| self.retried(source_ns, target_ns, copied, retry),
+                    |_| self.source.read_matching(source_ns, filter),
+                )
+                .await?
+            }
+        };
+
+        Ok(documents.chunks(BATCH_SIZE))
+    }
+
+    /// Replays the changes made to the source while copying on the target, so every collection
+    /// ends up as of the same moment
+    async fn catch_up(
+        &self,
+        source: &dyn Endpoint,
+        collections: &[CollectionReport],
+        since: Timestamp,
+    ) -> SnapshotReport {
+        // Only the collections that were copied are worth catching up
+        let targets = collections
+            .iter()
+            .filter(|collection| collection.result.is_ok())
+            .map(|collection| (collection.source.clone(), collection.target.clone()))
+            .collect::<BTreeMap<_, _>>();
+
+        info!("Catching up on changes since {since}");
+        send(&self.events, ProgressEvent::CatchingUp { changes: 0 });
+        let mut changes = 0;
+        let retries = Mutex::new(vec![]);
+        let result = self
+            .replay(source, &targets, since, &mut changes, &retries)
+            .await;
+        let retries = retries.into_inner().unwrap();
+        match result {
+            Ok(until) => SnapshotReport {
+                time: until,
+                changes,
+                retries,
+                result: Ok(()),
+            },
+            Err(ex) => SnapshotReport {
+                time: since,
+                changes,
+                retries,
+                result: Err(ex),
+            },
+        }
+    }
+
+    /// Applies the changes to the `targets`' sources since `since` to the targets, counting them
+    /// in `applied`, and gives back the moment they're caught up to. If the changes stop coming
+    /// part way, they're got again from the last one applied, which is applied again along with
+    /// any made at the same moment, as replaying a change twice leaves the same result.
+    async fn replay(
+        &self,
+        source: &dyn Endpoint,
+        targets: &BTreeMap<Namespace, Namespace>,
+        since: Timestamp,
+        applied: &mut u64,
+        retries: &Mutex<Vec<Retry>>,
+    ) -> MongoResult<Timestamp> {
+        let policy = self.options.retry;
+        let sources = targets.keys().cloned().collect::<Vec<_>>();
+        let mut from = since;
+        let mut read_attempt = 1;
+
+        let until = 'changes: loop {
+            let Changes { until, mut stream } = retry::retry(
+                &policy,
+                |retry| self.catch_up_retried(retries, retry),
+                |_| source.changes(&sources, from),
+            )
+            .await?;
+
+            while let Some(change) = stream.next().await {
+                let (time, source_ns, change) = match change {
+                    Ok(change) => {
+                        read_attempt = 1;
+                        change
+                    }
+                    Err(error)
+                        if read_attempt < policy.max_attempts && retry::is_transient(&error) =>
+                    {
+                        let backoff = policy.backoff(read_attempt);
+                        warn!("Getting changes failed, trying again in {backoff:?}: {error}");
+                        self.catch_up_retried(
+                            retries,
+                            Retry {
+                                attempt: read_attempt,
+                                error,
+                                backoff,
+                            },
+                        );
+                        tokio::time::sleep(backoff).await;
+                        read_attempt += 1;
+                        continue 'changes;
+                    }
+                    Err(error) => return Err(error),
+                };
+                let Some(target_ns) = targets.get(&source_ns) else {
+                    continue;
+                };
+
+                self.throttle.write(1, 0).await;
+                retry::retry(
+                    &policy,
+                    |retry| self.catch_up_retried(retries, retry),
+                    |_| self.target.apply_change(target_ns, change.clone()),
+                )
+                .await?;
+
+                from = time;
+                *applied += 1;
+                if applied.is_multiple_of(BATCH_SIZE as u64) {
+                    send(
+                        &self.events,
+                        ProgressEvent::CatchingUp { changes: *applied },
+                    );
+                }
+            }
+            break until;
+        };
+
+        debug!("Caught up to {until} with {applied} changes");
+        Ok(until)
+    }
+
+    /// Keeps track of a transient failure catching up that's being tried again
+    fn catch_up_retried(&self, retries: &Mutex<Vec<Retry>>, retry: Retry) {
+        send(
+            &self.events,
+            ProgressEvent::CatchUpRetrying {
+                retry: retry.clone(),
+            },
+        );
+        retries.lock().unwrap().push(retry);
+    }
+
+    /// Keeps track of a transient failure that's being tried again
+    fn retried(&self, source_ns: &Namespace, target_ns: &Namespace, copied: &Copied, retry: Retry) {
+        send(
+            &self.events,
+            ProgressEvent::CollectionRetrying {
+                source: source_ns.clone(),
+                target: target_ns.clone(),
+                retry: retry.clone(),
+            },
+        );
+        copied.retries.lock().unwrap().push(retry);
+    }
+}
+
+/// The size of a batch of documents as BSON
+fn batch_bytes(batch: &[Document]) -> MongoResult<u64> {
+    let mut bytes = 0;
+    for document in batch {
+        bytes += mongodb::bson::to_vec(document)?.len() as u64;
+    }
+    Ok(bytes)
+}
+
+/// Sends an event if anyone is listening, it doesn't matter if they've stopped
+fn send(events: &Option<mpsc::UnboundedSender<ProgressEvent>>, event: ProgressEvent) {
+    if let Some(events) = events {
+        let _ = events.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::{memory::MemoryStore, Change};
+    use async_trait::async_trait;
+    use mongodb::bson::doc;
+    use std::sync::atomic::AtomicUsize;
+
+    /// A [`MemoryStore`] that loses its connection a set number of times, and keeps track of
+    /// what's read from it
+    #[derive(Default)]
+    struct Flaky {
+        store: MemoryStore,
+        /// How many more reads fail part way through
+        read_failures: AtomicUsize,
+        /// How many documents are read before a read fails
+        read_failure_after: usize,
+        /// How many more writes fail after the documents are written, as if the reply was lost
+        write_failures: AtomicUsize,
+        /// Whether reads after the first come back in the opposite order, like natural order can
+        /// when documents move around
+        reorders: bool,
+        /// Every range read
+        ranges: Mutex<Vec<IdRange>>,
+        /// Changes made to the collection while it's copied, for a snapshot clone to catch up on
+        changes: Vec<(Timestamp, Change)>,
+        /// How many more times getting changes fails part way through
+        change_failures: AtomicUsize,
+        /// How many changes are got before getting them fails
+        change_failure_after: usize,
+        /// Every time changes were got since
+        changes_since: Mutex<Vec<Timestamp>>,
+    }
+
+    impl Flaky {
+        /// Whether the next read or write fails, counting it off if it does
+        fn fails(failures: &AtomicUsize) -> bool {
+            failures
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| x.checked_sub(1))
+                .is_ok()
+        }
+
+        fn reset() -> mongodb::error::Error {
+            io::Error::from(io::ErrorKind::ConnectionReset).into()
+        }
+    }
+
+    #[async_trait]
+    impl Endpoint for Flaky {
+        async fn list_namespaces(&self) -> MongoResult<Vec<Namespace>> {
+            self.store.list_namespaces().await
+        }
+
+        async fn collection_stats(&self, ns: &Namespace) -> MongoResult<CollectionStats> {
+            self.store.collection_stats(ns).await
+        }
+
+        async fn read_collection(&self, ns: &Namespace) -> MongoResult<DocumentStream> {
+            self.read_range(ns, &IdRange::all()).await
+        }
+
+        async fn partition(&self, ns: &Namespace, count: usize) -> MongoResult<Vec<IdRange>> {
+            self.store.partition(ns, count).await
+        }
+
+        async fn read_range(&self, ns: &Namespace, range: &IdRange) -> MongoResult<DocumentStream> {
+            let reread = {
+                let mut ranges = self.ranges.lock().unwrap();
+                ranges.push(range.clone());
+                ranges.len() > 1
+            };
+            let mut documents = self.store.read_range(ns, range).await?;
+            if self.reorders && reread {
+                let mut reversed = documents.collect::<Vec<_>>().await;
+                reversed.reverse();
+                documents = stream::iter(reversed).boxed();
+            }
+            if Self::fails(&self.read_failures) {
+                Ok(documents
+                    .take(self.read_failure_after)
+                    .chain(stream::once(async { Err(Self::reset()) }))
+                    .boxed())
+            } else {
+                Ok(documents)
+            }
+        }
+
+        async fn write_batch(&self, ns: &Namespace, documents: Vec<Document>) -> MongoResult<()> {
+            self.store.write_batch(ns, documents).await?;
+            if Self::fails(&self.write_failures) {
+                Err(Self::reset())
+            } else {
+                Ok(())
+            }
+        }
+
+        async fn snapshot(&self, _namespaces: &[Namespace]) -> MongoResult<Snapshot> {
+            Ok(Snapshot::CatchUp {
+                time: Timestamp {
+                    time: 1,
+                    increment: 0,
+                },
+            })
+        }
+
+        async fn changes(
+            &self,
+            _namespaces: &[Namespace],
+            since: Timestamp,
+        ) -> MongoResult<Changes> {
+            self.changes_since.lock().unwrap().push(since);
+            let changes = self
+                .changes
+                .iter()
+                .filter(|(time, _)| *time >= since)
+                .map(|(time, change)| Ok((*time, ns(), change.clone())))
+                .collect::<Vec<_>>();
+            let until = self.changes.last().map_or(since, |(time, _)| *time);
+            let stream = if Self::fails(&self.change_failures) {
+                stream::iter(changes.into_iter().take(self.change_failure_after))
+                    .chain(stream::once(async { Err(Self::reset()) }))
+                    .boxed()
+            } else {
+                stream::iter(changes).boxed()
+            };
+            Ok(Changes { until, stream })
+        }
+
+        fn applies_changes(&self) -> bool {
+            true
+        }
+
+        async fn apply_change(&self, ns: &Namespace, change: Change) -> MongoResult<()> {
+            self.store.apply_change(ns, change).await
+        }
+    }
+
+    fn ns() -> Namespace {
+        Namespace::new("db", "collection")
+    }
+
+    /// A source with a document for each of `ids`, added in reverse `_id` order so reading in `_id` order
+    /// isn't the same as reading in natural order
+    fn source(ids: impl DoubleEndedIterator<Item = Bson>) -> Flaky {
+        let source = Flaky::default();
+        source.store.insert(
+            ns(),
+            ids.rev().map(|id| doc! { "_id": id.clone(), "value": id }),
+        );
+        source
+    }
+
+    async fn clone(source: &Arc<Flaky>, target: &Arc<Flaky>, partitions: usize) -> CloneReport {
+        CloneJob::builder(source.clone(), target.clone())
+            .collection(ns(), ns())
+            .partitions(partitions)
+            .partition_threshold(0)
+            .retry(RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::ZERO,
+                max_backoff: Duration::ZERO,
+            })
+            .build()
+            .run()
+            .await
+    }
+
+    /// Asserts the target has exactly the documents in the source, in any order
+    fn assert_copied(source: &Flaky, target: &Flaky) {
+        let sorted = |store: &MemoryStore| {
+            let mut documents = store.documents(&ns()).unwrap_or_default();
+            documents.sort_by_key(|document| document.to_string());
+            documents
+        };
+        assert_eq!(sorted(&target.store), sorted(&source.store));
+    }
+
+    #[tokio::test]
+    async fn copies_large_collections_in_ranges() {
+        let source = Arc::new(source((0..2500).map(Bson::Int32)));
+        let target = Arc::new(Flaky::default());
+
+        let report = clone(&source, &target, 4).await;
+        assert!(report.succeeded(), "{report:?}");
+        assert_eq!(report.documents(), 2500);
+        assert_copied(&source, &target);
+
+        let ranges = source.ranges.lock().unwrap().clone();
+        assert_eq!(ranges.len(), 4);
+        assert_eq!(ranges[0].start, Bound::Unbounded);
+        assert_eq!(ranges[3].end, Bound::Unbounded);
+        for pair in ranges.windows(2) {
+            match (&pair[0].end, &pair[1].start) {
+                (Bound::Excluded(end), Bound::Included(start)) => assert_eq!(end, start),
+                bounds => panic!("Ranges don't meet: {bounds:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn carries_on_after_a_failed_read() {
+        // Read in `_id` order and carried on after the last `_id`, or read again from the start if
+        // the `_id`s can't be compared
+        let ordered = (0..2500).map(Bson::Int32).collect::<Vec<_>>();
+        let mixed = (0..2500)
+            .map(|i| match i % 2 {
+                0 => Bson::Int32(i),
+                _ => Bson::String(i.to_string()),
+            })
+            .collect::<Vec<_>>();
+
+        for (ids, first_range) in [(ordered, IdRange::ordered()), (mixed, IdRange::all())] {
+            let mut source = source(ids.into_iter());
+            source.read_failures.store(1, Ordering::Relaxed);
+            source.read_failure_after = 1500;
+            let source = Arc::new(source);
+            let target = Arc::new(Flaky::default());
+
+            let report = clone(&source, &target, 1).await;
+            assert!(report.succeeded(), "{report:?}");
+            assert_eq!(report.retries(), 1);
+            assert_eq!(report.documents(), 2500);
+            assert_copied(&source, &target);
+            assert_eq!(source.ranges.lock().unwrap()[0], first_range);
+        }
+    }
+
+    #[tokio::test]
+    async fn ignores_duplicates_from_writes_that_went_in() {
+        let source = Arc::new(source((0..2500).map(Bson::Int32)));
+        let target = Arc::new(Flaky::default());
+        target.write_failures.store(1, Ordering::Relaxed);
+
+        let report = clone(&source, &target, 1).await;
+        assert!(report.succeeded(), "{report:?}");
+        assert_eq!(report.retries(), 1);
+        assert_eq!(report.documents(), 2500);
+        assert_copied(&source, &target);
+    }
+
+    #[tokio::test]
+    async fn reads_again_from_the_start_when_the_order_changes() {
+        // Mixed `_id`s are read in natural order, which isn't the same when read again
+        let ids = (0..2500).map(|i| match i % 2 {
+            0 => Bson::Int32(i),
+            _ => Bson::String(i.to_string()),
+        });
+        let mut source = source(ids);
+        source.read_failures.store(1, Ordering::Relaxed);
+        source.read_failure_after = 1500;
+        source.reorders = true;
+        let source = Arc::new(source);
+        let target = Arc::new(Flaky::default());
+
+        let report = clone(&source, &target, 1).await;
+        assert!(report.succeeded(), "{report:?}");
+        assert_eq!(report.retries(), 1);
+        assert_eq!(report.documents(), 2500);
+        assert_copied(&source, &target);
+        assert_eq!(
+            *source.ranges.lock().unwrap(),
+            [IdRange::all(), IdRange::all()]
+        );
+    }
+
+    #[tokio::test]
+    async fn catches_up_from_the_last_change_after_a_failure() {
+        let time = |time| Timestamp { time, increment: 0 };
+        let changes = vec![
+            (time(2), Change::Upsert(doc! { "_id": 100, "value": 100 })),
+            (
+                time(3),
+                Change::Update {
+                    id: Bson::Int32(1),
+                    set: doc! { "value": "changed" },
+                    unset: vec![],
+                },
+            ),
+            (time(4), Change::Delete(Bson::Int32(2))),
+            (
+                time(5),
+                Change::Upsert(doc! { "_id": 3, "value": "replaced" }),
+            ),
+        ];
+        let mut source = source((0..10).map(Bson::Int32));
+        source.changes = changes.clone();
+        source.change_failures.store(1, Ordering::Relaxed);
+        source.change_failure_after = 2;
+        let source = Arc::new(source);
+        let target = Arc::new(Flaky::default());
+
+        let report = CloneJob::builder(source.clone(), target.clone())
+            .collection(ns(), ns())
+            .snapshot(true)
+            .retry(RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::ZERO,
+                max_backoff: Duration::ZERO,
+            })
+            .build()
+            .run()
+            .await;
+        assert!(report.succeeded(), "{report:?}");
+        let snapshot = report.snapshot.as_ref().unwrap();
+        assert_eq!(snapshot.time, time(5));
+        assert_eq!(snapshot.retries.len(), 1);
+        assert_eq!(report.retries(), 1);
+        // The last change applied before failing is applied again
+        assert_eq!(snapshot.changes, 5);
+        assert_eq!(*source.changes_since.lock().unwrap(), [time(1), time(3)]);
+
+        let expected = Flaky::default();
+        expected
+            .store
+            .insert(ns(), source.store.documents(&ns()).unwrap());
+        for (_, change) in changes {
+            expected.store.apply_change(&ns(), change).await.unwrap();
+        }
+        assert_copied(&expected, &target);
+    }
+
+    #[tokio::test]
+    async fn fails_on_duplicates_already_in_the_target() {
+        let source = Arc::new(source((0..10).map(Bson::Int32)));
+        let target = Arc::new(Flaky::default());
+        target
+            .store
+            .insert(ns(), [doc! { "_id": 5, "value": "other" }]);
+
+        let report = clone(&source, &target, 1).await;
+        assert!(!report.succeeded());
+        assert!(retry::is_duplicate_key_only(
+            report.collections[0].result.as_ref().unwrap_err()
+        ));
+    }
+}
@@ -0,0 +1,42 @@
+//! The clone engine behind Mongo Cloner, usable without the GUI.
+//!
+//! ```no_run
+//! # async fn clone() -> mongodb::error::Result<()> {
+//! use mongo_cloner::{endpoint::Namespace, job::CloneJob};
+//!
+//! let source = "mongodb://localhost:27017".parse::<mongo_cloner::endpoint::EndpointConfig>()
+//!     .unwrap()
+//!     .connect()
+//!     .await?;
+//! let target = "bson:./backup".parse::<mongo_cloner::endpoint::EndpointConfig>()
+//!     .unwrap()
+//!     .connect()
+//!     .await?;
+//!
+//! let report = CloneJob::builder(source, target)
+//!     .collection(Namespace::new("app", "users"), Namespace::new("app_copy", "users"))
+//!     .build()
+//!     .run()
+//!     .await;
+//! println!("Copied {} documents", report.documents());
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod connection;
+pub mod db;
+pub mod diagnostics;
+pub mod endpoint;
+pub mod gridfs;
+pub mod job;
+pub mod plan;
+pub mod profiles;
+pub mod progress;
+pub mod rename;
+pub mod retry;
+pub mod secrets;
+pub mod selection;
+pub mod sharding;
+pub mod throttle;
+pub mod tunnel;
+pub mod users;
@@ -1,12 +1,10 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod app;
-mod db;
 mod widgets;
 
 use crate::app::MongoClonerApp;
 use eframe::{egui::Vec2, IconData};
-use tracing::metadata::LevelFilter;
 
 fn main() {
     // Setup logging
@@ -16,7 +14,7 @@ fn main() {
         .init();
     #[cfg(not(debug_assertions))]
     tracing_subscriber::fmt::fmt()
-        .with_max_level(LevelFilter::INFO)
+        .with_max_level(tracing::metadata::LevelFilter::INFO)
         .init();
 
     // Load the static included icon
@@ -0,0 +1,115 @@
+use crate::{
+    endpoint::{EndpointConfig, Namespace},
+    gridfs::FileFilter,
+    job::CloneOptions,
+    rename::RenameRule,
+    secrets,
+    selection::Selection,
+};
+use mongodb::error::Result as MongoResult;
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+
+/// Everything about a clone, so it can be saved and run again later
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClonePlan {
+    /// Where collections are read from
+    pub source: EndpointConfig,
+    /// Where collections are written to
+    pub target: EndpointConfig,
+    /// The collections to copy and what to call them in `target`. Empty means every collection
+    /// `selection` picks.
+    #[serde(default)]
+    pub collections: Vec<(Namespace, Namespace)>,
+    /// Patterns for which collections to copy, which leave out the system databases by default
+    #[serde(default)]
+    pub selection: Selection,
+    /// GridFS buckets to only copy some of the files of
+    #[serde(default)]
+    pub file_filters: Vec<FileFilter>,
+    /// The rules the collections were renamed with, so they can be edited again. `collections`
+    /// already has the new names.
+    #[serde(default)]
+    pub rename_rules: Vec<RenameRule>,
+    /// How to go about copying
+    #[serde(default)]
+    pub options: CloneOptions,
+}
+
+impl ClonePlan {
+    /// Reads a plan saved with [`ClonePlan::save`]
+    pub fn load(path: impl AsRef<Path>) -> MongoResult<Self> {
+        let json = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json).map_err(io::Error::from)?)
+    }
+
+    /// A copy without the source's and target's passwords, so it's safe to save or share. They
+    /// have to be filled in again once it's opened, unless they're [references](crate::secrets)
+    /// which are kept.
+    pub fn without_passwords(&self) -> Self {
+        let mut plan = self.clone();
+        for url in [&mut plan.source.url, &mut plan.target.url] {
+            if !url.password.as_deref().is_some_and(secrets::is_reference) {
+                url.password = None;
+            }
+        }
+        plan
+    }
+
+    /// The [references](crate::secrets) in the source's and target's credentials, which would be
+    /// looked up on this machine and sent to the servers in the plan. Plans can come from anyone,
+    /// so these have to be agreed to before connecting.
+    pub fn references(&self) -> Vec<String> {
+        [&self.source, &self.target]
+            .into_iter()
+            .flat_map(|endpoint| secrets::references(&endpoint.url))
+            .collect()
+    }
+
+    /// Writes the plan to `path` as JSON, passwords included. Use
+    /// [`ClonePlan::without_passwords`] first to leave them out.
+    pub fn save(&self, path: impl AsRef<Path>) -> MongoResult<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::from)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rename::{RuleKind, RuleScope};
+
+    #[test]
+    fn keeps_the_rename_rules() {
+        let plan = ClonePlan {
+            source: "mongodb://source".parse().unwrap(),
+            target: "mongodb://target".parse().unwrap(),
+            collections: vec![],
+            selection: Selection::default(),
+            file_filters: vec![],
+            rename_rules: vec![RenameRule {
+                scope: RuleScope::Collections,
+                kind: RuleKind::Glob,
+                pattern: "prod_*".into(),
+                template: "staging_*".into(),
+            }],
+            options: CloneOptions::default(),
+        };
+        let file = tempfile::NamedTempFile::new().unwrap();
+        plan.save(file.path()).unwrap();
+        assert_eq!(
+            ClonePlan::load(file.path()).unwrap().rename_rules,
+            plan.rename_rules
+        );
+
+        // Plans saved before there were rules have none
+        let mut json = serde_json::to_value(&plan).unwrap();
+        json.as_object_mut().unwrap().remove("rename_rules");
+        fs::write(file.path(), json.to_string()).unwrap();
+        assert!(ClonePlan::load(file.path())
+            .unwrap()
+            .rename_rules
+            .is_empty());
+    }
+}
@@ -0,0 +1,477 @@
+use crate::{
+    endpoint::{CollectionStats, Namespace},
+    job::ProgressEvent,
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// How far back throughput is measured over
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(5);
+
+/// Where a collection is up to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CollectionState {
+    /// Not started yet
+    Waiting,
+    /// Being copied
+    Running,
+    /// Copied
+    Done,
+    /// Failed to copy, with why
+    Failed(String),
+}
+
+/// How much of a collection has been copied
+#[derive(Debug, Clone)]
+pub struct CollectionProgress {
+    /// What the collection is being called in the target
+    pub target: Namespace,
+    /// How big the collection was when the job started
+    pub expected: CollectionStats,
+    /// The number of documents copied so far
+    pub documents: u64,
+    /// The number of bytes copied so far
+    pub bytes: u64,
+    /// The number of transient failures that were tried again
+    pub retries: usize,
+    /// Where it's up to
+    pub state: CollectionState,
+}
+
+impl CollectionProgress {
+    /// How much of the collection has been copied, between 0 and 1
+    pub fn fraction(&self) -> f32 {
+        match self.state {
+            CollectionState::Done => 1.0,
+            _ => fraction(self.documents, self.bytes, &self.expected),
+        }
+    }
+}
+
+/// How quickly documents are being copied
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Throughput {
+    pub documents_per_sec: f64,
+    pub bytes_per_sec: f64,
+}
+
+/// Keeps track of how far along a job is from its [`ProgressEvent`]s
+#[derive(Debug, Clone)]
+pub struct ProgressTracker {
+    /// Every collection in the job, by its source namespace
+    collections: BTreeMap<Namespace, CollectionProgress>,
+    /// When the job started
+    start: Instant,
+    /// Recent `(time, documents, bytes)` totals, for measuring throughput
+    samples: VecDeque<(Instant, u64, u64)>,
+    /// How many changes have been replayed, once a snapshot clone is catching up
+    catching_up: Option<u64>,
+    /// Whether the job has finished
+    finished: bool,
+}
+
+impl Default for ProgressTracker {
+    fn default() -> Self {
+        Self {
+            collections: BTreeMap::new(),
+            start: Instant::now(),
+            samples: VecDeque::new(),
+            catching_up: None,
+            finished: false,
+        }
+    }
+}
+
+impl ProgressTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the progress with something that happened in the job
+    pub fn update(&mut self, event: &ProgressEvent) {
+        match event {
+            ProgressEvent::JobStarted { collections } => {
+                self.start = Instant::now();
+                for (source, target, expected) in collections {
+                    self.collections.insert(
+                        source.clone(),
+                        CollectionProgress {
+                            target: target.clone(),
+                            expected: *expected,
+                            documents: 0,
+                            bytes: 0,
+                            retries: 0,
+                            state: CollectionState::Waiting,
+                        },
+                    );
+                }
+            }
+            ProgressEvent::CollectionStarted { source, .. } => {
+                if let Some(collection) = self.collections.get_mut(source) {
+                    collection.state = CollectionState::Running;
+                }
+            }
+            ProgressEvent::CollectionProgress {
+                source,
+                documents,
+                bytes,
+                ..
+            } => {
+                // Partitions of a collection report at the same time, so totals can arrive out of
+                // order
+                if let Some(collection) = self.collections.get_mut(source) {
+                    collection.documents = collection.documents.max(*documents);
+                    collection.bytes = collection.bytes.max(*bytes);
+                }
+            }
+            ProgressEvent::CollectionRetrying { source, .. } => {
+                if let Some(collection) = self.collections.get_mut(source) {
+                    collection.retries += 1;
+                }
+            }
+            ProgressEvent::CollectionFinished(report) => {
+                if let Some(collection) = self.collections.get_mut(&report.source) {
+                    collection.documents = report.documents;
+                    collection.bytes = report.bytes;
+                    collection.retries = report.retries.len();
+                    collection.state = match &report.result {
+                        Ok(()) => CollectionState::Done,
+                        Err(ex) => CollectionState::Failed(ex.to_string()),
+                    };
+                }
+            }
+            ProgressEvent::CatchingUp { changes } => self.catching_up = Some(*changes),
+            ProgressEvent::CatchUpRetrying { .. } => {}
+            ProgressEvent::JobFinished => self.finished = true,
+        }
+
+        let now = Instant::now();
+        self.samples
+            .push_back((now, self.documents(), self.bytes()));
+        while let Some((time, ..)) = self.samples.front() {
+            if now.duration_since(*time) <= THROUGHPUT_WINDOW || self.samples.len() <= 2 {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    /// Every collection in the job, by its source namespace
+    pub fn collections(&self) -> impl Iterator<Item = (&Namespace, &CollectionProgress)> {
+        self.collections.iter()
+    }
+
+    /// The number of collections that have finished, successfully or not
+    pub fn finished_collections(&self) -> usize {
+        self.collections
+            .values()
+            .filter(|collection| {
+                matches!(
+                    collection.state,
+                    CollectionState::Done | CollectionState::Failed(_)
+                )
+            })
+            .count()
+    }
+
+    /// How many changes have been replayed, once a snapshot clone is catching up on the changes
+    /// made while copying
+    pub fn catching_up(&self) -> Option<u64> {
+        self.catching_up
+    }
+
+    /// Whether the whole job has finished
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// How long the job has been running for
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// The number of documents copied so far
+    pub fn documents(&self) -> u64 {
+        self.collections.values().map(|x| x.documents).sum()
+    }
+
+    /// The number of bytes copied so far
+    pub fn bytes(&self) -> u64 {
+        self.collections.values().map(|x| x.bytes).sum()
+    }
+
+    /// The number of transient failures that were tried again
+    pub fn retries(&self) -> usize {
+        self.collections.values().map(|x| x.retries).sum()
+    }
+
+    /// How big all the collections were when the job started, as far as is known
+    pub fn expected(&self) -> CollectionStats {
+        self.collections
+            .values()
+            .fold(CollectionStats::default(), |total, collection| {
+                CollectionStats {
+                    documents: total.documents + collection.expected.documents,
+                    bytes: total.bytes + collection.expected.bytes,
+                }
+            })
+    }
+
+    /// How much of the job has been done, between 0 and 1
+    pub fn fraction(&self) -> f32 {
+        if self.finished {
+            return 1.0;
+        }
+
+        // Collections that are done count in full, even if they ended up bigger or smaller than
+        // expected, so the total doesn't go backwards
+        let (documents, bytes) = self
+            .collections
+            .values()
+            .map(|collection| match collection.state {
+                CollectionState::Done | CollectionState::Failed(_) => {
+                    (collection.expected.documents, collection.expected.bytes)
+                }
+                _ => (
+                    collection.documents.min(collection.expected.documents),
+                    collection.bytes.min(collection.expected.bytes),
+                ),
+            })
+            .fold((0, 0), |(d, b), (x, y)| (d + x, b + y));
+
+        let expected = self.expected();
+        if expected == CollectionStats::default() {
+            // Nothing could be sized up, so go by how many collections are done
+            return self.finished_collections() as f32 / self.collections.len().max(1) as f32;
+        }
+        fraction(documents, bytes, &expected)
+    }
+
+    /// How quickly things have been copied over the last few seconds
+    pub fn throughput(&self) -> Throughput {
+        let (Some((first, first_docs, first_bytes)), Some((_, docs, bytes))) =
+            (self.samples.front(), self.samples.back())
+        else {
+            return Throughput::default();
+        };
+
+        let secs = first.elapsed().as_secs_f64();
+        if secs <= 0.0 {
+            return Throughput::default();
+        }
+
+        Throughput {
+            documents_per_sec: (docs - first_docs) as f64 / secs,
+            bytes_per_sec: (bytes - first_bytes) as f64 / secs,
+        }
+    }
+
+    /// Roughly how much longer the job will take, if it can be worked out
+    pub fn eta(&self) -> Option<Duration> {
+        if self.finished {
+            return Some(Duration::ZERO);
+        }
+
+        let expected = self.expected();
+        let throughput = self.throughput();
+        let remaining = if expected.bytes > 0 && throughput.bytes_per_sec > 0.0 {
+            expected.bytes.saturating_sub(self.bytes()) as f64 / throughput.bytes_per_sec
+        } else if expected.documents > 0 && throughput.documents_per_sec > 0.0 {
+            expected.documents.saturating_sub(self.documents()) as f64
+                / throughput.documents_per_sec
+        } else {
+            return None;
+        };
+
+        Some(Duration::from_secs_f64(remaining))
+    }
+}
+
+/// How much of `expected` has been copied, preferring bytes as documents can vary in size
+fn fraction(documents: u64, bytes: u64, expected: &CollectionStats) -> f32 {
+    if expected.bytes > 0 {
+        (bytes as f64 / expected.bytes as f64).min(1.0) as f32
+    } else if expected.documents > 0 {
+        (documents as f64 / expected.documents as f64).min(1.0) as f32
+    } else {
+        0.0
+    }
+}
+
+/// Displays a number of bytes in a human readable way, like `12.3 MB`
+pub struct HumanBytes(pub f64);
+
+impl fmt::Display for HumanBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+        let mut value = self.0;
+        let mut unit = 0;
+        while value >= 1000.0 && unit < UNITS.len() - 1 {
+            value /= 1000.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            write!(f, "{value:.0} {}", UNITS[unit])
+        } else {
+            write!(f, "{value:.1} {}", UNITS[unit])
+        }
+    }
+}
+
+/// Displays a duration in a human readable way, like `1h 2m 3s`
+pub struct HumanDuration(pub Duration);
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+        match (secs / 3600, secs / 60 % 60, secs % 60) {
+            (0, 0, s) => write!(f, "{s}s"),
+            (0, m, s) => write!(f, "{m}m {s}s"),
+            (h, m, s) => write!(f, "{h}h {m}m {s}s"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn started(collections: &[(&str, u64, u64)]) -> ProgressTracker {
+        let mut tracker = ProgressTracker::new();
+        tracker.update(&ProgressEvent::JobStarted {
+            collections: collections
+                .iter()
+                .map(|(name, documents, bytes)| {
+                    let ns = Namespace::new("db", *name);
+                    let stats = CollectionStats {
+                        documents: *documents,
+                        bytes: *bytes,
+                    };
+                    (ns.clone(), ns, stats)
+                })
+                .collect(),
+        });
+        tracker
+    }
+
+    fn progress(name: &str, documents: u64, bytes: u64) -> ProgressEvent {
+        let ns = Namespace::new("db", name);
+        ProgressEvent::CollectionProgress {
+            source: ns.clone(),
+            target: ns,
+            documents,
+            bytes,
+        }
+    }
+
+    /// Makes every sample so far `ago` older
+    fn age(tracker: &mut ProgressTracker, ago: Duration) {
+        for (time, ..) in &mut tracker.samples {
+            *time -= ago;
+        }
+    }
+
+    #[test]
+    fn keeps_the_highest_totals() {
+        let mut tracker = started(&[("a", 100, 1000)]);
+        tracker.update(&progress("a", 60, 600));
+        tracker.update(&progress("a", 30, 300));
+        assert_eq!((tracker.documents(), tracker.bytes()), (60, 600));
+        assert_eq!(tracker.fraction(), 0.6);
+
+        // Collections that aren't in the job are ignored
+        tracker.update(&progress("b", 1000, 1000));
+        assert_eq!(tracker.documents(), 60);
+    }
+
+    #[test]
+    fn goes_by_documents_without_sizes() {
+        let mut tracker = started(&[("a", 100, 0)]);
+        tracker.update(&progress("a", 25, 5000));
+        assert_eq!(tracker.fraction(), 0.25);
+
+        // Copying more than expected doesn't go past the end
+        tracker.update(&progress("a", 150, 5000));
+        assert_eq!(tracker.fraction(), 1.0);
+    }
+
+    #[test]
+    fn goes_by_collections_without_counts() {
+        let mut tracker = started(&[("a", 0, 0), ("b", 0, 0)]);
+        assert_eq!(tracker.fraction(), 0.0);
+        tracker.update(&ProgressEvent::CollectionStarted {
+            source: Namespace::new("db", "a"),
+            target: Namespace::new("db", "a"),
+        });
+        tracker
+            .collections
+            .get_mut(&Namespace::new("db", "a"))
+            .unwrap()
+            .state = CollectionState::Done;
+        assert_eq!(tracker.fraction(), 0.5);
+
+        tracker.update(&ProgressEvent::JobFinished);
+        assert_eq!(tracker.fraction(), 1.0);
+        assert_eq!(tracker.eta(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn measures_throughput_over_a_window() {
+        let mut tracker = started(&[("a", 1000, 10_000)]);
+        tracker.update(&progress("a", 100, 1000));
+        tracker.update(&progress("a", 200, 2000));
+        age(&mut tracker, Duration::from_secs(60));
+
+        // Old samples drop out, but there's always a couple to measure between
+        tracker.update(&progress("a", 500, 5000));
+        assert_eq!(tracker.samples.len(), 2);
+        let throughput = tracker.throughput();
+        assert!(throughput.documents_per_sec > 0.0);
+        assert!(throughput.documents_per_sec < 300.0 / 59.0);
+    }
+
+    #[test]
+    fn estimates_the_time_left() {
+        let mut tracker = started(&[("a", 1000, 10_000)]);
+        assert_eq!(tracker.eta(), None);
+
+        tracker.samples.clear();
+        tracker.update(&progress("a", 0, 0));
+        age(&mut tracker, Duration::from_secs(10));
+        tracker.update(&progress("a", 500, 5000));
+
+        // 5000 bytes in 10s leaves 10s for the other 5000
+        let eta = tracker.eta().unwrap().as_secs_f64();
+        assert!((10.0..10.5).contains(&eta), "{eta}");
+    }
+
+    #[test]
+    fn formats_bytes() {
+        assert_eq!(HumanBytes(0.0).to_string(), "0 B");
+        assert_eq!(HumanBytes(999.0).to_string(), "999 B");
+        assert_eq!(HumanBytes(1000.0).to_string(), "1.0 KB");
+        assert_eq!(HumanBytes(12_345_678.0).to_string(), "12.3 MB");
+        assert_eq!(HumanBytes(5e15).to_string(), "5000.0 TB");
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(HumanDuration(Duration::ZERO).to_string(), "0s");
+        assert_eq!(
+            HumanDuration(Duration::from_millis(59_999)).to_string(),
+            "59s"
+        );
+        assert_eq!(HumanDuration(Duration::from_secs(60)).to_string(), "1m 0s");
+        assert_eq!(
+            HumanDuration(Duration::from_secs(3723)).to_string(),
+            "1h 2m 3s"
+        );
+        assert_eq!(
+            HumanDuration(Duration::from_secs(90_000)).to_string(),
+            "25h 0m 0s"
+        );
+    }
+}
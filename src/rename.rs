@@ -0,0 +1,301 @@
+//! Rules for renaming lots of databases or collections the same way, like `prod_*` to `staging_*`
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// What a rule renames
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RuleScope {
+    #[default]
+    Databases,
+    Collections,
+}
+
+/// How a rule renames things
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RuleKind {
+    /// Names matching a glob like `prod_*`, renamed to a template like `staging_*` where each
+    /// `*` or `?` is whatever the same one in the glob matched
+    #[default]
+    Glob,
+    /// Matches of a regex, replaced with a template that can use `$1` or `${name}` for groups
+    Regex,
+    /// Adds some text to the start of every name
+    Prefix,
+    /// Adds some text to the end of every name
+    Suffix,
+    Lowercase,
+    Uppercase,
+}
+
+/// A way of renaming databases or collections
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenameRule {
+    pub scope: RuleScope,
+    pub kind: RuleKind,
+    /// The glob or regex to match, or the text to add for prefixes and suffixes
+    pub pattern: String,
+    /// What a glob or regex's matches are renamed to
+    pub template: String,
+}
+
+/// One rule ready to rename with
+#[derive(Debug)]
+enum Step {
+    Replace(Regex, String),
+    Prefix(String),
+    Suffix(String),
+    Lowercase,
+    Uppercase,
+}
+
+/// Some rules ready to rename with, applied in order
+#[derive(Debug)]
+pub struct Renamer {
+    steps: Vec<(RuleScope, Step)>,
+}
+
+impl Renamer {
+    /// Gets `rules` ready, or says which one's pattern is wrong
+    pub fn new(rules: &[RenameRule]) -> Result<Self, String> {
+        // A blank regex matches between every letter, so it's left out until it's filled in
+        let steps = rules
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| !(rule.kind == RuleKind::Regex && rule.pattern.is_empty()))
+            .map(|(i, rule)| {
+                let step = match rule.kind {
+                    RuleKind::Glob => {
+                        let (regex, template) = glob(&rule.pattern, &rule.template);
+                        let regex = compile(&regex, i)?;
+                        if missing_group(&regex, &template).is_some() {
+                            return Err(format!(
+                                "Rule {}: The template has more `*` and `?` than the pattern",
+                                i + 1
+                            ));
+                        }
+                        Step::Replace(regex, template)
+                    }
+                    RuleKind::Regex => {
+                        let regex = compile(&rule.pattern, i)?;
+                        if let Some(missing) = missing_group(&regex, &rule.template) {
+                            return Err(format!(
+                                "Rule {}: The template uses `${{{missing}}}`, which isn't a group \
+                                 in the pattern",
+                                i + 1
+                            ));
+                        }
+                        Step::Replace(regex, rule.template.clone())
+                    }
+                    RuleKind::Prefix => Step::Prefix(rule.pattern.clone()),
+                    RuleKind::Suffix => Step::Suffix(rule.pattern.clone()),
+                    RuleKind::Lowercase => Step::Lowercase,
+                    RuleKind::Uppercase => Step::Uppercase,
+                };
+                Ok((rule.scope, step))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { steps })
+    }
+
+    /// Whether there are no rules, so nothing gets renamed
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// What the rules for `scope` rename `name` to
+    pub fn rename(&self, scope: RuleScope, name: &str) -> String {
+        let mut name = name.to_string();
+        for (_, step) in self.steps.iter().filter(|(x, _)| *x == scope) {
+            name = match step {
+                Step::Replace(regex, template) => {
+                    regex.replace_all(&name, template.as_str()).into_owned()
+                }
+                Step::Prefix(prefix) => format!("{prefix}{name}"),
+                Step::Suffix(suffix) => format!("{name}{suffix}"),
+                Step::Lowercase => name.to_lowercase(),
+                Step::Uppercase => name.to_uppercase(),
+            };
+        }
+        name
+    }
+}
+
+/// Compiles the pattern of the `i`th rule
+fn compile(pattern: &str, i: usize) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|ex| format!("Rule {}: {ex}", i + 1))
+}
+
+/// The first group `template` refers to that `regex` doesn't have, which would be replaced with
+/// nothing
+fn missing_group(regex: &Regex, template: &str) -> Option<String> {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut rest = template;
+    while let Some(dollar) = rest.find('$') {
+        rest = &rest[dollar + 1..];
+        let name = if rest.starts_with('$') {
+            rest = &rest[1..];
+            continue;
+        } else if let Some(braced) = rest.strip_prefix('{') {
+            match braced.split_once('}') {
+                Some((name, after)) => {
+                    rest = after;
+                    name
+                }
+                // Without the closing brace it's taken as it is
+                None => continue,
+            }
+        } else {
+            let end = rest.find(|c| !is_name(c)).unwrap_or(rest.len());
+            let (name, after) = rest.split_at(end);
+            rest = after;
+            name
+        };
+        if name.is_empty() {
+            continue;
+        }
+        let exists = match name.parse::<usize>() {
+            Ok(index) => index < regex.captures_len(),
+            Err(_) => regex.capture_names().flatten().any(|x| x == name),
+        };
+        if !exists {
+            return Some(name.to_string());
+        }
+    }
+    None
+}
+
+/// Turns a glob into a regex matching whole names, and its template into a regex template
+fn glob(pattern: &str, template: &str) -> (String, String) {
+    let mut regex = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str("(.*)"),
+            '?' => regex.push_str("(.)"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+
+    let mut replacement = String::new();
+    let mut group = 0;
+    for c in template.chars() {
+        match c {
+            '*' | '?' => {
+                group += 1;
+                replacement.push_str(&format!("${{{group}}}"));
+            }
+            '$' => replacement.push_str("$$"),
+            c => replacement.push(c),
+        }
+    }
+    (regex, replacement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(scope: RuleScope, kind: RuleKind, pattern: &str, template: &str) -> RenameRule {
+        RenameRule {
+            scope,
+            kind,
+            pattern: pattern.into(),
+            template: template.into(),
+        }
+    }
+
+    fn db_rule(kind: RuleKind, pattern: &str, template: &str) -> RenameRule {
+        rule(RuleScope::Databases, kind, pattern, template)
+    }
+
+    fn rename(rules: &[RenameRule], name: &str) -> String {
+        Renamer::new(rules)
+            .unwrap()
+            .rename(RuleScope::Databases, name)
+    }
+
+    #[test]
+    fn globs_put_each_wildcard_in_the_template() {
+        let rules = [db_rule(RuleKind::Glob, "prod_*_v?", "staging_*_v?")];
+        assert_eq!(rename(&rules, "prod_orders_v2"), "staging_orders_v2");
+        // Globs match whole names, and `$` is only text
+        assert_eq!(rename(&rules, "old_prod_orders_v2"), "old_prod_orders_v2");
+        let rules = [db_rule(RuleKind::Glob, "*.db", "$*")];
+        assert_eq!(rename(&rules, "a.db"), "$a");
+        assert_eq!(rename(&rules, "axdb"), "axdb");
+    }
+
+    #[test]
+    fn regexes_replace_every_match_with_captures() {
+        let rules = [db_rule(
+            RuleKind::Regex,
+            r"(\d+)-(?P<env>[a-z]+)",
+            "${env}_$1",
+        )];
+        assert_eq!(rename(&rules, "app_1-dev_2-test"), "app_dev_1_test_2");
+    }
+
+    #[test]
+    fn leaves_names_that_dont_match() {
+        let rules = [
+            db_rule(RuleKind::Glob, "prod_*", "staging_*"),
+            db_rule(RuleKind::Regex, "^tmp", "scratch"),
+        ];
+        assert_eq!(rename(&rules, "analytics"), "analytics");
+    }
+
+    #[test]
+    fn applies_every_matching_rule_in_order() {
+        let rules = [
+            db_rule(RuleKind::Glob, "prod_*", "staging_*"),
+            db_rule(RuleKind::Prefix, "eu_", ""),
+            db_rule(RuleKind::Uppercase, "", ""),
+            db_rule(RuleKind::Regex, "STAGING", "Stage"),
+            db_rule(RuleKind::Suffix, "_copy", ""),
+        ];
+        assert_eq!(rename(&rules, "prod_orders"), "EU_Stage_ORDERS_copy");
+
+        let reversed = rules.iter().rev().cloned().collect::<Vec<_>>();
+        assert_eq!(rename(&reversed, "prod_orders"), "eu_PROD_ORDERS_COPY");
+    }
+
+    #[test]
+    fn only_applies_rules_for_the_scope() {
+        let renamer = Renamer::new(&[
+            db_rule(RuleKind::Prefix, "db_", ""),
+            rule(RuleScope::Collections, RuleKind::Suffix, "_coll", ""),
+        ])
+        .unwrap();
+        assert_eq!(renamer.rename(RuleScope::Databases, "x"), "db_x");
+        assert_eq!(renamer.rename(RuleScope::Collections, "x"), "x_coll");
+    }
+
+    #[test]
+    fn rejects_templates_using_missing_captures() {
+        for template in ["$2", "${2}", "${env}", "$env_x"] {
+            let error = Renamer::new(&[
+                db_rule(RuleKind::Prefix, "x", ""),
+                db_rule(RuleKind::Regex, "(a)", template),
+            ])
+            .unwrap_err();
+            assert!(error.starts_with("Rule 2: The template uses"), "{error}");
+        }
+        assert_eq!(
+            Renamer::new(&[db_rule(RuleKind::Glob, "prod_*", "*_*")]).unwrap_err(),
+            "Rule 1: The template has more `*` and `?` than the pattern"
+        );
+        // Escaped dollars aren't references
+        assert!(Renamer::new(&[db_rule(RuleKind::Regex, "(a)", "$$2$1")]).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_regexes() {
+        let error = Renamer::new(&[db_rule(RuleKind::Regex, "(", "")]).unwrap_err();
+        assert!(error.starts_with("Rule 1: "), "{error}");
+        // Blank ones are left out until they're filled in
+        assert!(Renamer::new(&[db_rule(RuleKind::Regex, "", "x")])
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+use mongodb::error::{Error as MongoError, ErrorKind, Result as MongoResult, WriteFailure};
+use serde::{Deserialize, Serialize};
+use std::{future::Future, io, time::Duration};
+use tracing::warn;
+
+/// Server error codes for failures that go away by themselves, like a primary stepping down
+const TRANSIENT_CODES: [i32; 12] = [
+    6,     // HostUnreachable
+    7,     // HostNotFound
+    89,    // NetworkTimeout
+    91,    // ShutdownInProgress
+    189,   // PrimarySteppedDown
+    262,   // ExceededTimeLimit
+    9001,  // SocketException
+    10107, // NotWritablePrimary
+    11600, // InterruptedAtShutdown
+    11602, // InterruptedDueToReplStateChange
+    13435, // NotPrimaryNoSecondaryOk
+    13436, // NotPrimaryOrSecondary
+];
+
+/// Error labels the driver and server put on failures that are safe to try again
+const TRANSIENT_LABELS: [&str; 3] = [
+    "RetryableWriteError",
+    "TransientTransactionError",
+    "ResumableChangeStreamError",
+];
+
+/// The server error code for inserting an `_id` that's already there
+const DUPLICATE_KEY: i32 = 11000;
+
+/// How many times to try things that fail with transient errors, and how long to wait in between
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// How many times something is tried before giving up, including the first try
+    pub max_attempts: u32,
+    /// How long to wait after the first failure, which doubles after each one after that
+    pub initial_backoff: Duration,
+    /// The longest to wait between tries
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never try again
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// How long to wait after try number `attempt` fails, counting from 1
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// A transient failure that was tried again
+#[derive(Debug, Clone)]
+pub struct Retry {
+    /// Which try failed, counting from 1
+    pub attempt: u32,
+    /// What went wrong
+    pub error: MongoError,
+    /// How long was waited before trying again
+    pub backoff: Duration,
+}
+
+/// Whether an error is likely to go away if the same thing is tried again, like network errors
+/// and elections
+pub fn is_transient(ex: &MongoError) -> bool {
+    if TRANSIENT_LABELS
+        .iter()
+        .any(|label| ex.contains_label(label))
+    {
+        return true;
+    }
+
+    match ex.kind.as_ref() {
+        ErrorKind::Io(ex) => matches!(
+            ex.kind(),
+            io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::NotConnected
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::Interrupted
+        ),
+        ErrorKind::ConnectionPoolCleared { .. } | ErrorKind::ServerSelection { .. } => true,
+        ErrorKind::Command(ex) => TRANSIENT_CODES.contains(&ex.code),
+        ErrorKind::Write(WriteFailure::WriteConcernError(ex)) => TRANSIENT_CODES.contains(&ex.code),
+        // Only the write concern failed, so every document is in but might not be replicated yet
+        ErrorKind::BulkWrite(ex) => {
+            ex.write_errors.as_ref().is_none_or(Vec::is_empty)
+                && ex
+                    .write_concern_error
+                    .as_ref()
+                    .is_some_and(|ex| TRANSIENT_CODES.contains(&ex.code))
+        }
+        _ => false,
+    }
+}
+
+/// Whether an insert only failed because some of the documents were already there, which is
+/// what happens when a batch that got partly written is tried again
+pub fn is_duplicate_key_only(ex: &MongoError) -> bool {
+    match ex.kind.as_ref() {
+        ErrorKind::BulkWrite(ex) => {
+            ex.write_concern_error.is_none()
+                && ex
+                    .write_errors
+                    .as_ref()
+                    .is_some_and(|errors| errors.iter().all(|error| error.code == DUPLICATE_KEY))
+        }
+        ErrorKind::Write(WriteFailure::WriteError(ex)) => ex.code == DUPLICATE_KEY,
+        _ => false,
+    }
+}
+
+/// How many of the documents in an insert were already there
+pub fn duplicate_keys(ex: &MongoError) -> u64 {
+    match ex.kind.as_ref() {
+        ErrorKind::BulkWrite(ex) => ex.write_errors.as_ref().map_or(0, |errors| {
+            errors
+                .iter()
+                .filter(|error| error.code == DUPLICATE_KEY)
+                .count() as u64
+        }),
+        ErrorKind::Write(WriteFailure::WriteError(ex)) => u64::from(ex.code == DUPLICATE_KEY),
+        _ => 0,
+    }
+}
+
+/// Runs `f` until it succeeds, fails with an error that isn't transient, or runs out of tries,
+/// waiting longer after each failure. `f` is given which try it is, counting from 1, and
+/// `on_retry` hears about every failure that is tried again.
+pub async fn retry<T, F, Fut>(
+    policy: &RetryPolicy,
+    mut on_retry: impl FnMut(Retry),
+    mut f: F,
+) -> MongoResult<T>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = MongoResult<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match f(attempt).await {
+            Err(error) if attempt < policy.max_attempts && is_transient(&error) => {
+                let backoff = policy.backoff(attempt);
+                warn!("Attempt {attempt} failed, trying again in {backoff:?}: {error}");
+                on_retry(Retry {
+                    attempt,
+                    error,
+                    backoff,
+                });
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Picking which namespaces to clone with patterns, rather than one by one
+
+use crate::{endpoint::Namespace, gridfs};
+use serde::{Deserialize, Serialize};
+
+/// The databases left out unless a pattern includes them, as they belong to the server
+pub const SYSTEM_DATABASES: [&str; 3] = ["admin", "local", "config"];
+
+/// Patterns matched against `<db>.<collection>`, where `*` matches anything and `?` any one
+/// character. Patterns starting with `!` leave out what they match. Everything is selected to
+/// start with, or nothing if there are any patterns that include, and the last pattern matching a
+/// namespace decides.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Selection {
+    pub patterns: Vec<String>,
+}
+
+impl Default for Selection {
+    /// Leaves out the system databases
+    fn default() -> Self {
+        Self {
+            patterns: SYSTEM_DATABASES
+                .iter()
+                .map(|db| format!("!{db}.*"))
+                .collect(),
+        }
+    }
+}
+
+impl Selection {
+    /// Reads patterns written one per line, skipping blank lines
+    pub fn parse(text: &str) -> Self {
+        Self {
+            patterns: text
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+
+    /// The patterns one per line, as [`Selection::parse`] reads them
+    pub fn to_text(&self) -> String {
+        self.patterns.join("\n")
+    }
+
+    /// Whether `ns` is picked by the patterns
+    pub fn selects(&self, ns: &Namespace) -> bool {
+        self.selects_any(std::slice::from_ref(ns))
+    }
+
+    /// Whether something going by any of `names` is picked by the patterns, like a GridFS bucket
+    /// and its `.files` and `.chunks` collections. The last pattern matching any of them decides.
+    pub fn selects_any(&self, names: &[Namespace]) -> bool {
+        let names = names
+            .iter()
+            .map(|ns| format!("{}.{}", ns.db, ns.collection))
+            .collect::<Vec<_>>();
+        let mut selected = self.patterns.iter().all(|pattern| pattern.starts_with('!'));
+        for pattern in &self.patterns {
+            let (include, glob) = match pattern.strip_prefix('!') {
+                Some(glob) => (false, glob),
+                None => (true, pattern.as_str()),
+            };
+            if names.iter().any(|name| matches(glob, name)) {
+                selected = include;
+            }
+        }
+        selected
+    }
+
+    /// Keeps the `(source, target)` pairs whose source is picked by the patterns, with both
+    /// collections of a GridFS bucket picked or left out together
+    pub fn filter(&self, collections: Vec<(Namespace, Namespace)>) -> Vec<(Namespace, Namespace)> {
+        let sources = collections
+            .iter()
+            .map(|(source, _)| source.clone())
+            .collect::<Vec<_>>();
+        collections
+            .into_iter()
+            .filter(|(source, _)| {
+                let bucket = [gridfs::FILES, gridfs::CHUNKS]
+                    .into_iter()
+                    .find_map(|suffix| source.collection.strip_suffix(suffix))
+                    .map(|bucket| Namespace::new(&source.db, bucket))
+                    .filter(|bucket| {
+                        sources.contains(&gridfs::files(bucket))
+                            && sources.contains(&gridfs::chunks(bucket))
+                    });
+                match bucket {
+                    Some(bucket) => {
+                        self.selects_any(&[gridfs::files(&bucket), gridfs::chunks(&bucket), bucket])
+                    }
+                    None => self.selects(source),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Whether `text` matches all of `glob`
+fn matches(glob: &str, text: &str) -> bool {
+    let glob = glob.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut g, mut t) = (0, 0);
+    // Where the last `*` was, and where in the text it's matching up to, to backtrack to
+    let mut star = None;
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, t));
+                g += 1;
+            }
+            Some('?') => {
+                g += 1;
+                t += 1;
+            }
+            Some(c) if *c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_g, star_t)) => {
+                    g = star_g + 1;
+                    t = star_t + 1;
+                    star = Some((star_g, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selects(patterns: &str, ns: &str) -> bool {
+        Selection::parse(patterns).selects(&ns.parse().unwrap())
+    }
+
+    #[test]
+    fn matches_whole_databases_collections_or_exact_names() {
+        assert!(selects("shop.*", "shop.orders"));
+        assert!(!selects("shop.*", "shopping.orders"));
+        assert!(selects("*.orders", "eu.orders"));
+        assert!(!selects("*.orders", "eu.orders_old"));
+        assert!(selects("shop.orders", "shop.orders"));
+        assert!(!selects("shop.orders", "shop.orders2"));
+        assert!(selects("shop.order?", "shop.orders"));
+        assert!(selects("*_prod.*_v*", "eu_prod.users_v2"));
+    }
+
+    #[test]
+    fn includes_only_what_is_listed() {
+        assert!(selects("shop.*\nblog.posts", "blog.posts"));
+        assert!(!selects("shop.*\nblog.posts", "blog.comments"));
+    }
+
+    #[test]
+    fn excludes_from_everything_else() {
+        assert!(selects("!shop.*", "blog.posts"));
+        assert!(!selects("!shop.*", "shop.orders"));
+        assert!(!selects("!*.tmp_*", "shop.tmp_orders"));
+    }
+
+    #[test]
+    fn the_last_matching_pattern_decides() {
+        let patterns = "shop.*\n!shop.audit_*\nshop.audit_2024";
+        assert!(selects(patterns, "shop.orders"));
+        assert!(!selects(patterns, "shop.audit_2023"));
+        assert!(selects(patterns, "shop.audit_2024"));
+        // Excluding after including wins
+        assert!(!selects("shop.orders\n!shop.*", "shop.orders"));
+    }
+
+    #[test]
+    fn leaves_out_system_databases_by_default() {
+        let selection = Selection::default();
+        for ns in ["admin.system.users", "local.oplog.rs", "config.chunks"] {
+            assert!(!selection.selects(&ns.parse().unwrap()), "{ns}");
+        }
+        assert!(selection.selects(&"shop.orders".parse().unwrap()));
+    }
+
+    #[test]
+    fn matches_any_name_of_a_bucket() {
+        let bucket = ["app.fs", "app.fs.files", "app.fs.chunks"].map(|ns| ns.parse().unwrap());
+        let selects_bucket = |patterns| Selection::parse(patterns).selects_any(&bucket);
+        assert!(selects_bucket("app.fs"));
+        assert!(selects_bucket("app.fs.files"));
+        assert!(selects_bucket("app.*.chunks"));
+        assert!(!selects_bucket("app.users"));
+        assert!(!selects_bucket("!app.fs.files"));
+        assert!(!selects_bucket("app.*\n!app.fs.*"));
+        assert!(selects_bucket("!app.fs.*\napp.fs"));
+    }
+
+    #[test]
+    fn filters_pairs_keeping_buckets_whole() {
+        let pairs = [
+            "app.users",
+            "app.fs.files",
+            "app.fs.chunks",
+            "app.logs.files",
+        ]
+        .map(|ns| {
+            let ns = ns.parse::<Namespace>().unwrap();
+            (ns.clone(), ns)
+        })
+        .to_vec();
+        let kept = |patterns| {
+            Selection::parse(patterns)
+                .filter(pairs.clone())
+                .into_iter()
+                .map(|(source, _)| source.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(kept("app.fs.files"), ["app.fs.files", "app.fs.chunks"]);
+        assert_eq!(kept("!app.fs.chunks"), ["app.users", "app.logs.files"]);
+        // Without its chunks, a `.files` collection is just a collection
+        assert_eq!(kept("app.logs"), Vec::<String>::new());
+        assert_eq!(
+            kept("app.users\napp.logs.*"),
+            ["app.users", "app.logs.files"]
+        );
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let selection = Selection::parse("  shop.*\n\n!shop.tmp \n");
+        assert_eq!(selection.patterns, ["shop.*", "!shop.tmp"]);
+        assert_eq!(Selection::parse(&selection.to_text()), selection);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::endpoint::Namespace;
+use mongodb::bson::{doc, Bson, Document};
+use serde::{Deserialize, Serialize};
+
+/// The most chunks a collection is split into up front, past this the balancer can do the rest
+pub const MAX_SPLITS: usize = 1024;
+
+/// Whether target collections are sharded the way their sources are before loading them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Sharding {
+    /// Leave target collections unsharded
+    #[default]
+    Off,
+    /// Shard target collections on the same key as their source
+    Keys,
+    /// Shard them on the same key, and split them into chunks and zones like the source so the
+    /// load is spread over every shard
+    Split,
+}
+
+/// A range of shard key values that's kept on the shards in a zone
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ZoneRange {
+    pub min: Document,
+    pub max: Document,
+    /// The zone's name, which `config.tags` calls a tag
+    #[serde(alias = "tag")]
+    pub zone: String,
+}
+
+/// How a collection is sharded
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardLayout {
+    /// The shard key, like `{ customer: 1, _id: 1 }` or `{ _id: "hashed" }`
+    pub key: Document,
+    /// Whether the shard key has a unique index
+    pub unique: bool,
+    /// Where chunks start, other than the first one, if they were asked for
+    pub split_points: Vec<Document>,
+    /// Ranges pinned to zones, if they were asked for
+    pub zones: Vec<ZoneRange>,
+}
+
+impl ShardLayout {
+    /// Whether the key is hashed, so chunks are split up by the server rather than at points
+    pub fn is_hashed(&self) -> bool {
+        self.key
+            .values()
+            .any(|x| x == &Bson::String("hashed".into()))
+    }
+
+    /// The admin commands that shard `ns` like this, in the order they have to be run
+    pub fn commands(&self, ns: &Namespace) -> Vec<Document> {
+        // Zones go on first, so the server makes the first chunks along them as it shards the
+        // collection rather than leaving the balancer to move them afterwards
+        let mut commands = self
+            .zones
+            .iter()
+            .map(|ZoneRange { min, max, zone }| {
+                doc! {
+                    "updateZoneKeyRange": ns.to_string(),
+                    "min": min.clone(),
+                    "max": max.clone(),
+                    "zone": zone,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut command = doc! {
+            "shardCollection": ns.to_string(),
+            "key": self.key.clone(),
+            "unique": self.unique,
+        };
+        // Hashed keys can be split up evenly by the server as the collection is sharded, or along
+        // the zones if there are any, which it won't take a number of chunks for
+        if self.is_hashed() && !self.zones.is_empty() {
+            command.insert("presplitHashedZones", true);
+        } else if self.is_hashed() && !self.split_points.is_empty() {
+            command.insert("numInitialChunks", self.split_points.len() as i64 + 1);
+        }
+        commands.push(command);
+
+        if !self.is_hashed() {
+            commands.extend(
+                self.split_points
+                    .iter()
+                    .map(|point| doc! { "split": ns.to_string(), "middle": point.clone() }),
+            );
+        }
+        commands
+    }
+}
+
+/// Picks at most [`MAX_SPLITS`] of `points`, spread out evenly
+pub fn thin_splits(points: Vec<Document>) -> Vec<Document> {
+    if points.len() <= MAX_SPLITS {
+        return points;
+    }
+    let step = points.len() as f64 / MAX_SPLITS as f64;
+    (0..MAX_SPLITS)
+        .map(|i| points[(i as f64 * step) as usize].clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(key: Document) -> ShardLayout {
+        ShardLayout {
+            key,
+            unique: false,
+            split_points: vec![],
+            zones: vec![],
+        }
+    }
+
+    fn names(commands: &[Document]) -> Vec<&str> {
+        commands
+            .iter()
+            .map(|command| command.keys().next().unwrap().as_str())
+            .collect()
+    }
+
+    #[test]
+    fn tells_hashed_keys_apart() {
+        assert!(layout(doc! { "_id": "hashed" }).is_hashed());
+        assert!(layout(doc! { "region": 1, "customer": "hashed" }).is_hashed());
+        assert!(!layout(doc! { "region": 1, "customer": -1 }).is_hashed());
+        assert!(!layout(doc! { "hashed": 1 }).is_hashed());
+    }
+
+    #[test]
+    fn thins_splits_out_evenly() {
+        let points = |count: i32| (0..count).map(|i| doc! { "x": i }).collect::<Vec<_>>();
+        assert_eq!(thin_splits(points(10)), points(10));
+        assert_eq!(thin_splits(points(MAX_SPLITS as i32)).len(), MAX_SPLITS);
+
+        let thinned = thin_splits(points(3000));
+        assert_eq!(thinned.len(), MAX_SPLITS);
+        assert_eq!(thinned[0], doc! { "x": 0 });
+        let xs = thinned
+            .iter()
+            .map(|point| point.get_i32("x").unwrap())
+            .collect::<Vec<_>>();
+        for pair in xs.windows(2) {
+            assert!((2..=3).contains(&(pair[1] - pair[0])), "{pair:?}");
+        }
+        assert!(*xs.last().unwrap() >= 2997);
+    }
+
+    #[test]
+    fn adds_zones_before_sharding() {
+        let ns = Namespace::new("shop", "orders");
+        let mut ranged = layout(doc! { "region": 1 });
+        ranged.split_points = vec![doc! { "region": "eu" }, doc! { "region": "us" }];
+        ranged.zones = vec![ZoneRange {
+            min: doc! { "region": "eu" },
+            max: doc! { "region": "us" },
+            zone: "EU".into(),
+        }];
+        let commands = ranged.commands(&ns);
+        assert_eq!(
+            names(&commands),
+            ["updateZoneKeyRange", "shardCollection", "split", "split"]
+        );
+        assert_eq!(commands[0].get_str("zone").unwrap(), "EU");
+        assert_eq!(
+            commands[2].get_document("middle").unwrap(),
+            &ranged.split_points[0]
+        );
+
+        // Hashed keys are split up by the server, along the zones if there are any
+        let mut hashed = ranged.clone();
+        hashed.key = doc! { "region": 1, "_id": "hashed" };
+        let commands = hashed.commands(&ns);
+        assert_eq!(names(&commands), ["updateZoneKeyRange", "shardCollection"]);
+        assert_eq!(commands[1].get_bool("presplitHashedZones"), Ok(true));
+        assert!(!commands[1].contains_key("numInitialChunks"));
+
+        hashed.zones.clear();
+        let commands = hashed.commands(&ns);
+        assert_eq!(names(&commands), ["shardCollection"]);
+        assert_eq!(commands[0].get_i64("numInitialChunks"), Ok(3));
+    }
+}
@@ -0,0 +1,245 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The longest a throttled task sleeps before checking if the limit has changed
+const MAX_SLEEP: Duration = Duration::from_millis(100);
+
+/// How fast documents may be read or written. `None` means there is no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub documents_per_sec: Option<f64>,
+    pub bytes_per_sec: Option<f64>,
+}
+
+impl RateLimit {
+    /// No limit at all
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+}
+
+/// A token bucket that may go into debt, so batches bigger than a second's worth still get
+/// through, they just hold up whatever comes after them
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new() -> Self {
+        Self {
+            tokens: 0.0,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Tops up the bucket for the time since it was last used, holding at most a second's worth
+    fn refill(&mut self, rate: Option<f64>) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.updated = now;
+        self.tokens = match rate {
+            Some(rate) => (self.tokens + elapsed * rate).min(rate),
+            None => 0.0,
+        };
+    }
+
+    /// How long until the bucket is out of debt
+    fn wait(&self, rate: Option<f64>) -> Duration {
+        match rate {
+            Some(rate) if self.tokens < 0.0 && rate > 0.0 => {
+                Duration::from_secs_f64(-self.tokens / rate)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// Limits the rate of one direction, reads or writes
+#[derive(Debug)]
+struct Limiter {
+    limit: RateLimit,
+    documents: Bucket,
+    bytes: Bucket,
+}
+
+/// Slows down reads and writes to protect the clusters being cloned. The limits can be changed
+/// while a clone is running.
+#[derive(Debug)]
+pub struct Throttle {
+    read: Mutex<Limiter>,
+    write: Mutex<Limiter>,
+}
+
+impl Throttle {
+    pub fn new(read: RateLimit, write: RateLimit) -> Self {
+        let limiter = |limit| {
+            Mutex::new(Limiter {
+                limit,
+                documents: Bucket::new(),
+                bytes: Bucket::new(),
+            })
+        };
+        Self {
+            read: limiter(read),
+            write: limiter(write),
+        }
+    }
+
+    /// How fast documents may be read from the source
+    pub fn read_limit(&self) -> RateLimit {
+        self.read.lock().unwrap().limit
+    }
+
+    /// How fast documents may be written to the target
+    pub fn write_limit(&self) -> RateLimit {
+        self.write.lock().unwrap().limit
+    }
+
+    /// Changes how fast documents may be read from the source
+    pub fn set_read_limit(&self, limit: RateLimit) {
+        self.read.lock().unwrap().limit = limit;
+    }
+
+    /// Changes how fast documents may be written to the target
+    pub fn set_write_limit(&self, limit: RateLimit) {
+        self.write.lock().unwrap().limit = limit;
+    }
+
+    /// Waits until the batches read so far have been paid for, so the next one can be pulled
+    pub(crate) async fn before_read(&self) {
+        settle(&self.read).await
+    }
+
+    /// Takes a batch that has been read out of the read budget, holding up the next read until
+    /// it's paid for
+    pub(crate) fn read(&self, documents: u64, bytes: u64) {
+        charge(&self.read, documents, bytes)
+    }
+
+    /// Waits until a batch can be written
+    pub(crate) async fn write(&self, documents: u64, bytes: u64) {
+        charge(&self.write, documents, bytes);
+        settle(&self.write).await
+    }
+}
+
+/// Takes a batch out of the limiter's buckets
+fn charge(limiter: &Mutex<Limiter>, documents: u64, bytes: u64) {
+    let mut limiter = limiter.lock().unwrap();
+    let limit = limiter.limit;
+    limiter.documents.refill(limit.documents_per_sec);
+    limiter.bytes.refill(limit.bytes_per_sec);
+    limiter.documents.tokens -= documents as f64;
+    limiter.bytes.tokens -= bytes as f64;
+}
+
+/// Waits for the limiter's buckets to be out of debt
+async fn settle(limiter: &Mutex<Limiter>) {
+    // Sleep in short steps so that raising the limit speeds things up straight away
+    loop {
+        let wait = {
+            let mut limiter = limiter.lock().unwrap();
+            let limit = limiter.limit;
+            limiter.documents.refill(limit.documents_per_sec);
+            limiter.bytes.refill(limit.bytes_per_sec);
+            limiter
+                .documents
+                .wait(limit.documents_per_sec)
+                .max(limiter.bytes.wait(limit.bytes_per_sec))
+        };
+        if wait.is_zero() {
+            return;
+        }
+        tokio::time::sleep(wait.min(MAX_SLEEP)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(documents_per_sec: f64) -> RateLimit {
+        RateLimit {
+            documents_per_sec: Some(documents_per_sec),
+            bytes_per_sec: None,
+        }
+    }
+
+    #[test]
+    fn goes_into_debt_for_big_batches() {
+        let mut bucket = Bucket::new();
+        bucket.tokens = -500.0;
+        assert_eq!(bucket.wait(Some(1000.0)), Duration::from_millis(500));
+        assert_eq!(bucket.wait(Some(0.0)), Duration::ZERO);
+
+        bucket.tokens = 10.0;
+        assert_eq!(bucket.wait(Some(1000.0)), Duration::ZERO);
+    }
+
+    #[test]
+    fn holds_at_most_a_seconds_worth() {
+        let mut bucket = Bucket::new();
+        bucket.tokens = 5000.0;
+        bucket.refill(Some(1000.0));
+        assert_eq!(bucket.tokens, 1000.0);
+
+        bucket.updated -= Duration::from_millis(250);
+        bucket.tokens = -500.0;
+        bucket.refill(Some(1000.0));
+        assert!(
+            (-260.0..=-240.0).contains(&bucket.tokens),
+            "{}",
+            bucket.tokens
+        );
+    }
+
+    #[test]
+    fn no_limit_forgives_debt() {
+        let mut bucket = Bucket::new();
+        bucket.tokens = -500.0;
+        assert_eq!(bucket.wait(None), Duration::ZERO);
+        bucket.refill(None);
+        assert_eq!(bucket.tokens, 0.0);
+    }
+
+    #[tokio::test]
+    async fn holds_up_the_next_read_until_paid_for() {
+        let throttle = Throttle::new(limit(1000.0), RateLimit::unlimited());
+        throttle.read(100, 0);
+        let start = Instant::now();
+        throttle.before_read().await;
+        let waited = start.elapsed();
+        assert!(waited >= Duration::from_millis(80), "{waited:?}");
+        assert!(waited < Duration::from_secs(1), "{waited:?}");
+    }
+
+    #[tokio::test]
+    async fn never_waits_without_a_limit() {
+        let throttle = Throttle::new(RateLimit::unlimited(), RateLimit::unlimited());
+        let start = Instant::now();
+        throttle.read(1_000_000, 1_000_000_000);
+        throttle.before_read().await;
+        throttle.write(1_000_000, 1_000_000_000).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn changing_the_limit_speeds_up_waits() {
+        let throttle = std::sync::Arc::new(Throttle::new(RateLimit::unlimited(), limit(1.0)));
+        let start = Instant::now();
+        let write = tokio::spawn({
+            let throttle = throttle.clone();
+            async move { throttle.write(60, 0).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        throttle.set_write_limit(RateLimit::unlimited());
+        write.await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(throttle.write_limit(), RateLimit::unlimited());
+    }
+}
@@ -0,0 +1,233 @@
+use crate::connection::{ConnectionString, Host};
+use mongodb::error::Result as MongoResult;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    net::TcpListener,
+    process::Stdio,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::AsyncReadExt,
+    net::TcpStream,
+    process::{Child, Command},
+};
+use tracing::{debug, info};
+
+/// How long to wait for `ssh` to start forwarding before giving up
+const OPEN_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Reaching a cluster through an SSH jump host, for clusters that can't be reached directly
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SshTunnel {
+    /// Whether to go through the tunnel at all
+    pub enabled: bool,
+    /// The jump host, as `host`, `host:port`, an IPv6 address, or `[address]:port`
+    pub host: String,
+    /// Who to log in to the jump host as, blank for whatever `ssh` picks
+    pub user: String,
+    /// A private key file to log in with, blank to use the SSH agent
+    pub key_file: String,
+    /// The port on this machine the tunnel listens on, 0 picks any free one
+    pub local_port: u16,
+}
+
+/// An open tunnel, which is closed once this is dropped
+pub struct Tunnel {
+    /// The `ssh` doing the forwarding, which is killed when this is dropped
+    _ssh: Child,
+}
+
+impl SshTunnel {
+    /// Opens a tunnel to the first host in `url`, and gives back the connection string to use
+    /// through it. Only one host can go through a tunnel, so it's connected to directly. The
+    /// driver can't skip just the host name check, so with TLS the server's certificate has to
+    /// be good for `127.0.0.1` too, or `tlsAllowInvalidCertificates` has to be set.
+    pub async fn open(&self, url: &ConnectionString) -> MongoResult<(Tunnel, ConnectionString)> {
+        if url.srv {
+            return Err(invalid(
+                "`+srv` connection strings can't go through an SSH tunnel, list the hosts instead",
+            ));
+        }
+        let target = url
+            .hosts
+            .first()
+            .ok_or_else(|| invalid("The connection string has no hosts"))?;
+        if self.host.trim().is_empty() {
+            return Err(invalid("The SSH tunnel needs a jump host"));
+        }
+
+        let local_port = match self.local_port {
+            0 => TcpListener::bind(("127.0.0.1", 0))?.local_addr()?.port(),
+            port => port,
+        };
+        let (jump_host, jump_port) = split_port(self.host.trim())?;
+        // Plans and profiles can come from anyone, and anything starting with `-` would be taken
+        // as another option by ssh, some of which run commands
+        for (value, what) in [
+            (jump_host, "jump host"),
+            (self.user.trim(), "SSH user"),
+            (self.key_file.trim(), "SSH key file"),
+        ] {
+            if value.starts_with('-') {
+                return Err(invalid(&format!("The {what} can't start with `-`")));
+            }
+        }
+
+        let mut ssh = Command::new("ssh");
+        ssh.arg("-N")
+            .args(["-o", "ExitOnForwardFailure=yes"])
+            .args(["-o", "BatchMode=yes"])
+            .arg("-L")
+            .arg(format!(
+                "127.0.0.1:{local_port}:{}:{}",
+                target.host,
+                target.port.unwrap_or(27017)
+            ));
+        if let Some(port) = jump_port {
+            ssh.arg("-p").arg(port.to_string());
+        }
+        if !self.key_file.trim().is_empty() {
+            ssh.args(["-i", self.key_file.trim()]);
+        }
+        ssh.arg("--").arg(match self.user.trim() {
+            "" => jump_host.to_string(),
+            user => format!("{user}@{jump_host}"),
+        });
+
+        info!("Opening an SSH tunnel to {target} through {}", self.host);
+        let mut child = ssh
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|ex| io::Error::new(ex.kind(), format!("Couldn't run ssh: {ex}")))?;
+
+        // There's no telling when ssh has started listening, so keep trying to connect
+        let started = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                let mut stderr = String::new();
+                if let Some(mut pipe) = child.stderr.take() {
+                    pipe.read_to_string(&mut stderr).await?;
+                }
+                return Err(io::Error::other(format!(
+                    "The SSH tunnel closed ({status}): {}",
+                    stderr.trim()
+                ))
+                .into());
+            }
+            if TcpStream::connect(("127.0.0.1", local_port)).await.is_ok() {
+                break;
+            }
+            if started.elapsed() > OPEN_TIMEOUT {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Timed out waiting for the SSH tunnel to open",
+                )
+                .into());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        debug!("SSH tunnel listening on 127.0.0.1:{local_port}");
+
+        let mut tunneled = url.clone();
+        tunneled.hosts = vec![Host {
+            host: "127.0.0.1".into(),
+            port: Some(local_port),
+        }];
+        tunneled.set_option("directConnection", Some("true"));
+
+        Ok((Tunnel { _ssh: child }, tunneled))
+    }
+}
+
+/// Splits the port off a jump host, if it has one. IPv6 addresses are full of colons, so one with
+/// a port has to be in brackets like `[fe80::1]:2222`, which ssh doesn't want.
+fn split_port(host: &str) -> MongoResult<(&str, Option<u16>)> {
+    let parse = |port: &str| {
+        port.parse::<u16>()
+            .map_err(|_| invalid("The jump host's port has to be a number up to 65535"))
+    };
+    if let Some(rest) = host.strip_prefix('[') {
+        let (address, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| invalid("The jump host is missing a `]`"))?;
+        let port = match rest {
+            "" => None,
+            rest => Some(parse(rest.strip_prefix(':').ok_or_else(|| {
+                invalid("Expected `:<port>` after the jump host's `]`")
+            })?)?),
+        };
+        return Ok((address, port));
+    }
+    match host.split_once(':') {
+        // More than one colon is a bare IPv6 address
+        Some((_, rest)) if rest.contains(':') => Ok((host, None)),
+        Some((host, port)) => Ok((host, Some(parse(port)?))),
+        None => Ok((host, None)),
+    }
+}
+
+/// The error for a tunnel that can't be opened as set up
+fn invalid(message: &str) -> mongodb::error::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Opens a tunnel to a made-up cluster, which has to fail before ssh is run
+    async fn open_error(tunnel: SshTunnel) -> String {
+        let url = "mongodb://db.internal:27017".parse().unwrap();
+        match tunnel.open(&url).await {
+            Ok(_) => panic!("Opened a tunnel through {tunnel:?}"),
+            Err(ex) => ex.to_string(),
+        }
+    }
+
+    #[test]
+    fn splits_ports_off_jump_hosts() {
+        for (host, split) in [
+            ("bastion", ("bastion", None)),
+            ("bastion:2222", ("bastion", Some(2222))),
+            ("::1", ("::1", None)),
+            ("fe80::1", ("fe80::1", None)),
+            ("[fe80::1]", ("fe80::1", None)),
+            ("[fe80::1]:2222", ("fe80::1", Some(2222))),
+        ] {
+            assert_eq!(split_port(host).unwrap(), split, "{host}");
+        }
+        for host in [
+            "bastion:ssh",
+            "bastion:70000",
+            "[::1",
+            "[::1]2222",
+            "[::1]:x",
+        ] {
+            assert!(split_port(host).is_err(), "{host}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_options_in_place_of_values() {
+        let tunnel = |host: &str, user: &str, key_file: &str| SshTunnel {
+            enabled: true,
+            host: host.into(),
+            user: user.into(),
+            key_file: key_file.into(),
+            local_port: 0,
+        };
+        let host = open_error(tunnel("-oProxyCommand=sh -c id", "", "")).await;
+        assert!(host.contains("jump host can't start with `-`"), "{host}");
+        let user = open_error(tunnel("jump", "-oProxyCommand=id", "")).await;
+        assert!(user.contains("SSH user can't start with `-`"), "{user}");
+        let key = open_error(tunnel("jump", "", "-oProxyCommand=id")).await;
+        assert!(key.contains("SSH key file can't start with `-`"), "{key}");
+        let port = open_error(tunnel("jump:-oProxyCommand=id", "", "")).await;
+        assert!(port.contains("port has to be a number"), "{port}");
+    }
+}
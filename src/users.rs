@@ -0,0 +1,346 @@
+use crate::endpoint::{Endpoint, Namespace};
+use mongodb::{bson::Document, error::Result as MongoResult};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::{debug, info};
+
+/// How long generated passwords are
+const PASSWORD_LENGTH: usize = 24;
+
+/// A role granted to a user or another role, which can be defined in a different database
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct RoleName {
+    pub role: String,
+    pub db: String,
+}
+
+/// Some actions allowed on a resource, like a database or collection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Privilege {
+    /// What the actions can be done to, like `{ db: "app", collection: "" }`
+    pub resource: Document,
+    pub actions: Vec<String>,
+}
+
+/// A role defined in a database, rather than one built into the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Role {
+    pub role: String,
+    pub db: String,
+    #[serde(default)]
+    pub privileges: Vec<Privilege>,
+    /// Other roles this one has everything from
+    #[serde(default)]
+    pub roles: Vec<RoleName>,
+}
+
+/// A user, without their password as that can't be read back
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    pub user: String,
+    pub db: String,
+    #[serde(default)]
+    pub roles: Vec<RoleName>,
+    pub custom_data: Option<Document>,
+    /// How the user can log in, like `SCRAM-SHA-256`
+    #[serde(default)]
+    pub mechanisms: Vec<String>,
+}
+
+/// The custom roles and users of some databases
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Users {
+    pub roles: Vec<Role>,
+    pub users: Vec<User>,
+}
+
+/// A password made up for a user that wasn't given one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneratedPassword {
+    pub db: String,
+    pub user: String,
+    pub password: String,
+}
+
+/// How copying users and roles went
+#[derive(Debug, Clone)]
+pub struct UsersReport {
+    /// Every role, by its name in the target, and whether it was created
+    pub roles: Vec<(RoleName, MongoResult<()>)>,
+    /// Every user, as `(db, user)` in the target, and whether they were created
+    pub users: Vec<((String, String), MongoResult<()>)>,
+    /// Passwords made up for users that weren't given one
+    pub generated: Vec<GeneratedPassword>,
+}
+
+impl UsersReport {
+    /// Whether every role and user was created
+    pub fn succeeded(&self) -> bool {
+        self.roles.iter().all(|(_, result)| result.is_ok())
+            && self.users.iter().all(|(_, result)| result.is_ok())
+    }
+}
+
+/// Where the databases and collections of a clone are copied to, for [`read`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Renames {
+    /// Which database each source database is copied to. If a database's collections are spread
+    /// over a few, the first one wins.
+    pub dbs: BTreeMap<String, String>,
+    /// Where each collection is copied to
+    pub collections: BTreeMap<Namespace, Namespace>,
+}
+
+impl Renames {
+    /// The database `db` is copied to, or `db` itself if it isn't being copied
+    fn db(&self, db: &str) -> String {
+        self.dbs.get(db).cloned().unwrap_or_else(|| db.to_string())
+    }
+
+    /// The same resource in the target, so a privilege on a collection follows it wherever it's
+    /// copied to. Empty names mean every database or collection, and are left that way.
+    fn resource(&self, mut resource: Document) -> Document {
+        let (Ok(db), collection) = (resource.get_str("db"), resource.get_str("collection")) else {
+            return resource;
+        };
+        if db.is_empty() {
+            return resource;
+        }
+        match collection {
+            Ok(collection) if !collection.is_empty() => {
+                let ns = Namespace::new(db, collection);
+                match self.collections.get(&ns) {
+                    Some(target) => {
+                        resource.insert("db", target.db.clone());
+                        resource.insert("collection", target.collection.clone());
+                    }
+                    None => {
+                        let db = self.db(db);
+                        resource.insert("db", db);
+                    }
+                }
+            }
+            _ => {
+                let db = self.db(db);
+                resource.insert("db", db);
+            }
+        }
+        resource
+    }
+}
+
+/// Where everything in `collections` is copied to, for [`read`]
+pub fn renames(collections: &[(Namespace, Namespace)]) -> Renames {
+    let mut renames = Renames::default();
+    for (source, target) in collections {
+        renames
+            .dbs
+            .entry(source.db.clone())
+            .or_insert_with(|| target.db.clone());
+        renames.collections.insert(source.clone(), target.clone());
+    }
+    renames
+}
+
+/// Reads the custom roles and users in the databases being copied from `source`, renamed to
+/// where they're copied to. Anything they refer to in other databases is left as is.
+pub async fn read(source: &dyn Endpoint, renames: &Renames) -> MongoResult<Users> {
+    let dbs = renames.dbs.keys().cloned().collect::<Vec<_>>();
+    let Users { roles, users } = source.users(&dbs).await?;
+    info!(
+        "Found {} roles and {} users in {} databases",
+        roles.len(),
+        users.len(),
+        dbs.len()
+    );
+
+    let rename_roles = |roles: Vec<RoleName>| {
+        roles
+            .into_iter()
+            .map(|RoleName { role, db }| RoleName {
+                role,
+                db: renames.db(&db),
+            })
+            .collect()
+    };
+
+    Ok(Users {
+        roles: roles
+            .into_iter()
+            .map(|role| Role {
+                db: renames.db(&role.db),
+                privileges: role
+                    .privileges
+                    .into_iter()
+                    .map(|privilege| Privilege {
+                        resource: renames.resource(privilege.resource),
+                        ..privilege
+                    })
+                    .collect(),
+                roles: rename_roles(role.roles),
+                role: role.role,
+            })
+            .collect(),
+        users: users
+            .into_iter()
+            .map(|user| User {
+                db: renames.db(&user.db),
+                roles: rename_roles(user.roles),
+                ..user
+            })
+            .collect(),
+    })
+}
+
+/// Creates `users` on `target`, or updates them if they're already there. Users are given the
+/// password in `passwords` under their `(db, user)`, or a generated one if there isn't one.
+pub async fn create(
+    target: &dyn Endpoint,
+    users: &Users,
+    passwords: &BTreeMap<(String, String), String>,
+) -> UsersReport {
+    // Roles can have other custom roles, which have to be there first, so create them all
+    // without any then fill them in
+    for role in &users.roles {
+        let bare = Role {
+            roles: vec![],
+            ..role.clone()
+        };
+        if let Err(ex) = target.put_role(&bare).await {
+            debug!("Couldn't create role {}@{}: {ex}", role.role, role.db);
+        }
+    }
+    let mut roles = vec![];
+    for role in &users.roles {
+        let name = RoleName {
+            role: role.role.clone(),
+            db: role.db.clone(),
+        };
+        roles.push((name, target.put_role(role).await));
+    }
+
+    let mut created = vec![];
+    let mut generated = vec![];
+    for user in &users.users {
+        let key = (user.db.clone(), user.user.clone());
+        let password = match passwords.get(&key).filter(|password| !password.is_empty()) {
+            Some(password) => password.clone(),
+            None => {
+                let password = generate_password();
+                generated.push(GeneratedPassword {
+                    db: user.db.clone(),
+                    user: user.user.clone(),
+                    password: password.clone(),
+                });
+                password
+            }
+        };
+        created.push((key, target.put_user(user, &password).await));
+    }
+
+    UsersReport {
+        roles,
+        users: created,
+        generated,
+    }
+}
+
+/// Copies the custom roles and users in the databases being copied from `source` to `target`,
+/// see [`read`] and [`create`]
+pub async fn copy(
+    source: &dyn Endpoint,
+    target: &dyn Endpoint,
+    renames: &Renames,
+    passwords: &BTreeMap<(String, String), String>,
+) -> MongoResult<UsersReport> {
+    let users = read(source, renames).await?;
+    Ok(create(target, &users, passwords).await)
+}
+
+/// Makes up a random password
+pub fn generate_password() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(PASSWORD_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Reads passwords from `db,user,password` lines, the way [`write_passwords`] writes them
+pub fn read_passwords(text: &str) -> Result<BTreeMap<(String, String), String>, String> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut parts = line.splitn(3, ',');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(db), Some(user), Some(password)) => {
+                    Ok(((db.to_string(), user.to_string()), password.to_string()))
+                }
+                _ => Err(format!("Expected `db,user,password`, found `{line}`")),
+            }
+        })
+        .collect()
+}
+
+/// Writes out generated passwords as `db,user,password` lines
+pub fn write_passwords(passwords: &[GeneratedPassword]) -> String {
+    passwords
+        .iter()
+        .map(|GeneratedPassword { db, user, password }| format!("{db},{user},{password}\n"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    fn renames() -> Renames {
+        super::renames(&[
+            (
+                Namespace::new("app", "orders"),
+                Namespace::new("staging", "orders"),
+            ),
+            (
+                Namespace::new("app", "users"),
+                Namespace::new("staging", "people"),
+            ),
+            (
+                Namespace::new("app", "audit"),
+                Namespace::new("archive", "app_audit"),
+            ),
+        ])
+    }
+
+    #[test]
+    fn renames_databases_to_the_first_they_go_to() {
+        assert_eq!(
+            renames().dbs,
+            BTreeMap::from([("app".to_string(), "staging".to_string())])
+        );
+    }
+
+    #[test]
+    fn moves_privileges_along_with_collections() {
+        let renames = renames();
+        let resource = |db: &str, collection: &str| doc! { "db": db, "collection": collection };
+        for (source, target) in [
+            // Whole databases
+            (resource("app", ""), resource("staging", "")),
+            // Renamed collections, and ones moved to another database
+            (resource("app", "users"), resource("staging", "people")),
+            (resource("app", "audit"), resource("archive", "app_audit")),
+            // Collections that aren't copied keep their name in the renamed database
+            (resource("app", "sessions"), resource("staging", "sessions")),
+            // Every database, and databases that aren't copied
+            (resource("", "orders"), resource("", "orders")),
+            (resource("other", "orders"), resource("other", "orders")),
+            (doc! { "cluster": true }, doc! { "cluster": true }),
+            (doc! { "db": "app" }, doc! { "db": "staging" }),
+        ] {
+            assert_eq!(renames.resource(source), target);
+        }
+    }
+}
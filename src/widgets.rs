@@ -1,2 +1,17 @@
+pub mod clone_options;
+pub mod clone_progress;
+pub mod cluster_settings;
+pub mod collection_tree;
+pub mod connection_test;
 pub mod db_render;
+pub mod endpoint_address;
+pub mod mapping;
+pub mod profiles;
+pub mod rate_limit;
+pub mod rename_rules;
+pub mod selection;
 pub mod server_address;
+pub mod ssh_tunnel;
+pub mod target_browser;
+pub mod tri_state;
+pub mod users_copy;
@@ -0,0 +1,94 @@
+use crate::widgets::rate_limit::RateLimitEditor;
+use eframe::egui::{CollapsingHeader, ComboBox, DragValue, Grid, Response, Ui, Widget};
+use mongo_cloner::{job::CloneOptions, sharding::Sharding};
+
+/// Settings for how a clone is done
+pub struct CloneOptionsEditor<'a> {
+    options: &'a mut CloneOptions,
+}
+
+impl<'a> CloneOptionsEditor<'a> {
+    pub fn new(options: &'a mut CloneOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        CollapsingHeader::new("Options")
+            .show(ui, |ui| {
+                Grid::new("clone_options").num_columns(2).show(ui, |ui| {
+                    ui.label("Parts to split large collections into");
+                    ui.add(DragValue::new(&mut self.options.partitions).clamp_range(1..=64));
+                    ui.end_row();
+
+                    ui.label("Split collections with at least");
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            DragValue::new(&mut self.options.partition_threshold)
+                                .speed(1000.0)
+                                .clamp_range(0..=u64::MAX),
+                        );
+                        ui.label("documents");
+                    });
+                    ui.end_row();
+
+                    ui.label("Collections copied at once");
+                    ui.add(DragValue::new(&mut self.options.max_collections).clamp_range(1..=256));
+                    ui.end_row();
+
+                    ui.label("Batches copied at once");
+                    ui.add(DragValue::new(&mut self.options.max_batches).clamp_range(1..=1024));
+                    ui.end_row();
+
+                    ui.label("Limit reads to");
+                    ui.add(&mut RateLimitEditor::new(&mut self.options.read_limit));
+                    ui.end_row();
+
+                    ui.label("Limit writes to");
+                    ui.add(&mut RateLimitEditor::new(&mut self.options.write_limit));
+                    ui.end_row();
+
+                    ui.label("Copy as of a single moment");
+                    ui.checkbox(&mut self.options.snapshot, "").on_hover_text(
+                        "Keeps collections consistent with each other on a busy cluster",
+                    );
+                    ui.end_row();
+
+                    ui.label("Shard the target like the source");
+                    ComboBox::from_id_source("sharding")
+                        .selected_text(sharding_label(self.options.sharding))
+                        .show_ui(ui, |ui| {
+                            for sharding in [Sharding::Off, Sharding::Keys, Sharding::Split] {
+                                ui.selectable_value(
+                                    &mut self.options.sharding,
+                                    sharding,
+                                    sharding_label(sharding),
+                                );
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Tries before giving up on transient errors");
+                    ui.add(
+                        DragValue::new(&mut self.options.retry.max_attempts).clamp_range(1..=100),
+                    );
+                    ui.end_row();
+                });
+            })
+            .header_response
+    }
+}
+
+/// How each sharding option is shown
+fn sharding_label(sharding: Sharding) -> &'static str {
+    match sharding {
+        Sharding::Off => "No",
+        Sharding::Keys => "Same shard keys",
+        Sharding::Split => "Same shard keys, chunks and zones",
+    }
+}
+
+impl<'a> Widget for &mut CloneOptionsEditor<'a> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
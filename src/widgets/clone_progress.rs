@@ -0,0 +1,181 @@
+use crate::widgets::rate_limit::RateLimitEditor;
+use eframe::egui::{self, Grid, ProgressBar, Response, Rgba, RichText, ScrollArea, Ui, Widget};
+use futures::StreamExt;
+use mongo_cloner::{
+    connection::redact,
+    job::{CloneJob, CloneReport, ProgressEvent},
+    progress::{CollectionState, HumanBytes, HumanDuration, ProgressTracker},
+    throttle::Throttle,
+};
+use poll_promise::Promise;
+use std::sync::{mpsc, Arc};
+use tokio::runtime::Runtime;
+
+/// A clone job running in the background, and what it has done so far
+pub struct CloneProgress {
+    /// Events forwarded from the job
+    events: mpsc::Receiver<ProgressEvent>,
+    /// How far along the job is
+    tracker: ProgressTracker,
+    /// Lets the job be sped up or slowed down while it runs
+    throttle: Arc<Throttle>,
+    /// The report for the whole job, once it's done
+    report: Promise<CloneReport>,
+}
+
+impl CloneProgress {
+    /// Starts `job` on `rt`, repainting whenever there is progress to show
+    pub fn start(mut job: CloneJob, rt: &Runtime, ctx: &egui::Context) -> Self {
+        let mut job_events = job.subscribe();
+        let throttle = job.throttle();
+        let (events_tx, events) = mpsc::channel();
+        let (sender, report) = Promise::new();
+
+        let repaint = ctx.clone();
+        rt.spawn(async move {
+            while let Some(event) = job_events.next().await {
+                if events_tx.send(event).is_err() {
+                    break;
+                }
+                repaint.request_repaint();
+            }
+        });
+
+        let ctx = ctx.clone();
+        rt.spawn(async move {
+            sender.send(job.run().await);
+            ctx.request_repaint();
+        });
+
+        Self {
+            events,
+            tracker: ProgressTracker::new(),
+            throttle,
+            report,
+        }
+    }
+
+    /// Whether the job has finished
+    pub fn is_done(&self) -> bool {
+        self.report.ready().is_some()
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        for event in self.events.try_iter() {
+            self.tracker.update(&event);
+        }
+        let tracker = &self.tracker;
+
+        ui.vertical(|ui| {
+            let expected = tracker.expected();
+            let throughput = tracker.throughput();
+
+            ui.add(ProgressBar::new(tracker.fraction()).show_percentage());
+            ui.label(format!(
+                "{} / {} documents, {} / {}",
+                tracker.documents(),
+                expected.documents,
+                HumanBytes(tracker.bytes() as f64),
+                HumanBytes(expected.bytes as f64),
+            ));
+            if let (Some(changes), false) = (tracker.catching_up(), tracker.is_finished()) {
+                ui.label(format!(
+                    "Catching up on changes made while copying, {changes} so far"
+                ));
+            }
+            if !tracker.is_finished() {
+                ui.label(format!(
+                    "{:.0} documents/s, {}/s, {} remaining",
+                    throughput.documents_per_sec,
+                    HumanBytes(throughput.bytes_per_sec),
+                    tracker
+                        .eta()
+                        .map(|eta| HumanDuration(eta).to_string())
+                        .unwrap_or_else(|| "unknown time".into()),
+                ));
+
+                ui.horizontal(|ui| {
+                    ui.label("Limit reads to");
+                    let mut limit = self.throttle.read_limit();
+                    if ui.add(&mut RateLimitEditor::new(&mut limit)).changed() {
+                        self.throttle.set_read_limit(limit);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Limit writes to");
+                    let mut limit = self.throttle.write_limit();
+                    if ui.add(&mut RateLimitEditor::new(&mut limit)).changed() {
+                        self.throttle.set_write_limit(limit);
+                    }
+                });
+            }
+
+            ui.separator();
+            ScrollArea::vertical().show(ui, |ui| {
+                Grid::new("clone_progress").num_columns(4).show(ui, |ui| {
+                    for (source, collection) in tracker.collections() {
+                        ui.label(format!("{source} -> {}", collection.target));
+                        ui.add(
+                            ProgressBar::new(collection.fraction())
+                                .desired_width(150.0)
+                                .text(format!(
+                                    "{} / {}",
+                                    collection.documents, collection.expected.documents
+                                )),
+                        );
+                        match &collection.state {
+                            CollectionState::Waiting => ui.label("Waiting"),
+                            CollectionState::Running => ui.spinner(),
+                            CollectionState::Done => ui.label(
+                                RichText::new("Done")
+                                    .color(Rgba::from_srgba_premultiplied(0, 250, 0, 255)),
+                            ),
+                            CollectionState::Failed(err) => ui.label(
+                                RichText::new(format!("Error: {err}"))
+                                    .color(Rgba::from_srgba_premultiplied(250, 0, 0, 255)),
+                            ),
+                        };
+                        if collection.retries > 0 {
+                            ui.label(format!("{} retries", collection.retries));
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+
+            if let Some(report) = self.report.ready() {
+                ui.label(format!(
+                    "Copied {} documents ({}) in {}, with {} retries",
+                    report.documents(),
+                    HumanBytes(report.bytes() as f64),
+                    HumanDuration(report.elapsed),
+                    report.retries(),
+                ));
+                match &report.snapshot {
+                    Some(snapshot) if snapshot.result.is_ok() => {
+                        ui.label(format!(
+                            "Everything is as of cluster time {}, after replaying {} changes",
+                            snapshot.time, snapshot.changes
+                        ));
+                    }
+                    Some(snapshot) => {
+                        if let Err(ex) = &snapshot.result {
+                            ui.label(
+                                RichText::new(redact(&format!("Error catching up: {ex}")))
+                                    .color(Rgba::from_srgba_premultiplied(250, 0, 0, 255)),
+                            );
+                        }
+                    }
+                    None => {}
+                }
+            }
+        })
+        .response
+    }
+}
+
+impl Widget for &mut CloneProgress {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
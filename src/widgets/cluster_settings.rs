@@ -0,0 +1,210 @@
+use eframe::egui::{CollapsingHeader, ComboBox, DragValue, Grid, Response, TextEdit, Ui, Widget};
+use mongo_cloner::db::{ClusterSettings, ReadLevel, ReadMode, WriteAck};
+use std::{collections::BTreeMap, time::Duration};
+
+/// Read preference, read concern and write concern for a cluster
+pub struct ClusterSettingsEditor<'a> {
+    settings: &'a mut ClusterSettings,
+}
+
+impl<'a> ClusterSettingsEditor<'a> {
+    pub fn new(settings: &'a mut ClusterSettings) -> Self {
+        Self { settings }
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        CollapsingHeader::new("Read and write settings")
+            .show(ui, |ui| {
+                Grid::new("cluster_settings").num_columns(2).show(ui, |ui| {
+                    ui.label("Read from");
+                    ComboBox::from_id_source("read_mode")
+                        .selected_text(format!("{:?}", self.settings.read_mode))
+                        .show_ui(ui, |ui| {
+                            for mode in [
+                                ReadMode::Default,
+                                ReadMode::Primary,
+                                ReadMode::PrimaryPreferred,
+                                ReadMode::Secondary,
+                                ReadMode::SecondaryPreferred,
+                                ReadMode::Nearest,
+                            ] {
+                                ui.selectable_value(
+                                    &mut self.settings.read_mode,
+                                    mode,
+                                    format!("{mode:?}"),
+                                );
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Member tags");
+                    ui.add_enabled_ui(
+                        !matches!(
+                            self.settings.read_mode,
+                            ReadMode::Default | ReadMode::Primary
+                        ),
+                        |ui| self.tags(ui),
+                    );
+                    ui.end_row();
+
+                    ui.label("Read concern");
+                    ComboBox::from_id_source("read_concern")
+                        .selected_text(format!("{:?}", self.settings.read_concern))
+                        .show_ui(ui, |ui| {
+                            for level in [
+                                ReadLevel::Default,
+                                ReadLevel::Local,
+                                ReadLevel::Majority,
+                                ReadLevel::Snapshot,
+                            ] {
+                                ui.selectable_value(
+                                    &mut self.settings.read_concern,
+                                    level,
+                                    format!("{level:?}"),
+                                );
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Write to");
+                    ui.horizontal(|ui| self.write_ack(ui));
+                    ui.end_row();
+
+                    ui.label("Wait for the journal");
+                    ui.checkbox(&mut self.settings.journal, "");
+                    ui.end_row();
+
+                    ui.label("Write timeout");
+                    ui.horizontal(|ui| {
+                        let mut enabled = self.settings.write_timeout.is_some();
+                        ui.checkbox(&mut enabled, "");
+                        let mut millis = self
+                            .settings
+                            .write_timeout
+                            .map_or(10_000, |timeout| timeout.as_millis() as u64);
+                        ui.add_enabled(
+                            enabled,
+                            DragValue::new(&mut millis)
+                                .speed(100.0)
+                                .clamp_range(1..=u64::MAX)
+                                .suffix(" ms"),
+                        );
+                        self.settings.write_timeout =
+                            enabled.then(|| Duration::from_millis(millis));
+                    });
+                    ui.end_row();
+                });
+            })
+            .header_response
+    }
+
+    /// The tag sets to pick members with, typed out like `dc:east,use:reporting; dc:west`
+    fn tags(&mut self, ui: &mut Ui) {
+        // Keep what's being typed while it's half done and doesn't parse yet
+        let id = ui.make_persistent_id("read_tags");
+        let focused = ui.memory().has_focus(id);
+        let stored = ui.data().get_temp::<String>(id);
+        let mut text = stored
+            .filter(|text| focused || parse_tags(text).as_ref() == Ok(&self.settings.read_tags))
+            .unwrap_or_else(|| format_tags(&self.settings.read_tags));
+
+        let response = ui.add(
+            TextEdit::singleline(&mut text)
+                .id(id)
+                .hint_text("dc:east,use:reporting; dc:west"),
+        );
+        if response.changed() {
+            if let Ok(tags) = parse_tags(&text) {
+                self.settings.read_tags = tags;
+            }
+        }
+        if parse_tags(&text).is_err() {
+            response
+                .on_hover_text("Expected `key:value` pairs split by `,`, with sets split by `;`");
+        }
+        ui.data().insert_temp(id, text);
+    }
+
+    /// How many members a write has to reach
+    fn write_ack(&mut self, ui: &mut Ui) {
+        let selected = match &self.settings.write_ack {
+            WriteAck::Default => "Default",
+            WriteAck::Nodes(_) => "Members",
+            WriteAck::Majority => "Majority",
+            WriteAck::Tag(_) => "Tag",
+        };
+        ComboBox::from_id_source("write_ack")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                let ack = &mut self.settings.write_ack;
+                if ui
+                    .selectable_label(selected == "Default", "Default")
+                    .clicked()
+                {
+                    *ack = WriteAck::Default;
+                }
+                if ui
+                    .selectable_label(selected == "Members", "Members")
+                    .clicked()
+                {
+                    *ack = WriteAck::Nodes(1);
+                }
+                if ui
+                    .selectable_label(selected == "Majority", "Majority")
+                    .clicked()
+                {
+                    *ack = WriteAck::Majority;
+                }
+                if ui.selectable_label(selected == "Tag", "Tag").clicked() {
+                    *ack = WriteAck::Tag(String::new());
+                }
+            });
+
+        match &mut self.settings.write_ack {
+            WriteAck::Nodes(nodes) => {
+                ui.add(DragValue::new(nodes).clamp_range(1..=50));
+            }
+            WriteAck::Tag(tag) => {
+                ui.add(TextEdit::singleline(tag).hint_text("Custom write concern"));
+            }
+            WriteAck::Default | WriteAck::Majority => {}
+        }
+    }
+}
+
+impl<'a> Widget for &mut ClusterSettingsEditor<'a> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
+
+/// Parses tag sets like `dc:east,use:reporting; dc:west`
+fn parse_tags(text: &str) -> Result<Vec<BTreeMap<String, String>>, String> {
+    text.split(';')
+        .map(str::trim)
+        .filter(|set| !set.is_empty())
+        .map(|set| {
+            set.split(',')
+                .map(|tag| {
+                    let (key, value) = tag
+                        .split_once(':')
+                        .ok_or_else(|| format!("Expected `key:value`, found `{tag}`"))?;
+                    Ok((key.trim().to_string(), value.trim().to_string()))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Writes out tag sets the way [`parse_tags`] reads them
+fn format_tags(tags: &[BTreeMap<String, String>]) -> String {
+    tags.iter()
+        .map(|set| {
+            set.iter()
+                .map(|(key, value)| format!("{key}:{value}"))
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
@@ -0,0 +1,194 @@
+use super::{
+    db_render::DbDisplay,
+    tri_state::{Selected, TriStateCheckbox},
+};
+use eframe::egui::{
+    Align, Layout, Response, RichText, ScrollArea, Sense, TextEdit, TextStyle, Ui, Vec2, Widget,
+};
+use mongo_cloner::{
+    db::{CollectionKind, Db},
+    endpoint::Namespace,
+};
+use std::collections::BTreeSet;
+
+/// A line of the tree, by the index of its database and collection
+#[derive(Debug, Clone, Copy, Hash)]
+enum Row {
+    Db(usize),
+    Collection(usize, usize),
+    /// The file filter under a bucket
+    FileFilter(usize, usize),
+}
+
+/// What the tree is narrowed down to, kept between frames
+#[derive(Debug, Clone, Default)]
+struct TreeFilter {
+    /// Text the names of databases or collections have to contain
+    search: String,
+    only_selected: bool,
+    /// Only collections going to a different namespace than they're in
+    only_renamed: bool,
+    /// The databases that have been closed
+    collapsed: BTreeSet<String>,
+}
+
+impl TreeFilter {
+    /// The rows to show out of `dbs`, leaving out databases with no collections left
+    fn rows(&self, dbs: &[Db]) -> Vec<Row> {
+        let search = self.search.trim().to_lowercase();
+        let matches = |name: &str| name.to_lowercase().contains(&search);
+
+        let mut rows = vec![];
+        for (i, db) in dbs.iter().enumerate() {
+            let db_name = &db.db_name;
+            let db_matches = matches(&db_name.name) || matches(&db_name.rename);
+            let collections = db
+                .collections
+                .iter()
+                .enumerate()
+                .filter(|(_, collection)| {
+                    (!self.only_selected || collection.selected)
+                        && (!self.only_renamed
+                            || collection.target_db(db_name) != db_name.name
+                            || collection.rename != collection.name)
+                        && (db_matches
+                            || matches(&collection.name)
+                            || matches(&collection.rename)
+                            || matches(&format!("{}.{}", db_name.name, collection.name)))
+                })
+                .map(|(j, collection)| (j, collection.kind))
+                .collect::<Vec<_>>();
+            if collections.is_empty() {
+                continue;
+            }
+
+            rows.push(Row::Db(i));
+            if self.collapsed.contains(&db_name.name) {
+                continue;
+            }
+            for (j, kind) in collections {
+                rows.push(Row::Collection(i, j));
+                if kind == CollectionKind::Bucket {
+                    rows.push(Row::FileFilter(i, j));
+                }
+            }
+        }
+        rows
+    }
+}
+
+/// Every database and collection in the source, narrowed down by a search and filters. Only the
+/// rows scrolled into view are laid out, so it keeps up with thousands of collections.
+pub struct CollectionTree<'a> {
+    dbs: &'a mut [Db],
+    /// What's already in the target, so collections that would overwrite it can be pointed out
+    existing: &'a BTreeSet<Namespace>,
+}
+
+impl<'a> CollectionTree<'a> {
+    pub fn new(dbs: &'a mut [Db], existing: &'a BTreeSet<Namespace>) -> Self {
+        Self { dbs, existing }
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        ui.vertical(|ui| {
+            let id = ui.make_persistent_id("collection_tree");
+            let mut filter = ui.data().get_temp::<TreeFilter>(id).unwrap_or_default();
+
+            ui.horizontal(|ui| {
+                everything(ui, self.dbs);
+                ui.add(
+                    TextEdit::singleline(&mut filter.search)
+                        .hint_text("Search databases and collections"),
+                );
+                if !filter.search.is_empty() && ui.small_button("✖").clicked() {
+                    filter.search.clear();
+                }
+                ui.checkbox(&mut filter.only_selected, "Only selected");
+                ui.checkbox(&mut filter.only_renamed, "Only renamed")
+                    .on_hover_text("Only collections going somewhere other than where they are");
+            });
+
+            let rows = filter.rows(self.dbs);
+            if rows.is_empty() {
+                ui.label("Nothing matches");
+            }
+
+            // Every row is exactly as tall as a text box, so it's known where each one is without
+            // laying them all out. Whatever doesn't fit is cut off rather than wrapped.
+            let row_height = ui.text_style_height(&TextStyle::Body) + 8.0;
+            ScrollArea::vertical()
+                .id_source("collection_tree_rows")
+                .show_rows(ui, row_height, rows.len(), |ui, range| {
+                    ui.with_layout(Layout::top_down(Align::Min), |ui| {
+                        for row in &rows[range] {
+                            ui.push_id(row, |ui| {
+                                let size = Vec2::new(ui.available_width(), row_height);
+                                let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+                                let mut ui = ui.child_ui(
+                                    rect,
+                                    Layout::left_to_right().with_cross_align(Align::Center),
+                                );
+                                ui.set_clip_rect(rect.intersect(ui.clip_rect()));
+                                ui.style_mut().wrap = Some(false);
+                                self.row(&mut ui, *row, &mut filter.collapsed);
+                            });
+                        }
+                    });
+                });
+
+            ui.data().insert_temp(id, filter);
+        })
+        .response
+    }
+
+    /// Lays out one row
+    fn row(&mut self, ui: &mut Ui, row: Row, collapsed: &mut BTreeSet<String>) {
+        match row {
+            Row::Db(i) => {
+                let name = self.dbs[i].db_name.name.clone();
+                let mut open = !collapsed.contains(&name);
+                DbDisplay::new(&mut self.dbs[i], self.existing).header(ui, &mut open);
+                if open {
+                    collapsed.remove(&name);
+                } else {
+                    collapsed.insert(name);
+                }
+            }
+            Row::Collection(i, j) => {
+                DbDisplay::new(&mut self.dbs[i], self.existing).collection(ui, j)
+            }
+            Row::FileFilter(i, j) => {
+                DbDisplay::new(&mut self.dbs[i], self.existing).file_filter(ui, j)
+            }
+        }
+    }
+}
+
+impl<'a> Widget for &mut CollectionTree<'a> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
+
+/// A checkbox for every collection in every database, and how many are selected
+fn everything(ui: &mut Ui, dbs: &mut [Db]) {
+    let collections = || dbs.iter().flat_map(|db| &db.collections);
+    let total = collections().count();
+    let mut state = Selected::of(collections().filter(|x| x.selected).count(), total);
+    if ui
+        .add(&mut TriStateCheckbox::new(&mut state, "Everything"))
+        .changed()
+    {
+        let selected = state == Selected::All;
+        for collection in dbs.iter_mut().flat_map(|db| &mut db.collections) {
+            collection.selected = selected;
+        }
+    }
+    let selected = dbs
+        .iter()
+        .flat_map(|db| &db.collections)
+        .filter(|x| x.selected)
+        .count();
+    ui.label(RichText::new(format!("{selected}/{total} selected")).weak());
+}
@@ -0,0 +1,118 @@
+use eframe::egui::{self, Grid, Response, Rgba, Ui, Widget};
+use mongo_cloner::{
+    connection::{redact, ConnectionString},
+    diagnostics::{Access, Diagnosis},
+    endpoint::EndpointConfig,
+};
+use mongodb::error::Result as MongoResult;
+use poll_promise::Promise;
+use tokio::runtime::Runtime;
+
+/// Pings a cluster and shows what it is and whether it can be used as a source or target
+pub struct ConnectionTest {
+    /// The connection string that was tested, so the results can be thrown out once it changes
+    pub url: ConnectionString,
+    /// What it's being tested for
+    access: Access,
+    /// What the test found, once it's done
+    diagnosis: Promise<MongoResult<Diagnosis>>,
+}
+
+impl ConnectionTest {
+    /// Starts testing whether the cluster `config` points to has what it needs for `access`
+    pub fn start(
+        config: &EndpointConfig,
+        access: Access,
+        rt: &Runtime,
+        ctx: &egui::Context,
+    ) -> Self {
+        let (sender, diagnosis) = Promise::new();
+        let url = config.url.clone();
+        let config = config.clone();
+        let ctx = ctx.clone();
+        let needs = access.clone();
+        rt.spawn(async move {
+            let diagnosis = match config.connect().await {
+                Ok(endpoint) => endpoint.diagnose(needs).await,
+                Err(ex) => Err(ex),
+            };
+            sender.send(diagnosis);
+            ctx.request_repaint();
+        });
+
+        Self {
+            url,
+            access,
+            diagnosis,
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        ui.vertical(|ui| match self.diagnosis.ready() {
+            None => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Testing connection");
+                });
+            }
+            Some(Err(ex)) => {
+                ui.colored_label(Rgba::RED, redact(&format!("Couldn't connect: {ex}")));
+            }
+            Some(Ok(diagnosis)) => {
+                Grid::new("connection_test").num_columns(2).show(ui, |ui| {
+                    ui.label("Round trip");
+                    ui.label(format!("{} ms", diagnosis.latency.as_millis()));
+                    ui.end_row();
+
+                    ui.label("Version");
+                    ui.label(&diagnosis.version);
+                    ui.end_row();
+
+                    ui.label("Topology");
+                    ui.label(diagnosis.topology.to_string());
+                    ui.end_row();
+
+                    ui.label("Logged in as");
+                    if diagnosis.users.is_empty() {
+                        ui.label("Nobody");
+                    } else {
+                        ui.label(diagnosis.users.join(", "));
+                    }
+                    ui.end_row();
+
+                    ui.label("Roles");
+                    let roles = diagnosis
+                        .roles
+                        .iter()
+                        .map(|role| format!("{}@{}", role.role, role.db))
+                        .collect::<Vec<_>>();
+                    ui.label(roles.join(", "));
+                    ui.end_row();
+                });
+
+                let needed = match self.access {
+                    Access::Read { .. } => "read",
+                    Access::Write => "write",
+                };
+                if diagnosis.missing.is_empty() {
+                    ui.colored_label(Rgba::GREEN, format!("Has what it needs to {needed}"));
+                } else {
+                    ui.colored_label(
+                        Rgba::RED,
+                        format!("Can't {needed} everything, it's missing"),
+                    );
+                    for missing in &diagnosis.missing {
+                        ui.colored_label(Rgba::RED, format!("• {missing}"));
+                    }
+                }
+            }
+        })
+        .response
+    }
+}
+
+impl Widget for &mut ConnectionTest {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
@@ -1,59 +1,129 @@
-use crate::db::Db;
+use super::tri_state::{Selected, TriStateCheckbox};
 use eframe::{
-    egui::{CollapsingHeader, Response, RichText, Ui, Widget},
+    egui::{Rgba, RichText, TextEdit, Ui},
     epaint::FontFamily,
 };
+use mongo_cloner::{
+    db::{CollectionKind, Db},
+    endpoint::Namespace,
+    gridfs,
+};
+use std::collections::BTreeSet;
+
+/// How far collections are indented under their database
+const INDENT: f32 = 16.0;
 
+/// The rows of a database in the collection tree: a header, then a row for each collection, and
+/// one more for each bucket's file filter
 pub struct DbDisplay<'a> {
     db: &'a mut Db,
+    /// What's already in the target, so collections that would overwrite it can be pointed out
+    existing: &'a BTreeSet<Namespace>,
 }
 
 impl<'a> DbDisplay<'a> {
-    pub fn new(db: &'a mut Db) -> Self {
-        Self { db }
+    pub fn new(db: &'a mut Db, existing: &'a BTreeSet<Namespace>) -> Self {
+        Self { db, existing }
+    }
+
+    /// The database's name and rename, with a button to open or close it and a checkbox for all
+    /// of its collections. How many are selected is shown while it's closed.
+    pub fn header(&mut self, ui: &mut Ui, open: &mut bool) {
+        let arrow = if *open { "⏷" } else { "⏵" };
+        if ui.small_button(arrow).clicked() {
+            *open = !*open;
+        }
+        let collections = &mut self.db.collections;
+        let total = collections.len();
+        let mut state = Selected::of(collections.iter().filter(|x| x.selected).count(), total);
+        let name = RichText::new(&self.db.db_name.name).strong();
+        // Rows are cut off at the side, so long names can be read by hovering over them
+        if ui
+            .add(&mut TriStateCheckbox::new(&mut state, name))
+            .on_hover_text(&self.db.db_name.name)
+            .changed()
+        {
+            let selected = state == Selected::All;
+            collections.iter_mut().for_each(|x| x.selected = selected);
+        }
+        if !*open {
+            let selected = collections.iter().filter(|x| x.selected).count();
+            ui.label(RichText::new(format!("{selected}/{total} selected")).weak());
+        }
+        ui.label("Rename to: ");
+        if ui
+            .text_edit_singleline(&mut self.db.db_name.rename)
+            .changed()
+        {
+            self.db.db_name.edited = true;
+        }
+        if self.db.db_name.edited && unedit_button(ui) {
+            self.db.db_name.rename = self.db.db_name.name.clone();
+            self.db.db_name.edited = false;
+        }
     }
 
-    pub fn show(&mut self, ui: &mut Ui) -> Response {
-        ui.horizontal(|ui| {
-            CollapsingHeader::new(self.db.db_name.name.as_str())
-                .default_open(true)
-                .show(ui, |ui| {
-                    ui.vertical(|ui| {
-                        ui.horizontal(|ui| {
-                            ui.label("Rename to: ");
-                            ui.text_edit_singleline(&mut self.db.db_name.rename);
-                        });
-                        ui.horizontal(|ui| {
-                            if ui.button("All").clicked() {
-                                self.db
-                                    .collections
-                                    .iter_mut()
-                                    .for_each(|f| f.selected = true);
-                            }
+    /// The `i`th collection, whether it's selected and what it's renamed to
+    pub fn collection(&mut self, ui: &mut Ui, i: usize) {
+        let collection = &mut self.db.collections[i];
+        ui.add_space(INDENT);
+        ui.checkbox(&mut collection.selected, collection.name.as_str())
+            .on_hover_text(format!("{}.{}", self.db.db_name.name, collection.name));
+        if collection.kind == CollectionKind::Bucket {
+            ui.label(RichText::new("GridFS").weak());
+        }
+        ui.label(RichText::new("->").family(FontFamily::Monospace));
+        if ui.text_edit_singleline(&mut collection.rename).changed() {
+            collection.edited = true;
+        }
+        if collection.edited && unedit_button(ui) {
+            collection.rename = collection.name.clone();
+            collection.edited = false;
+        }
+        if let Some(db) = &collection.rename_db {
+            ui.label(format!("in {db}"));
+            if ui
+                .small_button("✖")
+                .on_hover_text("Go with the rest of the database")
+                .clicked()
+            {
+                collection.rename_db = None;
+            }
+        }
+        let overwrites = collection
+            .targets(&self.db.db_name)
+            .iter()
+            .any(|target| self.existing.contains(target));
+        if collection.selected && overwrites {
+            ui.colored_label(
+                Rgba::from_srgba_premultiplied(250, 170, 0, 255),
+                "Already in the target",
+            )
+            .on_hover_text("Cloning will overwrite what's there");
+        }
+    }
 
-                            if ui.button("None").clicked() {
-                                self.db
-                                    .collections
-                                    .iter_mut()
-                                    .for_each(|f| f.selected = false);
-                            }
-                        });
-                        for collection in self.db.collections.iter_mut() {
-                            ui.horizontal(|ui| {
-                                ui.checkbox(&mut collection.selected, collection.name.as_str());
-                                ui.label(RichText::new("->").family(FontFamily::Monospace));
-                                ui.text_edit_singleline(&mut collection.rename);
-                            });
-                        }
-                    });
-                })
-        })
-        .response
+    /// Which files of the `i`th collection, a bucket, to copy, as a query on its files collection
+    pub fn file_filter(&mut self, ui: &mut Ui, i: usize) {
+        let bucket = &mut self.db.collections[i];
+        ui.add_space(INDENT * 2.5);
+        ui.label("Files matching");
+        let response = ui.add_enabled(
+            bucket.selected,
+            TextEdit::singleline(&mut bucket.file_filter)
+                .hint_text(r#"Every file, or like {"metadata.kind": "avatar"}"#),
+        );
+        if !bucket.file_filter.trim().is_empty() {
+            if let Err(ex) = gridfs::parse_filter(&bucket.file_filter) {
+                response.on_hover_text(ex);
+            }
+        }
     }
 }
 
-impl<'a> Widget for &mut DbDisplay<'a> {
-    fn ui(self, ui: &mut Ui) -> Response {
-        self.show(ui)
-    }
+/// A button to undo renaming something by hand, so rename rules can rename it again
+fn unedit_button(ui: &mut Ui) -> bool {
+    ui.small_button("↺")
+        .on_hover_text("Undo renaming by hand, so rename rules apply to it")
+        .clicked()
 }
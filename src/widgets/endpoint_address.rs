@@ -0,0 +1,62 @@
+use crate::widgets::{
+    cluster_settings::ClusterSettingsEditor, server_address::ServerAddress,
+    ssh_tunnel::SshTunnelEditor,
+};
+use eframe::egui::{Response, TextEdit, Ui, Widget};
+use mongo_cloner::endpoint::{local::LocalFormat, EndpointConfig, EndpointKind};
+
+/// Lets the user pick between a cluster and a local store, and enter the address of either
+pub struct EndpointAddress<'a> {
+    config: &'a mut EndpointConfig,
+    /// Set to whether the address can be connected to, which a bad connection string can't
+    valid: &'a mut bool,
+}
+
+impl<'a> EndpointAddress<'a> {
+    pub fn new(config: &'a mut EndpointConfig, valid: &'a mut bool) -> Self {
+        Self { config, valid }
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        ui.vertical_centered(|ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.config.kind, EndpointKind::Cluster, "Cluster");
+                ui.radio_value(
+                    &mut self.config.kind,
+                    EndpointKind::Local,
+                    "Local directory",
+                );
+            });
+
+            match self.config.kind {
+                EndpointKind::Cluster => {
+                    ui.add(&mut ServerAddress::new(&mut self.config.url, self.valid));
+                    ui.add(&mut SshTunnelEditor::new(&mut self.config.tunnel));
+                    ui.add(&mut ClusterSettingsEditor::new(&mut self.config.cluster));
+                }
+                EndpointKind::Local => {
+                    *self.valid = true;
+                    ui.horizontal(|ui| {
+                        ui.label("Directory: ");
+                        ui.add(
+                            TextEdit::singleline(&mut self.config.path)
+                                .desired_width(ui.available_width()),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Format: ");
+                        ui.radio_value(&mut self.config.format, LocalFormat::Bson, "BSON");
+                        ui.radio_value(&mut self.config.format, LocalFormat::Json, "JSON");
+                    });
+                }
+            }
+        })
+        .response
+    }
+}
+
+impl<'a> Widget for &mut EndpointAddress<'a> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
@@ -0,0 +1,284 @@
+use eframe::egui::{
+    self, Align, Label, Layout, Pos2, Rect, Response, RichText, ScrollArea, Sense, Stroke,
+    TextEdit, TextStyle, Ui, Vec2, Widget,
+};
+use mongo_cloner::db::Db;
+use std::{collections::BTreeSet, iter};
+
+/// A row on the source side, which can be dragged onto a target database
+#[derive(Debug, Clone, Copy)]
+enum Dragged {
+    /// A whole database, by its index
+    Db(usize),
+    /// A single collection, by the index of its database and its own
+    Collection(usize, usize),
+}
+
+/// Maps source databases and collections to target databases by dragging them across, with lines
+/// showing where everything goes. It edits the same renames as typing them in does.
+pub struct MappingView<'a> {
+    dbs: &'a mut [Db],
+    /// The databases already in the target, which can be dragged onto too
+    existing: &'a BTreeSet<String>,
+}
+
+impl<'a> MappingView<'a> {
+    pub fn new(dbs: &'a mut [Db], existing: &'a BTreeSet<String>) -> Self {
+        Self { dbs, existing }
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        ui.vertical(|ui| self.mapping(ui)).response
+    }
+
+    /// Both sides, the lines between them, and whatever was dropped this frame. Only the rows in
+    /// view are laid out, so it stays quick however many collections there are.
+    fn mapping(&mut self, ui: &mut Ui) {
+        // Everything the source goes to, as well as what's already there
+        let mut targets = self.existing.clone();
+        for db in self.dbs.iter() {
+            targets.insert(db.db_name.rename.clone());
+            for collection in &db.collections {
+                targets.insert(collection.target_db(&db.db_name).to_string());
+            }
+        }
+        targets.retain(|target| !target.trim().is_empty());
+        let targets = targets.into_iter().collect::<Vec<_>>();
+
+        let sources = self
+            .dbs
+            .iter()
+            .enumerate()
+            .flat_map(|(i, db)| {
+                iter::once(Dragged::Db(i))
+                    .chain((0..db.collections.len()).map(move |j| Dragged::Collection(i, j)))
+            })
+            .collect::<Vec<_>>();
+
+        let new_db_id = ui.make_persistent_id("new_target_db");
+        let mut new_db = ui.data().get_temp::<String>(new_db_id).unwrap_or_default();
+        // What's being dragged is kept here rather than in its row, which might scroll out of view
+        let dragged_id = ui.make_persistent_id("mapping_dragged");
+        let mut dragged = ui.data().get_temp::<Dragged>(dragged_id);
+
+        ui.columns(2, |columns| {
+            columns[0].label(RichText::new("Source").strong());
+            columns[1].label(RichText::new("Target").strong());
+        });
+
+        // The target side has a row for each database, then one to type a new one in
+        let rows = sources.len().max(targets.len() + 1);
+        let row_height = ui.text_style_height(&TextStyle::Body) + 8.0;
+        let pitch = row_height + ui.spacing().item_spacing.y;
+        let target_rects = ScrollArea::vertical()
+            .id_source("mapping_rows")
+            .show_rows(ui, row_height, rows, |ui, range| {
+                let mut first = None;
+                let mut target_rects = vec![];
+                for row in range {
+                    let size = Vec2::new(ui.available_width(), row_height);
+                    let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+                    first.get_or_insert((row, rect));
+                    let (left, right) = halves(rect);
+
+                    if let Some(&source) = sources.get(row) {
+                        self.source_row(ui, left, source, &mut dragged);
+                    }
+                    if let Some(target) = targets.get(row) {
+                        let text = if self.existing.contains(target) {
+                            RichText::new(target)
+                        } else {
+                            RichText::new(format!("{target} (new)")).italics()
+                        };
+                        drop_target(ui, right, dragged.is_some(), |ui| {
+                            ui.label(text);
+                        });
+                        target_rects.push((target.clone(), right));
+                    } else if row == targets.len() {
+                        drop_target(ui, right, dragged.is_some(), |ui| {
+                            ui.add(
+                                TextEdit::singleline(&mut new_db)
+                                    .hint_text("Drop on a new database"),
+                            );
+                        });
+                        if !new_db.trim().is_empty() {
+                            target_rects.push((new_db.trim().to_string(), right));
+                        }
+                    }
+                }
+
+                // Rows out of view are where they'd be laid out, so lines crossing the view are
+                // still drawn
+                if let Some((first_row, first_rect)) = first {
+                    let row_rect = |row: usize| {
+                        let offset = (row as f32 - first_row as f32) * pitch;
+                        halves(first_rect.translate(Vec2::new(0.0, offset)))
+                    };
+                    self.lines(ui, &sources, &targets, row_rect);
+                }
+                target_rects
+            })
+            .inner;
+
+        if let Some(source) = dragged {
+            let name = match source {
+                Dragged::Db(i) => &self.dbs[i].db_name.name,
+                Dragged::Collection(i, j) => &self.dbs[i].collections[j].name,
+            };
+            ui.output().cursor_icon = egui::CursorIcon::Grabbing;
+            egui::show_tooltip_at_pointer(ui.ctx(), dragged_id, |ui| {
+                ui.label(name);
+            });
+        }
+
+        // Whatever was let go of over a target database goes there
+        if ui.input().pointer.any_released() {
+            let pos = ui.input().pointer.interact_pos();
+            if let (Some(source), Some(pos)) = (dragged.take(), pos) {
+                if let Some((target, _)) = target_rects.iter().find(|(_, rect)| rect.contains(pos))
+                {
+                    self.drop(source, target);
+                    if *target == new_db.trim() {
+                        new_db.clear();
+                    }
+                }
+            }
+        }
+
+        let mut data = ui.data();
+        data.insert_temp(new_db_id, new_db);
+        match dragged {
+            Some(source) => data.insert_temp(dragged_id, source),
+            None => data.remove::<Dragged>(dragged_id),
+        }
+    }
+
+    /// A database, or an indented collection, which can be dragged
+    fn source_row(&self, ui: &mut Ui, rect: Rect, source: Dragged, dragged: &mut Option<Dragged>) {
+        let (name, strong, indent) = match source {
+            Dragged::Db(i) => (&self.dbs[i].db_name.name, true, 0.0),
+            Dragged::Collection(i, j) => {
+                let collection = &self.dbs[i].collections[j];
+                (&collection.name, collection.selected, 16.0)
+            }
+        };
+        let text = if strong {
+            RichText::new(name)
+        } else {
+            RichText::new(name).weak()
+        };
+        let mut rect = rect;
+        rect.min.x += indent;
+        let response = cell(ui, rect)
+            .add(Label::new(text).sense(Sense::drag()))
+            .on_hover_cursor(egui::CursorIcon::Grab);
+        if response.drag_started() {
+            *dragged = Some(source);
+        }
+    }
+
+    /// Lines from each database, and each collection going somewhere else, to where it goes
+    fn lines(
+        &self,
+        ui: &Ui,
+        sources: &[Dragged],
+        targets: &[String],
+        row_rect: impl Fn(usize) -> (Rect, Rect),
+    ) {
+        let visible = ui.clip_rect();
+        for (row, source) in sources.iter().enumerate() {
+            let (target, stroke) = match *source {
+                Dragged::Db(i) => (
+                    self.dbs[i].db_name.rename.as_str(),
+                    Stroke::new(1.5, ui.visuals().hyperlink_color),
+                ),
+                Dragged::Collection(i, j) => {
+                    let db = &self.dbs[i];
+                    let collection = &db.collections[j];
+                    if collection.rename_db.is_none() {
+                        continue;
+                    }
+                    let color = if collection.selected {
+                        ui.visuals().hyperlink_color
+                    } else {
+                        ui.visuals().weak_text_color()
+                    };
+                    (collection.target_db(&db.db_name), Stroke::new(1.0, color))
+                }
+            };
+            let to = match targets.iter().position(|name| name == target) {
+                Some(to) => to,
+                None => continue,
+            };
+            let from = row_rect(row).0.right_center();
+            let to = row_rect(to).1.left_center();
+            if from.y.max(to.y) < visible.top() || from.y.min(to.y) > visible.bottom() {
+                continue;
+            }
+            ui.painter().line_segment([from, to], stroke);
+        }
+    }
+
+    /// Sends what was dragged to `target`
+    fn drop(&mut self, source: Dragged, target: &str) {
+        match source {
+            Dragged::Db(i) => {
+                let db = &mut self.dbs[i];
+                db.db_name.rename = target.to_string();
+                db.db_name.edited = true;
+                // Collections sent elsewhere stay there, only the ones now going with the rest of
+                // the database don't need sending anymore
+                for collection in &mut db.collections {
+                    if collection.rename_db.as_deref() == Some(target) {
+                        collection.rename_db = None;
+                    }
+                }
+            }
+            Dragged::Collection(i, j) => {
+                let db = &mut self.dbs[i];
+                db.collections[j].rename_db =
+                    (target != db.db_name.rename).then(|| target.to_string());
+            }
+        }
+    }
+}
+
+impl<'a> Widget for &mut MappingView<'a> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
+
+/// The source and target sides of a row, with a gap between them for the lines
+fn halves(rect: Rect) -> (Rect, Rect) {
+    let gap = (rect.width() / 4.0).min(80.0);
+    let width = (rect.width() - gap) / 2.0;
+    let left = Rect::from_min_size(rect.min, Vec2::new(width, rect.height()));
+    let right = Rect::from_min_size(
+        Pos2::new(rect.max.x - width, rect.min.y),
+        Vec2::new(width, rect.height()),
+    );
+    (left, right)
+}
+
+/// A single line ui filling `rect`, cut off rather than wrapped at its edge
+fn cell(ui: &mut Ui, rect: Rect) -> Ui {
+    let mut cell = ui.child_ui(
+        rect,
+        Layout::left_to_right().with_cross_align(Align::Center),
+    );
+    cell.set_clip_rect(rect.intersect(ui.clip_rect()));
+    cell.style_mut().wrap = Some(false);
+    cell
+}
+
+/// An outlined spot things can be dropped on, highlighted while something's dragged over it
+fn drop_target(ui: &mut Ui, rect: Rect, dragging: bool, add_contents: impl FnOnce(&mut Ui)) {
+    let stroke = if dragging && ui.rect_contains_pointer(rect) {
+        Stroke::new(2.0, ui.visuals().selection.stroke.color)
+    } else {
+        ui.visuals().widgets.noninteractive.bg_stroke
+    };
+    ui.painter().rect_stroke(rect, 2.0, stroke);
+    add_contents(&mut cell(ui, rect.shrink2(Vec2::new(4.0, 0.0))));
+}
@@ -0,0 +1,272 @@
+use eframe::egui::{Button, CollapsingHeader, ComboBox, Response, Rgba, TextEdit, Ui, Widget};
+use mongo_cloner::{
+    endpoint::EndpointConfig,
+    profiles::{Key, Profiles},
+};
+use mongodb::error::Result as MongoResult;
+use std::{io, path::PathBuf};
+use tracing::error;
+
+/// The saved profiles, and the key for their passwords once unlocked
+pub struct ProfileStore {
+    /// Where profiles are kept, or `None` if there's no config directory to keep them in
+    path: Option<PathBuf>,
+    profiles: Profiles,
+    /// The key for the profiles' passwords, once the passphrase has been given
+    key: Option<Key>,
+    passphrase: String,
+    /// Where profiles are imported from and exported to
+    file: String,
+    /// The passphrase of the file being imported, blank if it's the same one
+    file_passphrase: String,
+    /// What the last thing done said, or why it failed
+    status: Option<Result<String, String>>,
+    /// The secrets the profiles being imported look up on this machine, until they're agreed to
+    import_secrets: Option<Vec<String>>,
+}
+
+impl ProfileStore {
+    /// Reads the profiles from the config directory
+    pub fn load() -> Self {
+        let path = Profiles::default_path();
+        let profiles = match path.as_ref().map(Profiles::load).transpose() {
+            Ok(profiles) => profiles.unwrap_or_default(),
+            Err(ex) => {
+                error!("Couldn't read profiles: {ex}");
+                Profiles::default()
+            }
+        };
+
+        Self {
+            path,
+            profiles,
+            key: None,
+            passphrase: String::new(),
+            file: "profiles.json".into(),
+            file_passphrase: String::new(),
+            status: None,
+            import_secrets: None,
+        }
+    }
+
+    /// Writes the profiles back to the config directory
+    fn save(&self) -> MongoResult<()> {
+        match &self.path {
+            Some(path) => self.profiles.save(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Remembers how something went, to show under the profiles
+    fn report(&mut self, result: MongoResult<String>) {
+        self.status = Some(result.map_err(|ex| ex.to_string()));
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        CollapsingHeader::new("Profiles")
+            .show(ui, |ui| {
+                ui.horizontal(|ui| match self.key {
+                    None => {
+                        ui.label("Passphrase");
+                        ui.add(TextEdit::singleline(&mut self.passphrase).password(true));
+                        let unlock = if self.profiles.has_passphrase() {
+                            "Unlock"
+                        } else {
+                            "Set passphrase"
+                        };
+                        if ui.button(unlock).clicked() {
+                            let unlocked = self.profiles.unlock(&self.passphrase);
+                            let result = unlocked.and_then(|key| {
+                                self.key = Some(key);
+                                self.save()?;
+                                Ok("Unlocked, passwords will be saved encrypted".to_string())
+                            });
+                            self.report(result);
+                        }
+                    }
+                    Some(_) => {
+                        ui.label("Unlocked");
+                        if ui.button("Lock").clicked() {
+                            self.key = None;
+                            self.passphrase.clear();
+                            self.status = None;
+                        }
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("File");
+                    ui.text_edit_singleline(&mut self.file);
+                    if ui.button("Export").clicked() {
+                        let result = self
+                            .profiles
+                            .save(&self.file)
+                            .map(|()| format!("Exported to {}", self.file));
+                        self.report(result);
+                    }
+                    if ui.button("Import").clicked() {
+                        let result = self.import(&[]);
+                        self.report(result);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("File's passphrase");
+                    ui.add(
+                        TextEdit::singleline(&mut self.file_passphrase)
+                            .password(true)
+                            .hint_text("Same as above"),
+                    );
+                });
+
+                if let Some(references) = self.import_secrets.clone() {
+                    ui.label("The profiles log in with these, looked up on this machine:");
+                    for reference in &references {
+                        ui.monospace(reference);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Import anyway").clicked() {
+                            let result = self.import(&references);
+                            self.report(result);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.import_secrets = None;
+                            self.status = None;
+                        }
+                    });
+                }
+
+                match &self.status {
+                    Some(Ok(status)) => {
+                        ui.label(status);
+                    }
+                    Some(Err(ex)) => {
+                        ui.colored_label(Rgba::RED, ex);
+                    }
+                    None => {}
+                }
+            })
+            .header_response
+    }
+
+    /// Adds the profiles in the file, re-encrypting their passwords with this passphrase. Profiles
+    /// can come from anyone, and whatever their references look up is sent to their servers, so
+    /// they're only added once every reference is in `agreed`.
+    fn import(&mut self, agreed: &[String]) -> MongoResult<String> {
+        self.import_secrets = None;
+        let key = self.key.clone().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Unlock the profiles before importing more",
+            )
+        })?;
+        let mut other = Profiles::read(&self.file)?;
+        let passphrase = if self.file_passphrase.is_empty() {
+            &self.passphrase
+        } else {
+            &self.file_passphrase
+        };
+        let other_key = other.unlock(passphrase)?;
+        let references = other.references(&other_key)?;
+        if references.iter().any(|x| !agreed.contains(x)) {
+            self.import_secrets = Some(references);
+            return Ok("Check the secrets the profiles use before importing them".into());
+        }
+        let count = self.profiles.import(&other, &other_key, &key)?;
+        self.save()?;
+        Ok(format!("Imported {count} profiles"))
+    }
+}
+
+/// Fills in an endpoint from a saved profile, or saves it as one
+pub struct ProfilePicker<'a> {
+    store: &'a mut ProfileStore,
+    endpoint: &'a mut EndpointConfig,
+    /// The name of the profile being used, which is what it's saved as
+    name: &'a mut String,
+}
+
+impl<'a> ProfilePicker<'a> {
+    pub fn new(
+        store: &'a mut ProfileStore,
+        endpoint: &'a mut EndpointConfig,
+        name: &'a mut String,
+    ) -> Self {
+        Self {
+            store,
+            endpoint,
+            name,
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        ui.horizontal(|ui| {
+            ui.label("Profile");
+            let mut picked = None;
+            ComboBox::from_id_source("profile")
+                .selected_text(self.name.as_str())
+                .show_ui(ui, |ui| {
+                    for profile in &self.store.profiles.profiles {
+                        if ui
+                            .selectable_label(*self.name == profile.name, &profile.name)
+                            .clicked()
+                        {
+                            picked = Some(profile.clone());
+                        }
+                    }
+                });
+            if let Some(profile) = picked {
+                *self.name = profile.name.clone();
+                match (&self.store.key, &profile.password) {
+                    (Some(key), _) => match profile.endpoint(key) {
+                        Ok(endpoint) => *self.endpoint = endpoint,
+                        Err(ex) => self.store.report(Err(ex)),
+                    },
+                    (None, Some(_)) => {
+                        *self.endpoint = profile.endpoint;
+                        self.store
+                            .report(Ok("Unlock the profiles to fill in the password".into()));
+                    }
+                    (None, None) => *self.endpoint = profile.endpoint,
+                }
+            }
+
+            ui.add(TextEdit::singleline(self.name).hint_text("Name"));
+            let named = !self.name.trim().is_empty();
+            if ui.add_enabled(named, Button::new("Save")).clicked() {
+                let name = self.name.trim().to_string();
+                let result = self
+                    .store
+                    .profiles
+                    .put(&name, self.endpoint, self.store.key.as_ref())
+                    .and_then(|()| self.store.save())
+                    .map(|()| format!("Saved {name}"));
+                self.store.report(result);
+            }
+            let saved = self
+                .store
+                .profiles
+                .profiles
+                .iter()
+                .any(|profile| profile.name == *self.name);
+            if ui.add_enabled(saved, Button::new("Delete")).clicked() {
+                self.store.profiles.remove(self.name);
+                let result = self.store.save().map(|()| format!("Deleted {}", self.name));
+                self.store.report(result);
+                self.name.clear();
+            }
+        })
+        .response
+    }
+}
+
+impl Widget for &mut ProfileStore {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
+
+impl<'a> Widget for &mut ProfilePicker<'a> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
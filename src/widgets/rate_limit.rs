@@ -0,0 +1,74 @@
+use eframe::egui::{DragValue, Response, Ui, Widget};
+use mongo_cloner::throttle::RateLimit;
+
+/// The number of bytes in a megabyte, limits are shown in MB/s as bytes are far too fine-grained
+const MB: f64 = 1_000_000.0;
+
+/// Edits a rate limit, each part of which can be turned off
+pub struct RateLimitEditor<'a> {
+    limit: &'a mut RateLimit,
+}
+
+impl<'a> RateLimitEditor<'a> {
+    pub fn new(limit: &'a mut RateLimit) -> Self {
+        Self { limit }
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        let mut changed = false;
+
+        let mut response = ui
+            .horizontal(|ui| {
+                changed |= optional_value(
+                    ui,
+                    &mut self.limit.documents_per_sec,
+                    1000.0,
+                    1.0,
+                    "documents/s",
+                );
+                changed |= optional_value(ui, &mut self.limit.bytes_per_sec, 10.0 * MB, MB, "MB/s");
+            })
+            .response;
+
+        if changed {
+            response.mark_changed();
+        }
+        response
+    }
+}
+
+/// A checkbox turning a value on or off, and the value if it's on. Returns whether anything
+/// changed.
+fn optional_value(
+    ui: &mut Ui,
+    value: &mut Option<f64>,
+    default: f64,
+    scale: f64,
+    unit: &str,
+) -> bool {
+    let mut enabled = value.is_some();
+    let mut changed = ui.checkbox(&mut enabled, unit).changed();
+
+    match (enabled, value.as_mut()) {
+        (true, Some(x)) => {
+            let mut scaled = *x / scale;
+            if ui
+                .add(DragValue::new(&mut scaled).clamp_range(0.1..=f64::MAX))
+                .changed()
+            {
+                *x = scaled * scale;
+                changed = true;
+            }
+        }
+        (true, None) => *value = Some(default),
+        (false, _) => *value = None,
+    }
+
+    changed
+}
+
+impl<'a> Widget for &mut RateLimitEditor<'a> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
@@ -0,0 +1,182 @@
+use eframe::{
+    egui::{
+        CollapsingHeader, ComboBox, Grid, Response, Rgba, RichText, ScrollArea, TextEdit, Ui,
+        Widget,
+    },
+    epaint::FontFamily,
+};
+use mongo_cloner::{
+    db::Db,
+    rename::{RenameRule, Renamer, RuleKind, RuleScope},
+};
+
+/// Every kind of rule and what it's called
+const KINDS: [(RuleKind, &str); 6] = [
+    (RuleKind::Glob, "Glob"),
+    (RuleKind::Regex, "Regex"),
+    (RuleKind::Prefix, "Add prefix"),
+    (RuleKind::Suffix, "Add suffix"),
+    (RuleKind::Lowercase, "Lowercase"),
+    (RuleKind::Uppercase, "Uppercase"),
+];
+
+/// Rules for renaming lots of databases and collections at once, with a preview of what they'll
+/// be called. Anything renamed by hand is left as it is.
+pub struct RenameRulesEditor<'a> {
+    rules: &'a mut Vec<RenameRule>,
+    dbs: &'a mut [Db],
+}
+
+impl<'a> RenameRulesEditor<'a> {
+    pub fn new(rules: &'a mut Vec<RenameRule>, dbs: &'a mut [Db]) -> Self {
+        Self { rules, dbs }
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        CollapsingHeader::new("Rename rules")
+            .show(ui, |ui| {
+                let mut remove = None;
+                for (i, rule) in self.rules.iter_mut().enumerate() {
+                    ui.push_id(i, |ui| {
+                        ui.horizontal(|ui| {
+                            rule_editor(ui, rule);
+                            if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                                remove = Some(i);
+                            }
+                        });
+                    });
+                }
+                if let Some(i) = remove {
+                    self.rules.remove(i);
+                }
+                if ui.button("Add rule").clicked() {
+                    self.rules.push(RenameRule::default());
+                }
+
+                let renamer = match Renamer::new(self.rules) {
+                    Ok(renamer) => renamer,
+                    Err(ex) => {
+                        ui.colored_label(Rgba::RED, ex);
+                        return;
+                    }
+                };
+                if renamer.is_empty() {
+                    return;
+                }
+
+                ui.separator();
+                ui.label("Preview");
+                ScrollArea::vertical()
+                    .id_source("rename_preview")
+                    .max_height(200.0)
+                    .show(ui, |ui| preview(ui, self.dbs, &renamer));
+                if ui
+                    .button("Apply")
+                    .on_hover_text("Rename everything that hasn't been renamed by hand")
+                    .clicked()
+                {
+                    Db::apply_renames(self.dbs, &renamer);
+                }
+            })
+            .header_response
+    }
+}
+
+impl<'a> Widget for &mut RenameRulesEditor<'a> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
+
+/// What a rule renames, how, and the pattern and template it needs
+fn rule_editor(ui: &mut Ui, rule: &mut RenameRule) {
+    ComboBox::from_id_source("scope")
+        .selected_text(match rule.scope {
+            RuleScope::Databases => "Databases",
+            RuleScope::Collections => "Collections",
+        })
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut rule.scope, RuleScope::Databases, "Databases");
+            ui.selectable_value(&mut rule.scope, RuleScope::Collections, "Collections");
+        });
+
+    let label = KINDS
+        .iter()
+        .find(|(kind, _)| *kind == rule.kind)
+        .map(|(_, label)| *label)
+        .unwrap_or_default();
+    ComboBox::from_id_source("kind")
+        .selected_text(label)
+        .show_ui(ui, |ui| {
+            for (kind, label) in KINDS {
+                ui.selectable_value(&mut rule.kind, kind, label);
+            }
+        });
+
+    let (pattern, template) = match rule.kind {
+        RuleKind::Glob => ("prod_*", Some("staging_*")),
+        RuleKind::Regex => ("^prod_(.*)$", Some("staging_$1")),
+        RuleKind::Prefix => ("old_", None),
+        RuleKind::Suffix => ("_copy", None),
+        RuleKind::Lowercase | RuleKind::Uppercase => return,
+    };
+    ui.add(
+        TextEdit::singleline(&mut rule.pattern)
+            .hint_text(pattern)
+            .desired_width(120.0),
+    );
+    if let Some(template) = template {
+        ui.label(RichText::new("->").family(FontFamily::Monospace));
+        ui.add(
+            TextEdit::singleline(&mut rule.template)
+                .hint_text(template)
+                .desired_width(120.0),
+        );
+    }
+}
+
+/// The names that would change, and the ones kept as they were renamed by hand
+fn preview(ui: &mut Ui, dbs: &[Db], renamer: &Renamer) {
+    let mut changes = 0;
+    Grid::new("rename_preview_grid")
+        .num_columns(3)
+        .striped(true)
+        .show(ui, |ui| {
+            let mut row =
+                |ui: &mut Ui, label: String, name: &str, renamed: String, edited: bool| {
+                    if renamed == name && !edited {
+                        return;
+                    }
+                    changes += 1;
+                    ui.label(label);
+                    ui.label(RichText::new("->").family(FontFamily::Monospace));
+                    if edited {
+                        ui.label(RichText::new(format!("{renamed} (renamed by hand)")).weak());
+                    } else {
+                        ui.label(renamed);
+                    }
+                    ui.end_row();
+                };
+            for db in dbs {
+                row(
+                    ui,
+                    db.db_name.name.clone(),
+                    &db.db_name.name,
+                    db.db_name.renamed(renamer),
+                    db.db_name.edited,
+                );
+                for collection in &db.collections {
+                    row(
+                        ui,
+                        format!("{}.{}", db.db_name.name, collection.name),
+                        &collection.name,
+                        collection.renamed(renamer),
+                        collection.edited,
+                    );
+                }
+            }
+        });
+    if changes == 0 {
+        ui.label("Nothing matches");
+    }
+}
@@ -0,0 +1,79 @@
+use eframe::egui::{CollapsingHeader, Response, RichText, TextEdit, Ui, Widget};
+use mongo_cloner::{db::Db, selection::Selection};
+
+/// Patterns for selecting collections across every database at once, like `app.*` or
+/// `!*.system.*`, one per line
+pub struct SelectionEditor<'a> {
+    selection: &'a mut Selection,
+    dbs: &'a mut [Db],
+}
+
+impl<'a> SelectionEditor<'a> {
+    pub fn new(selection: &'a mut Selection, dbs: &'a mut [Db]) -> Self {
+        Self { selection, dbs }
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        CollapsingHeader::new("Select by pattern")
+            .show(ui, |ui| {
+                ui.label(
+                    RichText::new(
+                        "One pattern per line, matching <db>.<collection>. * matches anything, \
+                         ? any one character and ! leaves out what matches. The last pattern \
+                         matching a collection wins.",
+                    )
+                    .weak(),
+                );
+
+                // The text as typed, so blank lines aren't taken out from under the cursor
+                let id = ui.make_persistent_id("selection_text");
+                let mut text = ui
+                    .data()
+                    .get_temp::<String>(id)
+                    .filter(|text| Selection::parse(text) == *self.selection)
+                    .unwrap_or_else(|| self.selection.to_text());
+                if ui
+                    .add(
+                        TextEdit::multiline(&mut text)
+                            .hint_text("app.*\n!*.system.*")
+                            .desired_rows(3),
+                    )
+                    .changed()
+                {
+                    *self.selection = Selection::parse(&text);
+                }
+                ui.data().insert_temp(id, text);
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Apply")
+                        .on_hover_text("Select just the collections the patterns pick")
+                        .clicked()
+                    {
+                        Db::select_matching(self.dbs, self.selection);
+                    }
+                    if ui
+                        .button("Reset")
+                        .on_hover_text("Go back to leaving out the system databases")
+                        .clicked()
+                    {
+                        *self.selection = Selection::default();
+                    }
+
+                    let collections = self.dbs.iter().flat_map(|db| &db.collections);
+                    let selected = collections.clone().filter(|x| x.selected).count();
+                    ui.label(format!(
+                        "{selected} of {} collections selected",
+                        collections.count()
+                    ));
+                });
+            })
+            .header_response
+    }
+}
+
+impl<'a> Widget for &mut SelectionEditor<'a> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
@@ -1,158 +1,285 @@
-use eframe::egui::{Response, TextEdit, Ui, Widget};
+use eframe::egui::{
+    CollapsingHeader, Color32, ComboBox, DragValue, Grid, Response, Rgba, TextEdit, Ui, Widget,
+};
+use mongo_cloner::connection::{ConnectionString, Host, MASK};
 
-use std::str::FromStr;
-use tracing::{debug, error};
+/// The ways a client can prove who it is, blank being whatever the driver picks
+const AUTH_MECHANISMS: [&str; 7] = [
+    "",
+    "SCRAM-SHA-1",
+    "SCRAM-SHA-256",
+    "MONGODB-X509",
+    "MONGODB-AWS",
+    "PLAIN",
+    "GSSAPI",
+];
 
-use url::Url;
+/// The ways messages to and from the server can be compressed
+const COMPRESSORS: [&str; 3] = ["snappy", "zlib", "zstd"];
 
+/// The address of a cluster, as a connection string and split up into its parts, kept in sync
 pub struct ServerAddress<'a> {
-    source_field: &'a mut Url,
+    url: &'a mut ConnectionString,
+    /// Set to whether what's typed in is a connection string that can be connected with
+    valid: &'a mut bool,
 }
 
 impl<'a> ServerAddress<'a> {
-    pub fn new(url: &'a mut Url) -> Self {
-        Self { source_field: url }
+    pub fn new(url: &'a mut ConnectionString, valid: &'a mut bool) -> Self {
+        Self { url, valid }
     }
 
     pub fn show(&mut self, ui: &mut Ui) -> Response {
-        let mut source_field = format!("{}", self.source_field);
-        let mut username = self.source_field.username().to_string();
-        let mut password = self.source_field.password().unwrap_or_default().to_string();
-
-        let mut host = self
-            .source_field
-            .host()
-            .map(|k| format!("{k}"))
-            .unwrap_or_default();
-
-        let mut port = self
-            .source_field
-            .port()
-            .map(|x| x.to_string())
-            .unwrap_or_default();
-
-        let mut ssl = self
-            .source_field
-            .query_pairs()
-            .find(|(key, _)| key == "ssl")
-            .map(|(_, value)| value.parse().unwrap_or(false))
-            .unwrap_or_default();
-
-        let mut srv = self.source_field.scheme().ends_with("+srv");
-
-        if srv {
-            self.source_field.set_port(None).unwrap();
-        }
-
         ui.vertical_centered(|ui| {
-            ui.horizontal(|ui| {
-                ui.label("Connection Uri: ");
-
-                if ui
-                    .add(
-                        TextEdit::singleline(&mut source_field).desired_width(ui.available_width()),
-                    )
-                    .changed()
-                {
-                    match Url::from_str(&source_field) {
-                        Ok(url) => {
-                            *self.source_field = url;
-                        }
-                        Err(ex) => {
-                            error!("Could not do parse uri: {ex}")
-                        }
-                    }
-                };
-            });
-
-            ui.horizontal(|ui| {
-                ui.horizontal(|ui| {
-                    ui.label("Host");
-                    if ui.text_edit_singleline(&mut host).changed()
-                        && self.source_field.set_host(Some(host.trim())).is_err()
-                    {
-                        error!("Ahhh fuck");
-                    };
-                });
-                ui.horizontal(|ui| {
-                    ui.label("Port");
-                    if ui
-                        .add(TextEdit::singleline(&mut port).interactive(!srv))
-                        .changed()
-                    {
-                        if port.trim() == "" {
-                            self.source_field.set_port(None).unwrap();
-                        }
-                        match port.parse() {
-                            Ok(val) => self.source_field.set_port(Some(val)).unwrap(),
-                            Err(e) => {
-                                error!("Inlaid Port: {e}")
-                            }
-                        }
-                    }
-                });
-            });
+            let reveal_id = ui.make_persistent_id("reveal_password");
+            let mut reveal = ui.data().get_temp::<bool>(reveal_id).unwrap_or_default();
+            let parsed = self.connection_string(ui, reveal);
+            let ports = self.hosts(ui);
 
             ui.columns(2, |columns| {
                 columns[0].horizontal(|ui| {
                     ui.label("Username");
+                    let mut username = self.url.username.clone().unwrap_or_default();
                     if ui.text_edit_singleline(&mut username).changed() {
-                        self.source_field.set_username(username.as_str()).unwrap();
+                        self.url.username = (!username.is_empty()).then_some(username);
                     };
                 });
                 columns[1].horizontal(|ui| {
                     ui.label("Password");
-                    if ui.text_edit_singleline(&mut password).changed() {
-                        if password.is_empty() {
-                            self.source_field.set_password(None).unwrap();
-                        } else {
-                            self.source_field
-                                .set_password(Some(password.as_str()))
-                                .unwrap()
-                        }
+                    let mut password = self.url.password.clone().unwrap_or_default();
+                    if ui
+                        .add(TextEdit::singleline(&mut password).password(!reveal))
+                        .changed()
+                    {
+                        self.url.password = (!password.is_empty()).then_some(password);
+                    }
+                    if ui
+                        .selectable_label(reveal, "👁")
+                        .on_hover_text("Show the password")
+                        .clicked()
+                    {
+                        reveal = !reveal;
                     }
                 });
             });
-            ui.horizontal(|ui| {
-                if ui.checkbox(&mut ssl, "Use SSL").changed() {
-                    let query_params: Vec<(_, _)> = self
-                        .source_field
-                        .query_pairs()
-                        .filter(|(key, _)| key != "ssl")
-                        .map(|(k, v)| (k.to_string(), v.to_string()))
-                        .collect();
-                    self.source_field.query_pairs_mut().clear().finish();
-                    for (key, val) in query_params {
-                        self.source_field
-                            .query_pairs_mut()
-                            .append_pair(key.as_ref(), val.as_ref())
-                            .finish();
-                    }
+            ui.data().insert_temp(reveal_id, reveal);
 
-                    self.source_field
-                        .query_pairs_mut()
-                        .append_pair("ssl", &ssl.to_string())
-                        .finish();
+            ui.horizontal(|ui| {
+                // Older connection strings say `ssl`, which is kept if it's there
+                let mut tls = self
+                    .url
+                    .flag("tls")
+                    .or_else(|| self.url.flag("ssl"))
+                    .unwrap_or_default();
+                if ui.checkbox(&mut tls, "Use TLS").changed() {
+                    let name = match (self.url.option("tls"), self.url.option("ssl")) {
+                        (None, Some(_)) => "ssl",
+                        _ => "tls",
+                    };
+                    self.url.set_option(name, Some(&tls.to_string()));
                 }
 
-                if ui.checkbox(&mut srv, "Use +srv").changed() {
-                    let scheme = self.source_field.scheme().to_string();
-                    if !srv {
-                        debug!("Stripping +srv from scheme");
-                        self.source_field
-                            .set_scheme(scheme.strip_suffix("+srv").unwrap_or(&scheme))
-                            .unwrap();
-                    } else {
-                        debug!("Adding +srv from scheme");
-                        self.source_field
-                            .set_scheme(&format!("{scheme}+srv"))
-                            .unwrap();
+                if ui.checkbox(&mut self.url.srv, "Use +srv").changed() && self.url.srv {
+                    self.url.hosts.truncate(1);
+                    for host in &mut self.url.hosts {
+                        host.port = None;
                     }
                 }
             });
+
+            let problems = self.url.problems();
+            for problem in &problems {
+                ui.colored_label(Rgba::RED, problem);
+            }
+            for warning in self.url.warnings() {
+                ui.colored_label(Color32::GOLD, warning);
+            }
+            *self.valid = parsed && ports && problems.is_empty();
+
+            self.options(ui);
         })
         .response
     }
+
+    /// The whole connection string, which keeps what's typed while it doesn't parse. The
+    /// password is masked unless `reveal`, and left alone if the mask isn't changed. Returns
+    /// whether what's typed parses.
+    fn connection_string(&mut self, ui: &mut Ui, reveal: bool) -> bool {
+        let id = ui.make_persistent_id("connection_string");
+        let focused = ui.memory().has_focus(id);
+        let stored = ui.data().get_temp::<String>(id);
+        let mut text = stored
+            .filter(|text| focused || self.parse(text, reveal).as_ref() == Ok(&*self.url))
+            .unwrap_or_else(|| {
+                if reveal {
+                    self.url.to_string()
+                } else {
+                    self.url.redacted()
+                }
+            });
+
+        ui.horizontal(|ui| {
+            ui.label("Connection Uri: ");
+            let response = ui.add(
+                TextEdit::singleline(&mut text)
+                    .id(id)
+                    .desired_width(ui.available_width()),
+            );
+            if response.changed() {
+                if let Ok(url) = self.parse(&text, reveal) {
+                    *self.url = url;
+                }
+            }
+        });
+        let parsed = match text.parse::<ConnectionString>() {
+            Ok(_) => true,
+            Err(ex) => {
+                ui.colored_label(Rgba::RED, ex);
+                false
+            }
+        };
+        ui.data().insert_temp(id, text);
+        parsed
+    }
+
+    /// Parses a typed connection string, keeping the current password if it's still masked
+    fn parse(&self, text: &str, reveal: bool) -> Result<ConnectionString, String> {
+        let mut url = text.parse::<ConnectionString>()?;
+        if !reveal && url.password.as_deref() == Some(MASK) {
+            url.password = self.url.password.clone();
+        }
+        Ok(url)
+    }
+
+    /// A row for each host, which can only be one without a port for `+srv`. Returns whether
+    /// every port typed in is a port.
+    fn hosts(&mut self, ui: &mut Ui) -> bool {
+        let srv = self.url.srv;
+        let mut remove = None;
+        let removable = self.url.hosts.len() > 1;
+        let mut ports = true;
+
+        for (i, host) in self.url.hosts.iter_mut().enumerate() {
+            ui.push_id(i, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Host");
+                    ui.text_edit_singleline(&mut host.host);
+
+                    // Like the connection string, what's typed is kept while it isn't a port
+                    ui.label("Port");
+                    let id = ui.make_persistent_id("port");
+                    let focused = ui.memory().has_focus(id);
+                    let stored = ui.data().get_temp::<String>(id);
+                    let mut port = stored
+                        .filter(|text| focused || parse_port(text) == Ok(host.port))
+                        .unwrap_or_else(|| host.port.map(|x| x.to_string()).unwrap_or_default());
+                    let response = ui.add(TextEdit::singleline(&mut port).id(id).interactive(!srv));
+                    match parse_port(&port) {
+                        Ok(parsed) => {
+                            if response.changed() {
+                                host.port = parsed;
+                            }
+                        }
+                        Err(ex) => {
+                            ui.colored_label(Rgba::RED, ex);
+                            ports = false;
+                        }
+                    }
+                    ui.data().insert_temp(id, port);
+
+                    if removable && ui.small_button("✖").on_hover_text("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            });
+        }
+
+        if let Some(i) = remove {
+            self.url.hosts.remove(i);
+        }
+        if !srv && ui.button("Add host").clicked() {
+            self.url.hosts.push(Host {
+                host: String::new(),
+                port: Some(27017),
+            });
+        }
+        ports
+    }
+
+    /// Everything else that can go in the options of a connection string
+    fn options(&mut self, ui: &mut Ui) {
+        CollapsingHeader::new("Connection options").show(ui, |ui| {
+            Grid::new("connection_options")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    let url = &mut *self.url;
+
+                    ui.label("Auth database");
+                    text_option(ui, url, "authSource", "admin");
+                    ui.end_row();
+
+                    ui.label("Auth mechanism");
+                    let selected = url.option("authMechanism").unwrap_or_default().to_string();
+                    ComboBox::from_id_source("auth_mechanism")
+                        .selected_text(mechanism_label(&selected))
+                        .show_ui(ui, |ui| {
+                            for mechanism in AUTH_MECHANISMS {
+                                if ui
+                                    .selectable_label(
+                                        selected == mechanism,
+                                        mechanism_label(mechanism),
+                                    )
+                                    .clicked()
+                                {
+                                    url.set_option(
+                                        "authMechanism",
+                                        (!mechanism.is_empty()).then_some(mechanism),
+                                    );
+                                }
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Replica set");
+                    text_option(ui, url, "replicaSet", "Found from the hosts");
+                    ui.end_row();
+
+                    ui.label("Connect directly");
+                    flag_option(ui, url, "directConnection").on_hover_text(
+                        "Only talk to the first host, rather than the whole cluster",
+                    );
+                    ui.end_row();
+
+                    ui.label("App name");
+                    text_option(ui, url, "appName", "Shown in the server's logs");
+                    ui.end_row();
+
+                    ui.label("Connect timeout");
+                    millis_option(ui, url, "connectTimeoutMS", 10_000);
+                    ui.end_row();
+
+                    ui.label("Server selection timeout");
+                    millis_option(ui, url, "serverSelectionTimeoutMS", 30_000);
+                    ui.end_row();
+
+                    ui.label("Socket timeout");
+                    millis_option(ui, url, "socketTimeoutMS", 0);
+                    ui.end_row();
+
+                    ui.label("Compressors");
+                    ui.horizontal(|ui| compressors(ui, url));
+                    ui.end_row();
+
+                    ui.label("TLS CA file");
+                    text_option(ui, url, "tlsCAFile", "The system's certificates");
+                    ui.end_row();
+
+                    ui.label("Client certificate");
+                    text_option(ui, url, "tlsCertificateKeyFile", "A PEM file with the key");
+                    ui.end_row();
+                });
+        });
+    }
 }
 
 impl<'a> Widget for &mut ServerAddress<'a> {
@@ -160,3 +287,95 @@ impl<'a> Widget for &mut ServerAddress<'a> {
         self.show(ui)
     }
 }
+
+/// A typed port, which can be left blank for the default
+fn parse_port(text: &str) -> Result<Option<u16>, String> {
+    match text.trim() {
+        "" => Ok(None),
+        port => match port.parse() {
+            Ok(0) | Err(_) => Err(format!("`{port}` isn't a port, they go from 1 to 65535")),
+            Ok(port) => Ok(Some(port)),
+        },
+    }
+}
+
+/// A text box for an option, which is left out while it's blank
+fn text_option(ui: &mut Ui, url: &mut ConnectionString, name: &str, hint: &str) -> Response {
+    let mut value = url.option(name).unwrap_or_default().to_string();
+    let response = ui.add(TextEdit::singleline(&mut value).hint_text(hint));
+    if response.changed() {
+        let value = value.trim();
+        url.set_option(name, (!value.is_empty()).then_some(value));
+    }
+    response
+}
+
+/// A checkbox for a `true`/`false` option, which is left out while it's unticked
+fn flag_option(ui: &mut Ui, url: &mut ConnectionString, name: &str) -> Response {
+    let mut value = url.flag(name).unwrap_or_default();
+    let response = ui.checkbox(&mut value, "");
+    if response.changed() {
+        url.set_option(name, value.then_some("true"));
+    }
+    response
+}
+
+/// A number of milliseconds for an option, which is left out while it's unticked
+fn millis_option(ui: &mut Ui, url: &mut ConnectionString, name: &str, default: u64) {
+    ui.horizontal(|ui| {
+        let value = url.option(name).and_then(|value| value.parse::<u64>().ok());
+        let mut enabled = value.is_some();
+        let mut millis = value.unwrap_or(default);
+        let ticked = ui.checkbox(&mut enabled, "").changed();
+        let dragged = ui
+            .add_enabled(
+                enabled,
+                DragValue::new(&mut millis)
+                    .speed(100.0)
+                    .clamp_range(0..=u64::MAX)
+                    .suffix(" ms"),
+            )
+            .changed();
+        if ticked || dragged {
+            url.set_option(name, enabled.then(|| millis.to_string()).as_deref());
+        }
+    });
+}
+
+/// A checkbox for each compressor, kept in the order they're listed
+fn compressors(ui: &mut Ui, url: &mut ConnectionString) {
+    let mut listed = url
+        .option("compressors")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    let mut changed = false;
+    for compressor in COMPRESSORS {
+        let mut on = listed.iter().any(|name| name == compressor);
+        if ui.checkbox(&mut on, compressor).changed() {
+            changed = true;
+            if on {
+                listed.push(compressor.to_string());
+            } else {
+                listed.retain(|name| name != compressor);
+            }
+        }
+    }
+    if changed {
+        let value = listed.join(",");
+        url.set_option("compressors", (!value.is_empty()).then_some(value.as_str()));
+    }
+}
+
+/// How an auth mechanism is shown, with blank as the default
+fn mechanism_label(mechanism: &str) -> &str {
+    if mechanism.is_empty() {
+        "Default"
+    } else {
+        mechanism
+    }
+}
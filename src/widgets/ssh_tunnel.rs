@@ -0,0 +1,63 @@
+use eframe::egui::{CollapsingHeader, DragValue, Grid, Response, TextEdit, Ui, Widget};
+use mongo_cloner::tunnel::SshTunnel;
+
+/// An SSH jump host to reach a cluster through
+pub struct SshTunnelEditor<'a> {
+    tunnel: &'a mut SshTunnel,
+}
+
+impl<'a> SshTunnelEditor<'a> {
+    pub fn new(tunnel: &'a mut SshTunnel) -> Self {
+        Self { tunnel }
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        CollapsingHeader::new("SSH tunnel")
+            .show(ui, |ui| {
+                ui.checkbox(&mut self.tunnel.enabled, "Connect through a jump host")
+                    .on_hover_text(
+                        "Only the first host is tunnelled to and connected to directly, so put \
+                         the primary first to write to a replica set",
+                    );
+                ui.add_enabled_ui(self.tunnel.enabled, |ui| {
+                    Grid::new("ssh_tunnel").num_columns(2).show(ui, |ui| {
+                        ui.label("Jump host");
+                        ui.add(
+                            TextEdit::singleline(&mut self.tunnel.host)
+                                .hint_text("bastion.example.com:22"),
+                        );
+                        ui.end_row();
+
+                        ui.label("User");
+                        ui.add(
+                            TextEdit::singleline(&mut self.tunnel.user).hint_text("Same as here"),
+                        );
+                        ui.end_row();
+
+                        ui.label("Key file");
+                        ui.add(
+                            TextEdit::singleline(&mut self.tunnel.key_file)
+                                .hint_text("Use the SSH agent"),
+                        );
+                        ui.end_row();
+
+                        ui.label("Local port");
+                        ui.horizontal(|ui| {
+                            ui.add(DragValue::new(&mut self.tunnel.local_port));
+                            if self.tunnel.local_port == 0 {
+                                ui.label("Any free port");
+                            }
+                        });
+                        ui.end_row();
+                    });
+                });
+            })
+            .header_response
+    }
+}
+
+impl<'a> Widget for &mut SshTunnelEditor<'a> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
@@ -0,0 +1,136 @@
+use eframe::egui::{self, CollapsingHeader, Response, Rgba, RichText, ScrollArea, Ui, Widget};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use mongo_cloner::{
+    connection::redact,
+    endpoint::{CollectionStats, Endpoint, EndpointConfig, Namespace},
+    progress::HumanBytes,
+};
+use mongodb::error::Result as MongoResult;
+use poll_promise::Promise;
+use std::collections::{BTreeMap, BTreeSet};
+use tokio::runtime::Runtime;
+
+/// How many collections are counted at once
+const CONCURRENT_STATS: usize = 8;
+
+/// The collections in each database and their stats
+type Listing = BTreeMap<String, Vec<(String, CollectionStats)>>;
+
+/// The databases and collections already in the target, to see what a clone would overwrite
+pub struct TargetBrowser {
+    /// The target that was listed, so the listing can be thrown out once it changes
+    pub config: EndpointConfig,
+    /// Every collection and its stats by database, once they've been read
+    listing: Promise<MongoResult<Listing>>,
+}
+
+impl TargetBrowser {
+    /// Starts connecting to the target `config` points at and listing what's in it, which can
+    /// take a while through a tunnel
+    pub fn start(config: &EndpointConfig, rt: &Runtime, ctx: &egui::Context) -> Self {
+        let (sender, listing) = Promise::new();
+        let target = config.clone();
+        let ctx = ctx.clone();
+        rt.spawn(async move {
+            let listing = match target.connect().await {
+                Ok(target) => list(&*target).await,
+                Err(ex) => Err(ex),
+            };
+            sender.send(listing);
+            ctx.request_repaint();
+        });
+
+        Self {
+            config: config.clone(),
+            listing,
+        }
+    }
+
+    /// The namespaces in the target, or none while they're still being listed
+    pub fn namespaces(&self) -> BTreeSet<Namespace> {
+        match self.listing.ready() {
+            Some(Ok(dbs)) => dbs
+                .iter()
+                .flat_map(|(db, collections)| {
+                    collections
+                        .iter()
+                        .map(move |(collection, _)| Namespace::new(db, collection))
+                })
+                .collect(),
+            _ => BTreeSet::new(),
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        ui.vertical(|ui| match self.listing.ready() {
+            None => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Connecting to and listing the target");
+                });
+            }
+            Some(Err(ex)) => {
+                ui.colored_label(
+                    Rgba::RED,
+                    redact(&format!("Couldn't list the target: {ex}")),
+                );
+            }
+            Some(Ok(dbs)) if dbs.is_empty() => {
+                ui.label("The target is empty");
+            }
+            Some(Ok(dbs)) => {
+                ScrollArea::vertical()
+                    .id_source("target_listing")
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        for (db, collections) in dbs {
+                            CollapsingHeader::new(db).show(ui, |ui| {
+                                for (collection, stats) in collections {
+                                    ui.horizontal(|ui| {
+                                        ui.label(collection);
+                                        ui.label(
+                                            RichText::new(format!(
+                                                "{} documents, {}",
+                                                stats.documents,
+                                                HumanBytes(stats.bytes as f64)
+                                            ))
+                                            .weak(),
+                                        );
+                                    });
+                                }
+                            });
+                        }
+                    });
+            }
+        })
+        .response
+    }
+}
+
+impl Widget for &mut TargetBrowser {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
+
+/// Every collection in `target` and its stats, grouped by database
+async fn list(target: &dyn Endpoint) -> MongoResult<Listing> {
+    let namespaces = target.list_namespaces().await?;
+    let stats = stream::iter(namespaces)
+        .map(|ns| async move {
+            let stats = target.collection_stats(&ns).await?;
+            MongoResult::Ok((ns, stats))
+        })
+        .buffered(CONCURRENT_STATS)
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let mut dbs = BTreeMap::<String, Vec<_>>::new();
+    for (ns, stats) in stats {
+        dbs.entry(ns.db).or_default().push((ns.collection, stats));
+    }
+    for collections in dbs.values_mut() {
+        collections.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+    Ok(dbs)
+}
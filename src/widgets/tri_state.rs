@@ -0,0 +1,134 @@
+use eframe::{
+    egui::{
+        pos2, Response, Sense, Shape, TextStyle, Ui, Vec2, Widget, WidgetInfo, WidgetText,
+        WidgetType,
+    },
+    emath::NumExt,
+    epaint::RectShape,
+};
+
+/// How many of a group of things are selected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selected {
+    All,
+    /// Some but not all of them
+    Partly,
+    Nothing,
+}
+
+impl Selected {
+    /// Which it is when `selected` out of `total` things are selected. Nothing out of nothing is
+    /// nothing.
+    pub fn of(selected: usize, total: usize) -> Self {
+        if selected == 0 {
+            Self::Nothing
+        } else if selected < total {
+            Self::Partly
+        } else {
+            Self::All
+        }
+    }
+}
+
+/// A checkbox for a group of things, with a dash when only some of them are selected. Clicking it
+/// selects all of them, or nothing if they all were, and marks the response as changed.
+pub struct TriStateCheckbox<'a> {
+    state: &'a mut Selected,
+    text: WidgetText,
+}
+
+impl<'a> TriStateCheckbox<'a> {
+    pub fn new(state: &'a mut Selected, text: impl Into<WidgetText>) -> Self {
+        Self {
+            state,
+            text: text.into(),
+        }
+    }
+
+    /// Laid out and painted just like egui's own checkbox, apart from the dash
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        let spacing = ui.spacing();
+        let icon_width = spacing.icon_width;
+        let icon_spacing = spacing.icon_spacing;
+        let interact_size = spacing.interact_size;
+
+        let text = (!self.text.is_empty()).then(|| {
+            let wrap_width = ui.available_width() - icon_width - icon_spacing;
+            self.text
+                .clone()
+                .into_galley(ui, None, wrap_width, TextStyle::Button)
+        });
+        let mut desired_size = match &text {
+            Some(text) => {
+                (Vec2::new(icon_width + icon_spacing, 0.0) + text.size()).at_least(interact_size)
+            }
+            None => Vec2::new(icon_width, 0.0),
+        };
+        desired_size = desired_size.at_least(Vec2::splat(interact_size.y));
+        desired_size.y = desired_size.y.max(icon_width);
+        let (rect, mut response) = ui.allocate_exact_size(desired_size, Sense::click());
+
+        if response.clicked() {
+            *self.state = match self.state {
+                Selected::All => Selected::Nothing,
+                Selected::Partly | Selected::Nothing => Selected::All,
+            };
+            response.mark_changed();
+        }
+        response.widget_info(|| {
+            WidgetInfo::selected(
+                WidgetType::Checkbox,
+                *self.state == Selected::All,
+                text.as_ref().map_or("", |text| text.text()),
+            )
+        });
+
+        if ui.is_rect_visible(rect) {
+            let visuals = ui.style().interact(&response);
+            let (small_icon_rect, big_icon_rect) = ui.spacing().icon_rectangles(rect);
+            ui.painter().add(RectShape {
+                rect: big_icon_rect.expand(visuals.expansion),
+                rounding: visuals.rounding,
+                fill: visuals.bg_fill,
+                stroke: visuals.bg_stroke,
+            });
+            match self.state {
+                Selected::All => {
+                    ui.painter().add(Shape::line(
+                        vec![
+                            pos2(small_icon_rect.left(), small_icon_rect.center().y),
+                            pos2(small_icon_rect.center().x, small_icon_rect.bottom()),
+                            pos2(small_icon_rect.right(), small_icon_rect.top()),
+                        ],
+                        visuals.fg_stroke,
+                    ));
+                }
+                Selected::Partly => {
+                    ui.painter().line_segment(
+                        [
+                            small_icon_rect.left_center(),
+                            small_icon_rect.right_center(),
+                        ],
+                        visuals.fg_stroke,
+                    );
+                }
+                Selected::Nothing => {}
+            }
+            if let Some(text) = text {
+                let text_pos = pos2(
+                    rect.min.x + icon_width + icon_spacing,
+                    rect.center().y - 0.5 * text.size().y,
+                );
+                text.paint_with_visuals(ui.painter(), text_pos, visuals);
+            }
+        }
+
+        response
+    }
+}
+
+impl<'a> Widget for &mut TriStateCheckbox<'a> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}
@@ -0,0 +1,223 @@
+use eframe::egui::{self, Grid, Response, Rgba, RichText, ScrollArea, TextEdit, Ui, Widget};
+use mongo_cloner::{
+    connection::redact,
+    endpoint::{Endpoint, EndpointConfig},
+    users::{self, Renames, Users, UsersReport},
+};
+use mongodb::error::Result as MongoResult;
+use poll_promise::Promise;
+use std::{collections::BTreeMap, fs, sync::Arc};
+use tokio::runtime::{Handle, Runtime};
+
+/// Copies the users and custom roles of some databases to the target, asking for their new
+/// passwords as they can't be read from the source
+pub struct UsersCopy {
+    /// Where reading and creating users runs
+    rt: Handle,
+    /// Where the users are created
+    target: EndpointConfig,
+    /// The users and roles in the source, already renamed to the target's databases
+    users: Promise<MongoResult<Users>>,
+    /// The passwords typed in so far by `(db, user)`, blank ones are generated
+    passwords: BTreeMap<(String, String), String>,
+    /// How creating the users went, once started
+    report: Option<Promise<MongoResult<UsersReport>>>,
+    /// Where generated passwords are saved to
+    passwords_path: String,
+    /// How saving generated passwords went
+    saved: Option<Result<(), String>>,
+}
+
+impl UsersCopy {
+    /// Starts reading the users in the databases being copied from `source`
+    pub fn start(
+        source: Arc<dyn Endpoint>,
+        target: EndpointConfig,
+        renames: Renames,
+        rt: &Runtime,
+        ctx: &egui::Context,
+    ) -> Self {
+        let (sender, users) = Promise::new();
+        let ctx = ctx.clone();
+        rt.spawn(async move {
+            sender.send(users::read(&*source, &renames).await);
+            ctx.request_repaint();
+        });
+
+        Self {
+            rt: rt.handle().clone(),
+            target,
+            users,
+            passwords: BTreeMap::new(),
+            report: None,
+            passwords_path: "passwords.csv".into(),
+            saved: None,
+        }
+    }
+
+    /// Whether it's done with or nothing has been started, so it can be closed
+    pub fn is_done(&self) -> bool {
+        match &self.report {
+            Some(report) => report.ready().is_some(),
+            None => true,
+        }
+    }
+
+    /// Starts creating the users on the target
+    fn create(&mut self, users: Users, ctx: &egui::Context) {
+        let (sender, report) = Promise::new();
+        let target = self.target.clone();
+        let passwords = self.passwords.clone();
+        let ctx = ctx.clone();
+        self.rt.spawn(async move {
+            let report = match target.connect().await {
+                Ok(target) => Ok(users::create(&*target, &users, &passwords).await),
+                Err(ex) => Err(ex),
+            };
+            sender.send(report);
+            ctx.request_repaint();
+        });
+        self.report = Some(report);
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        ui.vertical(|ui| {
+            let users = match self.users.ready() {
+                Some(Ok(users)) => users.clone(),
+                Some(Err(ex)) => {
+                    ui.colored_label(Rgba::RED, redact(&format!("Couldn't read users: {ex}")));
+                    return;
+                }
+                None => {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label("Reading users and roles");
+                    });
+                    return;
+                }
+            };
+
+            match &self.report {
+                None => {
+                    self.passwords_form(ui, &users);
+                    if ui.button("Create on target").clicked() {
+                        self.create(users, ui.ctx());
+                    }
+                }
+                Some(report) => match report.ready() {
+                    None => {
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.label("Creating users and roles");
+                        });
+                    }
+                    Some(Err(ex)) => {
+                        ui.colored_label(
+                            Rgba::RED,
+                            redact(&format!("Couldn't connect to target: {ex}")),
+                        );
+                    }
+                    Some(Ok(report)) => {
+                        let report = report.clone();
+                        self.report(ui, &report);
+                    }
+                },
+            }
+        })
+        .response
+    }
+
+    /// A password field for every user
+    fn passwords_form(&mut self, ui: &mut Ui, users: &Users) {
+        ui.label(format!(
+            "{} custom roles and {} users will be created. Passwords can't be copied, so give \
+             each user a new one or leave it blank to make one up.",
+            users.roles.len(),
+            users.users.len()
+        ));
+        ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+            Grid::new("user_passwords").num_columns(2).show(ui, |ui| {
+                for user in &users.users {
+                    let key = (user.db.clone(), user.user.clone());
+                    ui.label(format!("{}.{}", user.db, user.user));
+                    let password = self.passwords.entry(key).or_default();
+                    ui.add(
+                        TextEdit::singleline(password)
+                            .password(true)
+                            .hint_text("Generate"),
+                    );
+                    ui.end_row();
+                }
+            });
+        });
+    }
+
+    /// What was created and the passwords that were made up
+    fn report(&mut self, ui: &mut Ui, report: &UsersReport) {
+        let failures = report
+            .roles
+            .iter()
+            .map(|(name, result)| (format!("Role {}.{}", name.db, name.role), result))
+            .chain(
+                report
+                    .users
+                    .iter()
+                    .map(|((db, user), result)| (format!("User {db}.{user}"), result)),
+            )
+            .filter_map(|(name, result)| result.as_ref().err().map(|ex| (name, ex)))
+            .collect::<Vec<_>>();
+
+        if failures.is_empty() {
+            ui.label(format!(
+                "Created {} roles and {} users",
+                report.roles.len(),
+                report.users.len()
+            ));
+        } else {
+            for (name, ex) in failures {
+                ui.colored_label(Rgba::RED, redact(&format!("{name}: {ex}")));
+            }
+        }
+
+        if report.generated.is_empty() {
+            return;
+        }
+        ui.separator();
+        ui.label("Generated passwords, these won't be shown again once this is closed");
+        ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+            Grid::new("generated_passwords")
+                .num_columns(2)
+                .striped(true)
+                .show(ui, |ui| {
+                    for generated in &report.generated {
+                        ui.label(format!("{}.{}", generated.db, generated.user));
+                        ui.label(RichText::new(&generated.password).monospace());
+                        ui.end_row();
+                    }
+                });
+        });
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.passwords_path);
+            if ui.button("Save").clicked() {
+                let csv = users::write_passwords(&report.generated);
+                self.saved =
+                    Some(fs::write(&self.passwords_path, csv).map_err(|ex| ex.to_string()));
+            }
+        });
+        match &self.saved {
+            Some(Ok(())) => {
+                ui.label(format!("Saved to {}", self.passwords_path));
+            }
+            Some(Err(ex)) => {
+                ui.colored_label(Rgba::RED, format!("Couldn't save: {ex}"));
+            }
+            None => {}
+        }
+    }
+}
+
+impl Widget for &mut UsersCopy {
+    fn ui(self, ui: &mut Ui) -> Response {
+        self.show(ui)
+    }
+}